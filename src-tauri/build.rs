@@ -1,8 +1,32 @@
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Hashes every bundled asset, so that a damaged install can be told apart from a broken chart.
+fn hash_assets(dir: &Path, root: &Path, out: &mut Vec<(String, String)>) {
+  let mut entries: Vec<_> = std::fs::read_dir(dir).unwrap().map(|it| it.unwrap().path()).collect();
+  entries.sort();
+  for path in entries {
+    if path.is_dir() {
+      hash_assets(&path, root, out);
+    } else {
+      let name = path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+      out.push((name, hex::encode(Sha256::digest(std::fs::read(&path).unwrap()))));
+    }
+  }
+}
+
 fn main() {
   let lib_path = format!("{}/static-lib/{}", std::env::var("CARGO_MANIFEST_DIR").unwrap(), std::env::var("TARGET").unwrap());
   println!("cargo:rustc-link-search={lib_path}");
   println!("cargo:rustc-link-lib=z");
   println!("cargo:rerun-if-changed={lib_path}");
 
+  let assets = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("assets");
+  let mut hashes = Vec::new();
+  hash_assets(&assets, &assets, &mut hashes);
+  let table: String = hashes.iter().map(|(name, hash)| format!("    ({name:?}, {hash:?}),\n")).collect();
+  std::fs::write(Path::new(&std::env::var("OUT_DIR").unwrap()).join("asset_hashes.rs"), format!("&[\n{table}]\n")).unwrap();
+  println!("cargo:rerun-if-changed=assets");
+
   tauri_build::build();
 }
@@ -3,6 +3,14 @@ tray-hide = Minimize to tray
 tray-show = Show window
 tray-tasks = Tasks
 tray-quit = Quit
+tray-idle = No renders running
+tray-waiting = Waiting to render
+tray-paused = Queue paused
+tray-rendering = { $name } — { $progress }%
+tray-pause = Pause queue
+tray-resume = Resume queue
+tray-cancel = Cancel current render
+tray-output = Open output folder
 
 read-chart-failed = Failed to read chart
 load-info-failed = Failed to load chart info
@@ -11,3 +19,5 @@ preset-exists = Preset with the same name already exists
 preset-not-found = Specified preset not found
 
 not-valid-rpe = Not a valid RPE directory
+
+chart-changed = The chart changed since it was queued; the current version is rendered
@@ -3,5 +3,45 @@ load-avatar-failed = Failed to load avatar
 load-chart-failed = Failed to load chart
 load-music-failed = Failed to load music
 load-sfx-failed = Failed to load SFX `{ $name }`
+ffmpeg-not-found = FFmpeg not found
 run-ffmpeg-failed = Failed to run ffmpeg
 no-hwacc = Hardware acceleration is unsupported!
+encoder-died = Encoder { $encoder } stopped in the middle of the render
+join-segments-failed = Failed to join the encoded segments
+remux-unreadable = Couldn't read the length of the video to remux
+remux-length-mismatch = The video is { $video }s long but the new mix is { $mix }s. Was it rendered from this chart and config?
+mux-failed = Failed to mux the audio into the encoded video
+software-fallback = No hardware encoder is available, encoding in software instead
+ffmpeg-unknown-encoder = This ffmpeg build lacks the chosen encoder. Pick another encoder or install a full ffmpeg build
+ffmpeg-no-space = The disk holding the output folder is full
+ffmpeg-permission-denied = ffmpeg isn't allowed to write to the output folder
+ffmpeg-invalid-option = ffmpeg rejected an encoder setting. Check the bitrate and preset
+ffmpeg-encoder-failed = The encoder couldn't start with these settings. Try another preset, resolution or encoder
+ffmpeg-exited = ffmpeg stopped unexpectedly, see the details for its output
+encoder-backpressure = The encoder is falling behind and held up rendering { $share }% of the time. Lower the resolution, frame rate or encoder preset
+skin-override-failed = Failed to lay the skin overrides over the resource pack
+assets-corrupted = The recorder install is damaged, reinstall it (bad files: { $files })
+respack-corrupted = The resource pack is damaged, install it again
+invalid-params = Invalid render parameters at `{ $field }`: { $error }
+unknown-params = Unknown render parameters: { $fields }
+line-texture-failed = Failed to build the judge line texture
+line-texture-unsupported = The judge line texture is only supported for RPE charts, so the plain line is drawn
+nvenc-busy = All NVENC sessions of the GPU are in use by other renders or programs. Wait for one to finish, or encode in software
+load-splash-failed = Failed to load splash image
+load-bga-failed = Failed to load background video `{ $name }`
+load-overlay-failed = Failed to load overlay image `{ $path }`
+results-score = Score  { $score }
+results-accuracy = Accuracy  { $accuracy }%
+results-max-combo = Max Combo  { $combo }
+results-perfect = Perfect  { $count }
+results-good = Good  { $count }
+results-bad = Bad  { $count }
+results-miss = Miss  { $count }
+credit-charter = Charter: { $name }
+credit-illustrator = Illustrator: { $name }
+credit-composer = Composer: { $name }
+roll-composer = Composer
+roll-charter = Charter
+roll-illustrator = Illustrator
+roll-respack = Resource pack
+roll-recorded-with = Recorded with Phi Recorder { $version }
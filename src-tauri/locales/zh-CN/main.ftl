@@ -3,6 +3,14 @@ tray-hide = 最小化到托盘
 tray-show = 显示窗口
 tray-tasks = 任务列表
 tray-quit = 退出
+tray-idle = 没有正在进行的渲染
+tray-waiting = 等待渲染
+tray-paused = 队列已暂停
+tray-rendering = { $name } — { $progress }%
+tray-pause = 暂停队列
+tray-resume = 继续队列
+tray-cancel = 取消当前渲染
+tray-output = 打开输出文件夹
 
 read-chart-failed = 读取谱面失败
 load-info-failed = 加载谱面信息失败
@@ -11,3 +19,5 @@ preset-exists = 同名配置已存在
 preset-not-found = 指定的配置不存在
 
 not-valid-rpe = 不是有效的 RPE 目录
+
+chart-changed = 谱面在加入队列后有改动，将渲染当前版本
@@ -3,5 +3,45 @@ load-avatar-failed = 加载头像失败
 load-chart-failed = 加载谱面失败
 load-music-failed = 加载音乐失败
 load-sfx-failed = 加载音效 `{ $name }` 失败
+ffmpeg-not-found = 未找到 FFmpeg
 run-ffmpeg-failed = 运行 ffmpeg 失败
 no-hwacc = 不支持硬件加速
+encoder-died = 编码器 { $encoder } 在渲染中途停止
+join-segments-failed = 合并已编码的分段失败
+remux-unreadable = 无法读取要重新封装的视频的时长
+remux-length-mismatch = 视频时长为 { $video } 秒，而新混音为 { $mix } 秒。该视频是由此谱面和配置渲染的吗？
+mux-failed = 将音频封装进已编码的视频失败
+software-fallback = 没有可用的硬件编码器，已改用软件编码
+ffmpeg-unknown-encoder = 当前 ffmpeg 不包含所选编码器，请换用其他编码器或安装完整版 ffmpeg
+ffmpeg-no-space = 输出文件夹所在磁盘空间不足
+ffmpeg-permission-denied = ffmpeg 无权写入输出文件夹
+ffmpeg-invalid-option = ffmpeg 拒绝了某项编码设置，请检查码率和预设
+ffmpeg-encoder-failed = 编码器无法以当前设置启动，请尝试其他预设、分辨率或编码器
+ffmpeg-exited = ffmpeg 意外停止，详情中附有其输出
+encoder-backpressure = 编码器跟不上渲染速度，{ $share }% 的时间在等待编码。请降低分辨率、帧率或编码预设
+skin-override-failed = 无法将单独替换的皮肤元素叠加到资源包上
+assets-corrupted = 录制器安装已损坏，请重新安装（损坏的文件：{ $files }）
+respack-corrupted = 资源包已损坏，请重新安装
+invalid-params = 渲染参数 `{ $field }` 有误：{ $error }
+unknown-params = 未知的渲染参数：{ $fields }
+line-texture-failed = 生成判定线贴图失败
+line-texture-unsupported = 仅 RPE 谱面支持判定线贴图，将绘制普通判定线
+nvenc-busy = 显卡的 NVENC 会话已被其他渲染或程序占满，请等待其结束，或改用软件编码
+load-splash-failed = 加载开场图片失败
+load-bga-failed = 加载背景视频 `{ $name }` 失败
+load-overlay-failed = 加载叠加图片 `{ $path }` 失败
+results-score = 分数  { $score }
+results-accuracy = 准确率  { $accuracy }%
+results-max-combo = 最大连击  { $combo }
+results-perfect = Perfect  { $count }
+results-good = Good  { $count }
+results-bad = Bad  { $count }
+results-miss = Miss  { $count }
+credit-charter = 谱师：{ $name }
+credit-illustrator = 曲绘：{ $name }
+credit-composer = 曲师：{ $name }
+roll-composer = 曲师
+roll-charter = 谱师
+roll-illustrator = 曲绘
+roll-respack = 资源包
+roll-recorded-with = 由 Phi Recorder { $version } 录制
@@ -0,0 +1,401 @@
+//! Headless render agent: accepts signed jobs over TCP, renders them in a local
+//! queue and streams the status and finally the video back to the submitter.
+//!
+//! A connection carries one job. The agent opens with a random nonce; the
+//! client answers with `Submit`, signed with its token as
+//! `HMAC-SHA256(token, nonce \n params \n chart \n size \n sha256)`, followed by
+//! the chart bytes. The agent checks the signature before it reads any of the
+//! chart, then checks the chart against the signed hash. Tokens never cross the
+//! wire, and a signature can't be replayed.
+//!
+//! Only a few connections may be in the handshake at once, and the handshake
+//! and the upload each have to finish in time.
+use crate::{
+    common::{ensure_dir, output_dir, respack_dir, CONFIG_DIR, DATA_DIR},
+    farm::{pack, recv, recv_file, send},
+    render::RenderParams,
+    task::{TaskQueue, TaskStatus},
+    ASSET_PATH,
+};
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tracing::{info, warn};
+
+/// How often the agent reports the status of a running job.
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+/// Connections that may be between connecting and passing the signature check.
+const MAX_HANDSHAKES: usize = 16;
+/// Time a client has to send a signed submission after connecting.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a signed-in client has to upload its chart.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// `agent.yml` in the agent's data folder.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AgentConfig {
+    #[serde(default = "AgentConfig::default_port")]
+    port: u16,
+    /// User name to token
+    users: HashMap<String, String>,
+    /// Renders allowed to run at once
+    #[serde(default = "AgentConfig::default_parallel")]
+    parallel: usize,
+    /// Largest chart accepted, in MiB
+    #[serde(default = "AgentConfig::default_max_chart")]
+    max_chart_mb: u64,
+    /// Renders whose video grows past this many MiB are canceled
+    #[serde(default)]
+    max_output_mb: Option<u64>,
+    /// Renders running longer than this many minutes are canceled
+    #[serde(default)]
+    max_minutes: Option<f64>,
+}
+
+impl AgentConfig {
+    fn default_port() -> u16 {
+        14514
+    }
+
+    fn default_parallel() -> usize {
+        1
+    }
+
+    fn default_max_chart() -> u64 {
+        200
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum FromClient {
+    /// Followed by the chart, `chart` being its file name and `sha256` the hex
+    /// hash of its bytes. `params` is the JSON of the `RenderParams` exactly as
+    /// signed.
+    Submit {
+        user: String,
+        signature: String,
+        params: String,
+        chart: String,
+        size: u64,
+        sha256: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum FromAgent {
+    Challenge { nonce: String },
+    Rejected { reason: String },
+    Accepted { job: String },
+    /// Sent every `STATUS_INTERVAL` while the job runs, and once more when it ends
+    Status { status: TaskStatus },
+    /// Followed by the video
+    Video { name: String, size: u64 },
+}
+
+fn signing_key(token: &str) -> Hmac<Sha256> {
+    Hmac::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length")
+}
+
+fn sign(mac: &mut Hmac<Sha256>, nonce: &str, params: &str, chart: &str, size: u64, sha256: &str) {
+    mac.update(format!("{nonce}\n{params}\n{chart}\n{size}\n{sha256}").as_bytes());
+}
+
+/// Keeps a submission from reading or writing anything on the agent outside
+/// the chart it came with, the files in `uploads` and the agent's own resource
+/// packs, and from passing ffmpeg anything but the values it asks for.
+pub(crate) fn confine(params: &mut RenderParams, uploads: &Path) -> Result<()> {
+    params.output_name = None;
+    // the queue sets it itself when it restarts a render
+    params.resume = None;
+    params.config.check_ffmpeg_values()?;
+    if params.subtitles.is_some() || params.overlays.iter().any(|it| it.image.is_some()) {
+        bail!("subtitle files and image overlays can't be used on an agent");
+    }
+    let uploads = std::fs::canonicalize(uploads)?;
+    for path in params.config.input_files() {
+        if !std::fs::canonicalize(&path).is_ok_and(|it| it.starts_with(&uploads)) {
+            bail!("{} is not among the files uploaded to the agent", path.display());
+        }
+    }
+    if let Some(respack) = params.config.to_config().res_pack_path {
+        let respack = std::fs::canonicalize(&respack).with_context(|| format!("resource pack {respack} not found on the agent"))?;
+        if !respack.starts_with(std::fs::canonicalize(respack_dir()?)?) {
+            bail!("resource packs must be one of the agent's own");
+        }
+    }
+    Ok(())
+}
+
+/// Receives the chart of a signed submission to `chart`, checks it against the
+/// signed hash and queues the job.
+async fn accept(
+    queue: &TaskQueue,
+    read: &mut BufReader<OwnedReadHalf>,
+    size: u64,
+    sha256: &str,
+    params: &str,
+    chart: &Path,
+    job: &str,
+) -> Result<u32> {
+    tokio::time::timeout(UPLOAD_TIMEOUT, recv_file(read, size, chart))
+        .await
+        .context("upload timed out")??;
+    if hex::encode(Sha256::digest(std::fs::read(chart)?)) != sha256.to_lowercase() {
+        bail!("chart does not match its signature");
+    }
+    let mut params: RenderParams = serde_json::from_str(params).context("invalid params")?;
+    confine(&mut params, chart.parent().context("chart has no folder")?)?;
+    params.path = chart.to_owned();
+    params.job_id = Some(job.to_owned());
+    queue.post(params).await
+}
+
+/// Bytes a job has written so far: the video, and the stems or segments that
+/// `keepStems` leaves next to it.
+fn output_size(output: &Path) -> u64 {
+    let size = |path: &Path| std::fs::metadata(path).map_or(0, |it| it.len());
+    let segments: u64 = std::fs::read_dir(output.with_extension("parts"))
+        .map(|entries| entries.filter_map(|it| Some(it.ok()?.metadata().ok()?.len())).sum())
+        .unwrap_or(0);
+    size(output) + size(&output.with_extension("video.mkv")) + size(&output.with_extension("audio.wav")) + segments
+}
+
+/// Sends the final status, followed by the video if there is one.
+async fn finish(write: &mut OwnedWriteHalf, status: TaskStatus, output: &Path) -> Result<()> {
+    let done = matches!(status, TaskStatus::Done { .. });
+    send(write, &FromAgent::Status { status }).await?;
+    if done {
+        let mut file = tokio::fs::File::open(output).await?;
+        let name = output.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let size = file.metadata().await?.len();
+        send(write, &FromAgent::Video { name, size }).await?;
+        tokio::io::copy(&mut file, write).await?;
+        write.flush().await?;
+    }
+    Ok(())
+}
+
+async fn handle(
+    config: Arc<AgentConfig>,
+    queue: Arc<TaskQueue>,
+    stream: TcpStream,
+    handshake: OwnedSemaphorePermit,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let (nonce, submit) = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+        send(&mut write, &FromAgent::Challenge { nonce: nonce.clone() }).await?;
+        anyhow::Ok((nonce, recv::<FromClient>(&mut read).await?))
+    })
+    .await
+    .context("handshake timed out")??;
+
+    let FromClient::Submit { user, signature, params, chart, size, sha256 } = submit;
+    let reject = |reason: &str| FromAgent::Rejected { reason: reason.to_owned() };
+    let token = config.users.get(&user);
+    // an unknown user is checked against an empty token and gets the same answer
+    // as a bad signature, so that user names can't be probed
+    let mut mac = signing_key(token.map_or("", String::as_str));
+    sign(&mut mac, &nonce, &params, &chart, size, &sha256);
+    if mac.verify_slice(&hex::decode(&signature).unwrap_or_default()).is_err() || token.is_none() {
+        send(&mut write, &reject("unauthorized")).await?;
+        bail!("unauthorized submission as {user}");
+    }
+    // signed in, so no longer one of the connections that may be anyone
+    drop(handshake);
+    if size > config.max_chart_mb << 20 {
+        send(&mut write, &reject("chart too large")).await?;
+        bail!("{user} sent a chart of {size} bytes");
+    }
+    let extension = Path::new(&chart).extension().map(|it| it.to_string_lossy().into_owned()).unwrap_or_default();
+    let job = uuid::Uuid::new_v4().to_string();
+    let path = ensure_dir(DATA_DIR.get().unwrap().join("agent")).join(format!("{job}.{extension}"));
+
+    let id = match accept(&queue, &mut read, size, &sha256, &params, &path, &job).await {
+        Ok(id) => id,
+        Err(err) => {
+            let _ = std::fs::remove_file(&path);
+            send(&mut write, &reject(&err.to_string())).await?;
+            bail!("rejected job from {user}: {err:?}");
+        }
+    };
+    info!("Job {job} from {user} accepted");
+    send(&mut write, &FromAgent::Accepted { job: job.clone() }).await?;
+
+    let started = Instant::now();
+    let mut quota = None;
+    let (mut status, output) = loop {
+        let (status, output) = queue.status(id).await;
+        if matches!(status, TaskStatus::Done { .. } | TaskStatus::Failed { .. } | TaskStatus::Canceled) {
+            break (status, output);
+        }
+        if quota.is_none() {
+            if config.max_minutes.is_some_and(|it| started.elapsed().as_secs_f64() > it * 60.) {
+                quota = Some(format!("exceeded the time limit of {} minutes", config.max_minutes.unwrap()));
+            } else if config.max_output_mb.is_some_and(|it| output_size(&output) > it << 20) {
+                quota = Some(format!("exceeded the size limit of {} MiB", config.max_output_mb.unwrap()));
+            }
+            if let Some(reason) = &quota {
+                warn!("Job {job} from {user} {reason}");
+                queue.cancel(id).await;
+            }
+        }
+        if send(&mut write, &FromAgent::Status { status }).await.is_err() {
+            // nobody is left to receive the video
+            queue.cancel(id).await;
+        }
+        tokio::time::sleep(STATUS_INTERVAL).await;
+    };
+    let _ = std::fs::remove_file(&path);
+    if let (TaskStatus::Canceled, Some(error)) = (&status, quota) {
+        status = TaskStatus::Failed {
+            error,
+            code: None,
+            stage: None,
+        };
+    }
+
+    let result = finish(&mut write, status, &output).await;
+    // the video belongs to the submitter, the agent keeps nothing
+    let _ = std::fs::remove_file(&output);
+    let _ = std::fs::remove_file(output.with_extension("manifest.json"));
+    let _ = std::fs::remove_file(output.with_extension("video.mkv"));
+    let _ = std::fs::remove_file(output.with_extension("audio.wav"));
+    let _ = std::fs::remove_dir_all(output.with_extension("parts"));
+    result
+}
+
+/// Renders `params` on the agent at `address`, reporting its status as it
+/// arrives, and saves the video to the local output folder.
+pub async fn submit(
+    address: &str,
+    user: &str,
+    token: &str,
+    params: &RenderParams,
+    mut on_status: impl FnMut(&TaskStatus),
+) -> Result<PathBuf> {
+    let stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("failed to connect to {address}"))?;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let FromAgent::Challenge { nonce } = recv(&mut read).await? else {
+        bail!("agent did not send a challenge");
+    };
+
+    let params_json = serde_json::to_string(params)?;
+    let (chart, bytes) = pack(&params.path)?;
+    let size = bytes.len() as u64;
+    let sha256 = hex::encode(Sha256::digest(&bytes));
+    let mut mac = signing_key(token);
+    sign(&mut mac, &nonce, &params_json, &chart, size, &sha256);
+    let submit = FromClient::Submit {
+        user: user.to_owned(),
+        signature: hex::encode(mac.finalize().into_bytes()),
+        params: params_json,
+        chart,
+        size,
+        sha256,
+    };
+    send(&mut write, &submit).await?;
+    let uploaded = async {
+        write.write_all(&bytes).await?;
+        write.flush().await
+    }
+    .await;
+    if let Err(err) = uploaded {
+        // a rejected submission is answered before the chart is read, and the
+        // agent may have hung up on the rest of it
+        if let Ok(FromAgent::Rejected { reason }) = recv(&mut read).await {
+            bail!("agent rejected the job: {reason}");
+        }
+        return Err(err.into());
+    }
+
+    loop {
+        match recv(&mut read).await? {
+            FromAgent::Rejected { reason } => bail!("agent rejected the job: {reason}"),
+            FromAgent::Accepted { job } => info!("Agent at {address} accepted job {job}"),
+            FromAgent::Status { status } => {
+                on_status(&status);
+                match status {
+                    TaskStatus::Failed { error, .. } => bail!("remote render failed: {error}"),
+                    TaskStatus::Canceled => bail!("remote render was canceled"),
+                    _ => {}
+                }
+            }
+            FromAgent::Video { name, size } => {
+                let name = Path::new(&name).file_name().context("invalid video name")?;
+                let output = output_dir()?.join(name);
+                recv_file(&mut read, size, &output).await?;
+                return Ok(output);
+            }
+            FromAgent::Challenge { .. } => bail!("unexpected challenge"),
+        }
+    }
+}
+
+/// `agent <asset path> <data folder>`: serves jobs as configured by
+/// `agent.yml` in the data folder, which also holds the agent's `respack`
+/// folder and its work files.
+pub async fn main() -> Result<()> {
+    let asset: PathBuf = std::env::args().nth(2).context("missing asset path")?.into();
+    let data: PathBuf = std::env::args().nth(3).context("missing data folder")?.into();
+    let file = data.join("agent.yml");
+    let config: AgentConfig = serde_yaml::from_str(
+        &std::fs::read_to_string(&file).with_context(|| format!("failed to read {}", file.display()))?,
+    )
+    .context("invalid agent.yml")?;
+    if config.users.is_empty() {
+        bail!("agent.yml lists no users");
+    }
+
+    ASSET_PATH.set(asset).unwrap();
+    let data = ensure_dir(data);
+    CONFIG_DIR.set(data.clone()).unwrap();
+    DATA_DIR.set(data).unwrap();
+
+    let queue = Arc::new(TaskQueue::new());
+    queue.set_parallel(config.parallel);
+    let listener = TcpListener::bind(("0.0.0.0", config.port))
+        .await
+        .with_context(|| format!("failed to listen on port {}", config.port))?;
+    info!("Agent listening on port {}", config.port);
+    let config = Arc::new(config);
+    let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let Ok(handshake) = Arc::clone(&handshakes).try_acquire_owned() else {
+            warn!("Dropped connection from {address}: too many handshakes at once");
+            continue;
+        };
+        let (config, queue) = (Arc::clone(&config), Arc::clone(&queue));
+        tokio::spawn(async move {
+            if let Err(err) = handle(config, queue, stream, handshake).await {
+                warn!("Job from {address} ended: {err:?}");
+            }
+        });
+    }
+}
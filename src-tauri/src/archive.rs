@@ -0,0 +1,242 @@
+use anyhow::{bail, Context, Result};
+use prpr::fs::{self, FileSystem};
+use sha2::{Digest, Sha256};
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Most files a chart archive may hold.
+const MAX_ENTRIES: usize = 10_000;
+/// Most bytes a chart archive may unpack to, background videos included.
+const MAX_UNPACKED: u64 = 4 << 30;
+/// How long an unpacked archive, or a zip found safe, is remembered after its
+/// last use.
+const CACHE_TTL: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+/// Checks that a zip unpacks to something the size of a chart and stays inside
+/// its own folder. Entries are inflated rather than trusting their headers, which
+/// a zip bomb lies in.
+fn check_zip(path: &Path) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path)?).context("not a zip archive")?;
+    let mut budget = Budget::new();
+    for i in 0..zip.len() {
+        budget.add_file()?;
+        let file = zip.by_index(i)?;
+        if file.enclosed_name().is_none() {
+            bail!("archive entry {:?} points outside the archive", file.name());
+        }
+        let name = file.name().to_owned();
+        let size = std::io::copy(&mut file.take(budget.left + 1), &mut std::io::sink())
+            .with_context(|| format!("archive entry {name:?} is damaged"))?;
+        budget.add_bytes(size)?;
+    }
+    Ok(())
+}
+
+/// `name` as a relative path that stays inside the folder it is unpacked in.
+fn enclosed(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Counts files and bytes unpacked from an archive against the limits.
+struct Budget {
+    entries: usize,
+    left: u64,
+}
+
+impl Budget {
+    fn new() -> Self {
+        Self {
+            entries: 0,
+            left: MAX_UNPACKED,
+        }
+    }
+
+    fn add_file(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.entries > MAX_ENTRIES {
+            bail!("archive has more than the {MAX_ENTRIES} files allowed");
+        }
+        Ok(())
+    }
+
+    fn add_bytes(&mut self, size: u64) -> Result<()> {
+        if size > self.left {
+            bail!("archive unpacks to more than {} MiB", MAX_UNPACKED >> 20);
+        }
+        self.left -= size;
+        Ok(())
+    }
+}
+
+fn extract_7z(path: &Path, target: &Path) -> Result<()> {
+    let mut reader = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())?;
+    let mut budget = Budget::new();
+    let mut failure = None;
+    reader.for_each_entries(|entry, data| {
+        if entry.is_directory() {
+            return Ok(true);
+        }
+        let result = (|| -> Result<()> {
+            budget.add_file()?;
+            let name = enclosed(Path::new(entry.name()))
+                .with_context(|| format!("archive entry {:?} points outside the archive", entry.name()))?;
+            let dest = target.join(name);
+            std::fs::create_dir_all(dest.parent().unwrap())?;
+            // the header's size is not trusted, the data is cut off past the budget
+            let size = std::io::copy(&mut data.take(budget.left + 1), &mut std::fs::File::create(&dest)?)?;
+            budget.add_bytes(size)
+        })();
+        match result {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                failure = Some(err);
+                Ok(false)
+            }
+        }
+    })?;
+    failure.map_or(Ok(()), Err)
+}
+
+/// Folder holding the chart in an unpacked archive: the one with `info.yml`,
+/// since packs are often zipped along with their folder.
+fn chart_root(dir: &Path) -> PathBuf {
+    let mut stack = vec![dir.to_owned()];
+    while let Some(dir) = stack.pop() {
+        if dir.join("info.yml").is_file() {
+            return dir;
+        }
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            stack.extend(entries.filter_map(|it| it.ok()).map(|it| it.path()).filter(|it| it.is_dir()));
+        }
+    }
+    dir.to_owned()
+}
+
+/// Where unpacked archives and the zips found safe are remembered.
+fn cache_dir() -> PathBuf {
+    tempfile::env::temp_dir().join("phi-recorder-charts")
+}
+
+/// Names the version of the archive at `path` by its path, size and modification
+/// time, which change whenever the file is replaced.
+fn version_key(path: &Path) -> Result<String> {
+    let meta = std::fs::metadata(path)?;
+    let modified = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let key = Sha256::digest(format!("{}:{}:{modified}", path.canonicalize()?.display(), meta.len()));
+    Ok(hex::encode(&key[..16]))
+}
+
+/// [`check_zip`], skipped for a version of the zip that already passed, since
+/// inflating every entry is as slow as unpacking the whole chart.
+fn check_zip_cached(path: &Path) -> Result<()> {
+    let passed = cache_dir().join(format!("{}.checked", version_key(path)?));
+    if !passed.is_file() {
+        check_zip(path)?;
+        crate::common::ensure_dir(cache_dir());
+    }
+    touch(&passed);
+    Ok(())
+}
+
+/// Marks an entry of the cache as used now, so [`prune_cache`] keeps it.
+fn touch(entry: &Path) {
+    let _ = std::fs::write(used_marker(entry), "");
+}
+
+fn used_marker(entry: &Path) -> PathBuf {
+    if entry.is_dir() {
+        entry.join(".used")
+    } else {
+        entry.to_owned()
+    }
+}
+
+/// Removes what the cache holds for archives not opened in [`CACHE_TTL`], along
+/// with unpacks left behind by renders that were killed midway.
+fn prune_cache() {
+    let Ok(entries) = std::fs::read_dir(cache_dir()) else {
+        return;
+    };
+    for path in entries.filter_map(|it| Some(it.ok()?.path())) {
+        let idle = std::fs::metadata(used_marker(&path))
+            .or_else(|_| std::fs::metadata(&path))
+            .and_then(|it| it.modified())
+            .ok()
+            .and_then(|it| it.elapsed().ok());
+        if idle.is_some_and(|it| it > CACHE_TTL) {
+            let _ = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+        }
+    }
+}
+
+/// Unpacks a 7z archive into the temp folder, once per version of the file, so
+/// that renders after the first reuse it.
+fn unpack(path: &Path) -> Result<PathBuf> {
+    let cache = cache_dir();
+    let target = cache.join(version_key(path)?);
+    if !target.is_dir() {
+        let partial = tempfile::Builder::new().prefix(".unpack-").tempdir_in(crate::common::ensure_dir(cache))?;
+        extract_7z(path, partial.path())?;
+        // a render racing for the same archive may have won
+        if std::fs::rename(partial.into_path(), &target).is_err() && !target.is_dir() {
+            bail!("failed to move the unpacked archive into {}", target.display());
+        }
+    }
+    touch(&target);
+    Ok(chart_root(&target))
+}
+
+fn fingerprint_into(hasher: &mut Sha256, root: &Path, path: &Path) -> Result<()> {
+    let meta = std::fs::metadata(path)?;
+    if meta.is_dir() {
+        let mut entries = std::fs::read_dir(path)?.map(|it| Ok(it?.path())).collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            fingerprint_into(hasher, root, &entry)?;
+        }
+    } else {
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let name = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        hasher.update(format!("{name}:{}:{modified}\n", meta.len()));
+    }
+    Ok(())
+}
+
+/// Changes whenever the chart at `path` does: a file is saved, added, removed or
+/// renamed in a chart folder, or an archive is replaced. Only looks at metadata,
+/// so it is cheap enough to check before every use.
+pub fn fingerprint(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    fingerprint_into(&mut hasher, path, path)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Opens a chart for reading, checking zips with [`check_zip`] first (once per
+/// version of the file) and unpacking 7z archives to a folder.
+pub fn open(path: &Path) -> Result<Box<dyn FileSystem + Send + Sync + 'static>> {
+    if path.is_file() {
+        prune_cache();
+        let extension = path.extension().map(|it| it.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if extension == "7z" {
+            let dir = unpack(path).with_context(|| format!("failed to unpack {}", path.display()))?;
+            return fs::fs_from_file(&dir);
+        }
+        check_zip_cached(path).with_context(|| format!("refusing to open {}", path.display()))?;
+    }
+    fs::fs_from_file(path)
+}
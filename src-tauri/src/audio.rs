@@ -0,0 +1,279 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sasa::Frame;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// One-pole smoothing coefficient for an envelope with the given time constant
+/// (in milliseconds), advancing `rate` times per second.
+pub fn envelope_coeff(time: f32, rate: f32) -> f32 {
+    if time <= 0. {
+        1.
+    } else {
+        1. - (-1. / (time / 1000. * rate)).exp()
+    }
+}
+
+/// Estimates the inter-sample peak between `b` and `c` with a 4x oversampled cubic.
+fn true_peak(a: f32, b: f32, c: f32, d: f32) -> f32 {
+    let mut peak = b.abs();
+    for t in [0.25, 0.5, 0.75] {
+        let value = b
+            + 0.5
+                * t
+                * (c - a + t * (2. * a - 5. * b + 4. * c - d + t * (3. * (b - c) + d - a)));
+        peak = peak.max(value.abs());
+    }
+    peak
+}
+
+/// Lookahead brickwall limiter for an interleaved stereo buffer.
+///
+/// The gain needed for every frame is computed up front, then spread backwards
+/// over `lookahead` frames so the gain is already down when a peak arrives.
+pub fn limit(samples: &mut [f32], ceiling: f32, lookahead: usize, release_coeff: f32) {
+    let frames = samples.len() / 2;
+    if frames == 0 || ceiling <= 0. {
+        return;
+    }
+    let lookahead = lookahead.max(1);
+    let at = |i: isize, ch: usize| -> f32 {
+        let i = i.clamp(0, frames as isize - 1) as usize;
+        samples[i * 2 + ch]
+    };
+    let gains: Vec<f32> = (0..frames as isize)
+        .map(|i| {
+            let peak = (0..2)
+                .map(|ch| true_peak(at(i - 1, ch), at(i, ch), at(i + 1, ch), at(i + 2, ch)))
+                .fold(0., f32::max);
+            if peak > ceiling {
+                ceiling / peak
+            } else {
+                1.
+            }
+        })
+        .collect();
+
+    // sliding minimum over [i, i + lookahead)
+    let mut minimum = vec![1.; frames];
+    let mut window: VecDeque<usize> = VecDeque::new();
+    for i in (0..frames).rev() {
+        while window.back().is_some_and(|&j| gains[j] >= gains[i]) {
+            window.pop_back();
+        }
+        window.push_back(i);
+        while window.front().is_some_and(|&j| j >= i + lookahead) {
+            window.pop_front();
+        }
+        minimum[i] = gains[*window.front().unwrap()];
+    }
+
+    // moving average over (i - lookahead, i] keeps the ramp smooth while
+    // staying below the required gain at the peak itself
+    let mut sum = 0.;
+    let mut envelope = 1.;
+    for i in 0..frames {
+        sum += minimum[i] as f64;
+        if i >= lookahead {
+            sum -= minimum[i - lookahead] as f64;
+        }
+        let count = (i + 1).min(lookahead);
+        let target = (sum / count as f64) as f32;
+        if target < envelope {
+            envelope = target;
+        } else {
+            envelope += release_coeff * (target - envelope);
+        }
+        samples[i * 2] = (samples[i * 2] * envelope).clamp(-ceiling, ceiling);
+        samples[i * 2 + 1] = (samples[i * 2 + 1] * envelope).clamp(-ceiling, ceiling);
+    }
+}
+
+/// Level, relative to a clip's peak, at which its attack is taken to begin.
+const ONSET_LEVEL: f32 = 0.1;
+/// Latest onset that is compensated; anything later is the sound itself.
+const MAX_ONSET: f64 = 0.05;
+
+/// Seconds of silence or soft attack before a clip reaches [`ONSET_LEVEL`] of its
+/// peak, at most [`MAX_ONSET`].
+pub fn onset(frames: &[Frame], sample_rate: u32) -> f64 {
+    let level = |frame: &Frame| frame.0.abs().max(frame.1.abs());
+    let peak = frames.iter().map(level).fold(0., f32::max);
+    if peak <= 0. {
+        return 0.;
+    }
+    let first = frames.iter().position(|it| level(it) >= peak * ONSET_LEVEL).unwrap_or(0);
+    (first as f64 / sample_rate as f64).min(MAX_ONSET)
+}
+
+/// Direct form I biquad, normalized so that `a0` is 1.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, x: [0.; 2], y: [0.; 2] }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two stages of the BS.1770 K-weighting filter, a high shelf modelling the
+/// head and a high-pass, designed for `sample_rate`.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2. * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    );
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad::new([1., -2., 1.], [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0]);
+    [shelf, high_pass]
+}
+
+/// Loudness figures of a finished mix.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Loudness {
+    /// Integrated loudness (BS.1770, gated) in LUFS; `None` for silence
+    pub integrated: Option<f64>,
+    /// Highest inter-sample peak, in dBTP
+    pub true_peak: f64,
+    /// Samples at or beyond full scale, which clip once written as integers
+    pub clipped: usize,
+}
+
+/// Measures an interleaved stereo buffer.
+pub fn loudness(samples: &[f32], sample_rate: u32) -> Loudness {
+    let frames = samples.len() / 2;
+    let mut filters = [k_weighting(sample_rate as f64), k_weighting(sample_rate as f64)];
+    // mean square of the weighted signal in 100 ms steps; blocks are 400 ms
+    // long and overlap by 75%, so each is made of four steps
+    let step = (sample_rate as usize / 10).max(1);
+    let mut steps = Vec::with_capacity(frames / step + 1);
+    let mut sum = 0.;
+    for i in 0..frames {
+        for (ch, [shelf, high_pass]) in filters.iter_mut().enumerate() {
+            let y = high_pass.process(shelf.process(samples[i * 2 + ch] as f64));
+            sum += y * y;
+        }
+        if (i + 1) % step == 0 {
+            steps.push(sum / step as f64);
+            sum = 0.;
+        }
+    }
+    let blocks: Vec<f64> = steps.windows(4).map(|it| it.iter().sum::<f64>() / 4.).collect();
+    let lufs = |power: f64| -0.691 + 10. * power.log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = blocks.iter().copied().filter(|&it| lufs(it) > threshold).collect();
+        (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+    };
+    let integrated = gated_mean(-70.).and_then(|mean| gated_mean(lufs(mean) - 10.)).map(lufs);
+
+    let at = |i: isize, ch: usize| -> f32 {
+        let i = i.clamp(0, frames as isize - 1) as usize;
+        samples[i * 2 + ch]
+    };
+    let peak = (0..frames as isize)
+        .flat_map(|i| (0..2).map(move |ch| (i, ch)))
+        .map(|(i, ch)| true_peak(at(i - 1, ch), at(i, ch), at(i + 1, ch), at(i + 2, ch)))
+        .fold(0., f32::max);
+    Loudness {
+        integrated,
+        true_peak: 20. * (peak as f64).max(1e-10).log10(),
+        clipped: samples.iter().filter(|it| it.abs() >= 1.).count(),
+    }
+}
+
+/// Triangular (TPDF) dither used when quantizing the mix to integer PCM.
+pub struct TpdfDither(StdRng);
+
+impl TpdfDither {
+    pub fn new() -> Self {
+        Self(StdRng::seed_from_u64(0))
+    }
+
+    /// Quantizes `sample` to a signed `bits`-bit integer with ±1 LSB of triangular noise.
+    pub fn quantize(&mut self, sample: f32, bits: u32) -> i32 {
+        let scale = ((1_i64 << (bits - 1)) - 1) as f64;
+        let noise = self.0.gen::<f64>() - self.0.gen::<f64>();
+        (sample as f64 * scale + noise).round().clamp(-scale - 1., scale) as i32
+    }
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -std::f32::consts::TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (xr, xi) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Levels of `bands` log-spaced bands from 40 Hz to 16 kHz in a mono `window`
+/// (power-of-two long), mapped from -70..0 dBFS to 0..1.
+pub fn spectrum(window: &[f32], sample_rate: f32, bands: usize) -> Vec<f32> {
+    let n = window.len();
+    let mut re: Vec<f32> = window
+        .iter()
+        .enumerate()
+        .map(|(i, &it)| it * (0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / n as f32).cos()))
+        .collect();
+    let mut im = vec![0.; n];
+    fft(&mut re, &mut im);
+    let (low, high) = (40_f32, 16000_f32.min(sample_rate / 2.));
+    let bin = |freq: f32| ((freq / sample_rate * n as f32) as usize).clamp(1, n / 2);
+    (0..bands)
+        .map(|i| {
+            let from = bin(low * (high / low).powf(i as f32 / bands as f32));
+            let to = bin(low * (high / low).powf((i + 1) as f32 / bands as f32)).max(from + 1);
+            let peak = (from..to)
+                .map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt())
+                .fold(0., f32::max);
+            // the Hann window halves the amplitude
+            let db = 20. * (peak * 4. / n as f32).max(1e-6).log10();
+            ((db + 70.) / 70.).clamp(0., 1.)
+        })
+        .collect()
+}
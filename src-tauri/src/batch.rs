@@ -0,0 +1,175 @@
+use crate::{
+    archive,
+    render::{RenderConfig, RenderParams},
+};
+use anyhow::{bail, Context, Result};
+use prpr::fs;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    ops::DerefMut,
+    path::{Path, PathBuf},
+};
+
+/// One row of a job list.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchJob {
+    chart: String,
+    /// File name template, see `RenderParams::output_name`
+    #[serde(default)]
+    output: Option<String>,
+    /// Name of a saved preset to start from instead of the current config
+    #[serde(default)]
+    preset: Option<String>,
+    /// `RenderConfig` fields (camelCase) laid over the preset
+    #[serde(default)]
+    overrides: Map<String, Value>,
+    #[serde(default)]
+    gpu: Option<u32>,
+    /// See `RenderParams::offset_delta`
+    #[serde(default)]
+    offset_delta: f32,
+}
+
+/// Splits CSV text into rows of fields, with `"`-quoted fields holding commas,
+/// quotes (doubled) and newlines.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let (mut row, mut field) = (Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, text.chars().peekable());
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|row| row.iter().any(|it| !it.trim().is_empty()));
+    rows
+}
+
+/// CSV with a header row: `chart`, `output`, `preset`, `gpu` and `offsetDelta` columns, plus one
+/// column per overridden config field. Cells are read as JSON where they parse
+/// (numbers, booleans, arrays), as plain strings otherwise, and skipped if empty.
+fn parse_csv_jobs(text: &str) -> Result<Vec<BatchJob>> {
+    let mut rows = parse_csv(text).into_iter();
+    let header = rows.next().context("job list is empty")?;
+    rows.enumerate()
+        .map(|(i, row)| {
+            let mut job = BatchJob {
+                chart: String::new(),
+                output: None,
+                preset: None,
+                overrides: Map::new(),
+                gpu: None,
+                offset_delta: 0.,
+            };
+            for (column, cell) in header.iter().zip(row) {
+                let cell = cell.trim();
+                if cell.is_empty() {
+                    continue;
+                }
+                match column.trim() {
+                    "chart" => job.chart = cell.to_owned(),
+                    "output" => job.output = Some(cell.to_owned()),
+                    "preset" => job.preset = Some(cell.to_owned()),
+                    "gpu" => job.gpu = Some(cell.parse().with_context(|| format!("invalid gpu {cell} in row {}", i + 2))?),
+                    "offsetDelta" => {
+                        job.offset_delta =
+                            cell.parse().with_context(|| format!("invalid offsetDelta {cell} in row {}", i + 2))?
+                    }
+                    key => {
+                        let value = serde_json::from_str(cell).unwrap_or_else(|_| Value::String(cell.to_owned()));
+                        job.overrides.insert(key.to_owned(), value);
+                    }
+                }
+            }
+            if job.chart.is_empty() {
+                bail!("row {} has no chart", i + 2);
+            }
+            Ok(job)
+        })
+        .collect()
+}
+
+/// Jobs of a `.toml` job list, which can't be an array at the top level.
+#[derive(Deserialize)]
+struct TomlJobs {
+    jobs: Vec<BatchJob>,
+}
+
+/// Reads a `.json` or `.yml`/`.yaml` (array of jobs), `.toml` (`[[jobs]]`
+/// tables) or `.csv` job list into render params, starting every job from
+/// `config` or from its preset.
+pub async fn load(path: &Path, config: &RenderConfig, presets: &HashMap<String, RenderConfig>) -> Result<Vec<RenderParams>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let text = text.trim_start_matches('\u{feff}');
+    let extension = path.extension().map(|it| it.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let jobs: Vec<BatchJob> = match extension.as_str() {
+        "csv" => parse_csv_jobs(text)?,
+        "yml" | "yaml" => serde_yaml::from_str(text).context("invalid job list")?,
+        "toml" => toml::from_str::<TomlJobs>(text).context("invalid job list")?.jobs,
+        _ => serde_json::from_str(text).context("invalid job list")?,
+    };
+
+    let mut result = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        let base = match &job.preset {
+            Some(name) => presets.get(name).with_context(|| format!("preset {name} not found"))?,
+            None => config,
+        };
+        let mut config = serde_json::to_value(base)?;
+        if let Some(config) = config.as_object_mut() {
+            config.extend(job.overrides);
+        }
+        // strict, as a misspelled override would otherwise be dropped without a word
+        let mut unknown = Vec::new();
+        let ignored = serde_ignored::Deserializer::new(config, |path| unknown.push(path.to_string()));
+        let config: RenderConfig = serde_path_to_error::deserialize(ignored).map_err(|err| {
+            let field = err.path().to_string();
+            anyhow::Error::new(err.into_inner()).context(format!("invalid override {field} for {}", job.chart))
+        })?;
+        if !unknown.is_empty() {
+            bail!("unknown overrides for {}: {}", job.chart, unknown.join(", "));
+        }
+
+        // relative to the job list, not to wherever the app was started
+        let chart = match path.parent() {
+            Some(dir) => dir.join(&job.chart),
+            None => PathBuf::from(&job.chart),
+        };
+        let mut fs = archive::open(&chart).with_context(|| format!("failed to open {}", job.chart))?;
+        let info = fs::load_info(fs.deref_mut()).await?;
+        result.push(RenderParams {
+            path: chart,
+            info,
+            config,
+            progress_frames: None,
+            progress_millis: None,
+            job_id: None,
+            subtitles: None,
+            overlays: Vec::new(),
+            output_name: job.output,
+            gpu: job.gpu,
+            resume: None,
+            offset_delta: job.offset_delta,
+            strict: false,
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,148 @@
+use crate::render::cmd_hidden;
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::*;
+use std::{
+    io::{Read, Write},
+    process::{Child, ChildStdout, Stdio},
+};
+use tempfile::NamedTempFile;
+use tracing::info;
+
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "avi", "flv", "m4v"];
+/// How much smaller than the frame the video is decoded; it is scaled back up
+/// smoothly, and usually dimmed and blurred anyway.
+const DOWNSCALE: u32 = 2;
+
+/// The background video in a chart package, among the files at its root: one
+/// named like `bga` or `background` if there are several.
+pub fn find(names: &[String]) -> Option<&String> {
+    let videos: Vec<&String> = names
+        .iter()
+        .filter(|it| {
+            it.rsplit_once('.')
+                .map_or(false, |(_, ext)| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    videos
+        .iter()
+        .find(|it| {
+            let name = it.to_lowercase();
+            name.starts_with("bga") || name.starts_with("background")
+        })
+        .or_else(|| videos.first())
+        .copied()
+}
+
+/// A background video decoded by an ffmpeg child, one frame per video frame of
+/// the render, so that it can be read in step with chart time.
+pub struct Bga {
+    proc: Child,
+    stdout: ChildStdout,
+    image: Image,
+    /// Read into first, so that a video cut off mid-frame leaves the last whole one
+    next: Vec<u8>,
+    texture: Texture2D,
+    /// Index of the frame in `image`, `None` before the first
+    index: Option<u64>,
+    fps: u32,
+    /// Chart time at which the video starts
+    offset: f32,
+    ended: bool,
+    // ffmpeg reads the video from here
+    _file: NamedTempFile,
+}
+
+impl Bga {
+    /// Starts decoding `bytes` (a video with extension `name`'s) scaled to cover
+    /// `resolution` and blurred by `blur` (a Gaussian sigma in output pixels).
+    pub fn open(
+        ffmpeg: &str,
+        name: &str,
+        bytes: &[u8],
+        resolution: (u32, u32),
+        fps: u32,
+        blur: f32,
+        offset: f32,
+    ) -> Result<Self> {
+        let extension = name.rsplit_once('.').map_or("mp4", |(_, it)| it);
+        let mut file = tempfile::Builder::new().suffix(&format!(".{extension}")).tempfile()?;
+        file.write_all(bytes)?;
+        file.flush()?;
+
+        // raw frames have to be of even size for most pixel formats on the way
+        let (w, h) = ((resolution.0 / DOWNSCALE).max(2) & !1, (resolution.1 / DOWNSCALE).max(2) & !1);
+        let mut filters = format!("fps={fps},scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h}");
+        if blur > 0. {
+            filters.push_str(&format!(",gblur=sigma={}", blur / DOWNSCALE as f32));
+        }
+        // drawn the same way as render targets, which come out upside down
+        filters.push_str(",vflip");
+        info!("BGA: {name}, decoded at {w}x{h}");
+        let mut proc = cmd_hidden(ffmpeg)
+            .args(["-loglevel", "error", "-i"])
+            .arg(file.path())
+            .args(["-an", "-sn", "-vf", &filters, "-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("failed to run ffmpeg")?;
+        let stdout = proc.stdout.take().unwrap();
+        let image = Image {
+            bytes: vec![0; w as usize * h as usize * 4],
+            width: w as u16,
+            height: h as u16,
+        };
+        let texture = Texture2D::from_image(&image);
+        texture.set_filter(FilterMode::Linear);
+        let mut bga = Self {
+            proc,
+            stdout,
+            next: image.bytes.clone(),
+            image,
+            texture,
+            index: None,
+            fps,
+            offset,
+            ended: false,
+            _file: file,
+        };
+        // fails right away on a video ffmpeg can't read
+        bga.advance(0);
+        if bga.ended {
+            bail!("no frames could be decoded from {name}");
+        }
+        bga.texture.update(&bga.image);
+        Ok(bga)
+    }
+
+    /// Reads frames up to `index`, keeping the last one once the video has ended.
+    fn advance(&mut self, index: u64) {
+        while !self.ended && self.index.map_or(true, |it| it < index) {
+            if self.stdout.read_exact(&mut self.next).is_err() {
+                self.ended = true;
+                break;
+            }
+            std::mem::swap(&mut self.next, &mut self.image.bytes);
+            self.index = Some(self.index.map_or(0, |it| it + 1));
+        }
+    }
+
+    /// The frame shown at chart time `time`: the first one until the video starts
+    /// and the last one after it ends.
+    pub fn frame(&mut self, time: f32) -> Texture2D {
+        let index = ((time - self.offset).max(0.) * self.fps as f32) as u64;
+        let before = self.index;
+        self.advance(index);
+        if self.index != before {
+            self.texture.update(&self.image);
+        }
+        self.texture
+    }
+}
+
+impl Drop for Bga {
+    fn drop(&mut self) {
+        let _ = self.proc.kill();
+        let _ = self.proc.wait();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::manifest::hash_path;
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// SHA-256 of every bundled asset at build time, by path relative to the asset folder.
+const ASSETS: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/asset_hashes.rs"));
+
+/// Where the stamp of the last clean [`verify_assets`] is kept.
+fn asset_stamp_path() -> PathBuf {
+    std::env::temp_dir().join("phi-recorder-assets.stamp")
+}
+
+/// Path, size and modification time of every bundled asset in `dir`, which
+/// changes whenever an asset is touched, so a render can skip hashing assets
+/// that were already found intact.
+fn asset_stamp(dir: &Path) -> Option<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(dir.to_string_lossy().as_bytes());
+    for (name, expected) in ASSETS {
+        let meta = std::fs::metadata(dir.join(name)).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        hasher.update(format!("\n{name} {expected} {} {}", meta.len(), modified.as_nanos()));
+    }
+    Some(hex::encode(hasher.finalize()))
+}
+
+/// Bundled assets in `dir` that are missing or differ from the build, each with
+/// what is wrong with it.
+pub fn verify_assets(dir: &Path) -> Vec<String> {
+    let stamp = asset_stamp(dir);
+    if stamp.is_some() && std::fs::read_to_string(asset_stamp_path()).ok() == stamp {
+        return Vec::new();
+    }
+    let broken: Vec<String> = ASSETS
+        .iter()
+        .filter_map(|(name, expected)| match hash_path(&dir.join(name)) {
+            Ok(hash) if hash == *expected => None,
+            Ok(_) => Some(format!("{name} (corrupted)")),
+            Err(_) => Some(format!("{name} (missing)")),
+        })
+        .collect();
+    if let Some(stamp) = stamp.filter(|_| broken.is_empty()) {
+        let _ = std::fs::write(asset_stamp_path(), stamp);
+    }
+    broken
+}
+
+/// Where the hash of an installed resource pack is kept.
+fn sidecar(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Remembers the hash of a freshly installed resource pack, for [`verify_respack`].
+pub fn record(path: &Path) -> Result<()> {
+    let file = sidecar(path);
+    std::fs::write(&file, hash_path(path)?).with_context(|| format!("failed to write {}", file.display()))
+}
+
+/// Checks a resource pack against the hash recorded when it was installed, or,
+/// for a zip without one, that every file in it still reads back intact.
+pub fn verify_respack(path: &Path) -> Result<()> {
+    if let Ok(expected) = std::fs::read_to_string(sidecar(path)) {
+        if hash_path(path)? != expected.trim() {
+            bail!("{} changed since it was installed", path.display());
+        }
+        return Ok(());
+    }
+    if path.is_dir() {
+        return Ok(());
+    }
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let name = file.name().to_owned();
+        // the zip reader checks the CRC once a file is read to the end
+        std::io::copy(&mut file, &mut std::io::sink()).with_context(|| format!("{name} is damaged"))?;
+    }
+    Ok(())
+}
@@ -1,9 +1,30 @@
 use anyhow::{bail, Result};
-use std::{path::PathBuf, sync::OnceLock};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
 
 pub static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
 pub static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
 
+/// Scratch files and folders of the previews shown in the app, each kept until
+/// the next preview of its kind replaces it or the app exits.
+#[derive(Default)]
+pub struct Previews(Mutex<HashMap<&'static str, Box<dyn Send>>>);
+
+impl Previews {
+    /// Keeps `scratch` (a `TempPath` or `TempDir`) alive, deleting the previous
+    /// preview of `kind`.
+    pub fn replace(&self, kind: &'static str, scratch: impl Send + 'static) {
+        self.0.lock().unwrap().insert(kind, Box::new(scratch));
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
 pub fn ensure_dir(path: PathBuf) -> PathBuf {
     if path.exists() {
         if !path.is_dir() {
@@ -50,3 +71,117 @@ pub fn respack_dir() -> Result<PathBuf> {
     }
     Ok(dir)
 }
+
+/// Drops the current process to below-normal priority. Child processes (ffmpeg)
+/// inherit it. On Linux this applies to the calling thread, which is the one
+/// rendering and spawning ffmpeg.
+pub fn lower_priority() -> Result<()> {
+    #[cfg(unix)]
+    unsafe {
+        if libc::setpriority(libc::PRIO_PROCESS, 0, 10) != 0 {
+            bail!("setpriority failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "windows")]
+    unsafe {
+        use winapi::um::{
+            processthreadsapi::GetCurrentProcess,
+            winbase::{SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS},
+        };
+        if SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS) == 0 {
+            bail!("SetPriorityClass failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Restricts the current process (and the children it spawns afterwards) to the given CPU cores.
+pub fn pin_to_cores(cores: &[usize]) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &core in cores {
+            libc::CPU_SET(core, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            bail!("sched_setaffinity failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "windows")]
+    unsafe {
+        use winapi::um::{processthreadsapi::GetCurrentProcess, winbase::SetProcessAffinityMask};
+        let mask = cores
+            .iter()
+            .filter(|&&core| core < usize::BITS as usize)
+            .fold(0usize, |mask, &core| mask | 1 << core);
+        if SetProcessAffinityMask(GetCurrentProcess(), mask) == 0 {
+            bail!("SetProcessAffinityMask failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    if cfg!(not(any(target_os = "linux", target_os = "windows"))) {
+        bail!("CPU affinity is not supported on this platform ({} cores requested)", cores.len());
+    }
+    Ok(())
+}
+
+/// Keeps the machine from going to sleep (the display may still turn off) while
+/// it is alive, or until the process exits, whichever comes first.
+pub struct KeepAwake {
+    #[cfg(not(target_os = "windows"))]
+    inhibitor: std::process::Child,
+}
+
+impl KeepAwake {
+    pub fn acquire() -> Result<Self> {
+        #[cfg(target_os = "windows")]
+        unsafe {
+            use winapi::um::{
+                winbase::SetThreadExecutionState,
+                winnt::{ES_CONTINUOUS, ES_SYSTEM_REQUIRED},
+            };
+            // tied to this thread, and dropped by Windows when the process exits
+            if SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) == 0 {
+                bail!("SetThreadExecutionState failed");
+            }
+            Ok(Self {})
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let pid = std::process::id().to_string();
+            // both helpers exit on their own once this process is gone, killed or not
+            let mut command = if cfg!(target_os = "macos") {
+                let mut command = std::process::Command::new("caffeinate");
+                command.args(["-i", "-w", &pid]);
+                command
+            } else {
+                let mut command = std::process::Command::new("systemd-inhibit");
+                command
+                    .args(["--what=idle:sleep", "--who=Phi Recorder", "--why=Rendering", "tail", "--pid"])
+                    .args([&pid, "-f", "/dev/null"]);
+                command
+            };
+            let inhibitor = command
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .map_err(|err| anyhow::anyhow!("failed to run {:?}: {err}", command.get_program()))?;
+            Ok(Self { inhibitor })
+        }
+    }
+}
+
+impl Drop for KeepAwake {
+    fn drop(&mut self) {
+        #[cfg(target_os = "windows")]
+        unsafe {
+            use winapi::um::{winbase::SetThreadExecutionState, winnt::ES_CONTINUOUS};
+            SetThreadExecutionState(ES_CONTINUOUS);
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let _ = self.inhibitor.kill();
+            let _ = self.inhibitor.wait();
+        }
+    }
+}
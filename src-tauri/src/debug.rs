@@ -0,0 +1,96 @@
+use prpr::core::{Anim, Chart, NoteKind};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Upcoming notes listed per line when [`DebugOverlay::note_ids`] is on.
+const NOTES_PER_LINE: usize = 3;
+/// How far ahead notes are listed, in seconds of chart time.
+const NOTE_LOOKAHEAD: f32 = 1.;
+
+/// What the recorder writes next to every judge line, on top of what prpr draws
+/// for `chartDebug`.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DebugOverlay {
+    /// Index of the line in the chart, as in `onlyLines` and `hiddenLines`
+    pub line_indices: bool,
+    /// `#line.note` and time of the notes about to be hit on the line
+    pub note_ids: bool,
+    /// Position, rotation, alpha and scroll height of the line
+    pub event_values: bool,
+    /// Chart time and beat
+    pub time: bool,
+}
+
+impl DebugOverlay {
+    pub fn is_enabled(&self) -> bool {
+        self.line_indices || self.note_ids || self.event_values || self.time
+    }
+
+    /// Labels for the lines of `chart` at chart time `time`, each with where its
+    /// line is as a fraction of the chart area (x right, y down).
+    pub fn labels(&self, chart: &Chart, time: f32) -> Vec<((f32, f32), Vec<String>)> {
+        let beat = self.time.then(|| chart.bpm_list.borrow_mut().beat(time));
+        chart
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let object = &line.object;
+                let now = |anim: &Anim<f32>| {
+                    let mut anim = anim.clone();
+                    anim.set_time(time);
+                    anim.now()
+                };
+                let (x, y) = (now(&object.translation.0), now(&object.translation.1));
+                // children only follow their parent's position here, not its rotation
+                let (px, py) = line
+                    .parent
+                    .and_then(|parent| chart.lines.get(parent))
+                    .map_or((0., 0.), |parent| {
+                        (now(&parent.object.translation.0), now(&parent.object.translation.1))
+                    });
+                let mut text = Vec::new();
+                if self.line_indices {
+                    text.push(match line.parent {
+                        Some(parent) => format!("L{index} (parent L{parent})"),
+                        None => format!("L{index}"),
+                    });
+                }
+                if self.event_values {
+                    text.push(format!("pos {:.3}, {:.3}", x + px, y + py));
+                    text.push(format!(
+                        "rot {:.1}° alpha {:.2} height {:.2}",
+                        now(&object.rotation),
+                        now(&object.alpha),
+                        now(&line.height)
+                    ));
+                }
+                if self.note_ids {
+                    let upcoming: Vec<String> = line
+                        .notes
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, note)| {
+                            let end = match note.kind {
+                                NoteKind::Hold { end_time, .. } => end_time,
+                                _ => note.time,
+                            };
+                            end >= time && note.time <= time + NOTE_LOOKAHEAD
+                        })
+                        .take(NOTES_PER_LINE)
+                        .map(|(id, note)| format!("#{index}.{id}@{:.2}", note.time))
+                        .collect();
+                    if !upcoming.is_empty() {
+                        text.push(upcoming.join(" "));
+                    }
+                }
+                if let Some(beat) = beat {
+                    text.push(format!("t {time:.3}s beat {beat:.2}"));
+                }
+                (((x + px + 1.) / 2., (1. - (y + py)) / 2.), text)
+            })
+            .filter(|(_, text)| !text.is_empty())
+            .collect()
+    }
+}
@@ -0,0 +1,469 @@
+//! Render farm: a coordinator hands queued jobs out to workers on other
+//! machines, which render them in their own queue and upload the videos back.
+//!
+//! Workers connect over TCP and speak one JSON message per line. A message
+//! announcing a file (`Job`, `Upload`) is followed by exactly `size` raw bytes.
+//!
+//! Both sides hold the same token and prove it on connecting: each sends a
+//! random nonce, which the other answers with `HMAC-SHA256(token, role \n nonce)`.
+//! The coordinator listens on localhost unless told otherwise.
+//!
+//! Only the chart travels with a job. Workers run jobs through
+//! [`agent::confine`], so jobs that need other files (subtitles, overlay images)
+//! are refused, resource packs must be the worker's own, and bitrates and
+//! encoders must be ones ffmpeg can take as they are.
+use crate::{
+    agent,
+    common::{ensure_dir, output_dir, DATA_DIR},
+    render::RenderParams,
+    task::{TaskQueue, TaskStatus},
+};
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    io::Write,
+    net::{IpAddr, Ipv4Addr},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::Mutex,
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+/// Bumped whenever the messages below change incompatibly.
+pub const FARM_PROTOCOL_VERSION: u32 = 2;
+/// How long an idle worker waits before asking for work again.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest message line accepted, which keeps a peer from filling memory
+/// without ever sending a newline.
+const MAX_LINE: u64 = 1 << 20;
+/// Largest chart a worker accepts.
+const MAX_CHART: u64 = 1 << 30;
+/// Largest video the coordinator accepts.
+const MAX_VIDEO: u64 = 16 << 30;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum FromWorker {
+    Register { name: String, protocol_version: u32, nonce: String },
+    /// Answers `Challenge`
+    Prove { signature: String },
+    Pull,
+    /// Followed by the video
+    Upload { job: String, name: String, size: u64 },
+    Failed { job: String, error: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum FromCoordinator {
+    /// Answers `Register`, `signature` proving the coordinator holds the token
+    Challenge { nonce: String, signature: String },
+    Welcome,
+    Rejected { reason: String },
+    Idle,
+    /// Followed by the chart, `chart` being its file name
+    Job { job: String, params: Value, chart: String, size: u64 },
+    Received,
+}
+
+pub(crate) async fn send<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), message: &T) -> Result<()> {
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(message)?).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub(crate) async fn recv<T: DeserializeOwned>(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<T> {
+    let mut line = String::new();
+    if reader.take(MAX_LINE).read_line(&mut line).await? == 0 {
+        bail!("connection closed");
+    }
+    if !line.ends_with('\n') && line.len() as u64 >= MAX_LINE {
+        bail!("message longer than {} KiB", MAX_LINE >> 10);
+    }
+    serde_json::from_str(line.trim()).context("invalid message")
+}
+
+fn new_nonce() -> String {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    hex::encode(nonce)
+}
+
+/// Answers the other side's `nonce`, proving that `role` holds `token`. The
+/// role is signed along so that one side's answer is no use to the other.
+fn prove(token: &str, role: &str, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(role.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+fn sign(token: &str, role: &str, nonce: &str) -> String {
+    hex::encode(prove(token, role, nonce).finalize().into_bytes())
+}
+
+fn verify(token: &str, role: &str, nonce: &str, signature: &str) -> bool {
+    prove(token, role, nonce)
+        .verify_slice(&hex::decode(signature).unwrap_or_default())
+        .is_ok()
+}
+
+/// Copies the `size` bytes following a message into a new file at `path`.
+pub(crate) async fn recv_file(reader: &mut (impl AsyncBufRead + Unpin), size: u64, path: &Path) -> Result<()> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let copied = tokio::io::copy(&mut reader.take(size), &mut file).await?;
+    if copied != size {
+        bail!("connection closed after {copied} of {size} bytes");
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// The chart as a single file: charts given as a folder are zipped up.
+pub(crate) fn pack(path: &Path) -> Result<(String, Vec<u8>)> {
+    if !path.is_dir() {
+        let name = path.file_name().context("chart has no file name")?.to_string_lossy().into_owned();
+        return Ok((name, std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?));
+    }
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default();
+    let mut stack = vec![path.to_owned()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?.path();
+            if entry.is_dir() {
+                stack.push(entry);
+                continue;
+            }
+            let name = entry.strip_prefix(path)?.to_string_lossy().replace('\\', "/");
+            zip.start_file(name, options)?;
+            zip.write_all(&std::fs::read(&entry)?)?;
+        }
+    }
+    Ok(("chart.zip".to_owned(), zip.finish()?.into_inner()))
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum FarmStatus {
+    Queued,
+    Rendering { worker: String },
+    Done { worker: String, output: PathBuf },
+    Failed { worker: String, error: String },
+}
+
+struct FarmJob {
+    id: String,
+    name: String,
+    params: RenderParams,
+    status: FarmStatus,
+}
+
+#[derive(Serialize)]
+pub struct FarmJobView {
+    id: String,
+    name: String,
+    status: FarmStatus,
+}
+
+type Jobs = Arc<Mutex<Vec<FarmJob>>>;
+
+async fn set_status(jobs: &Jobs, id: &str, status: FarmStatus) {
+    if let Some(job) = jobs.lock().await.iter_mut().find(|it| it.id == id) {
+        job.status = status;
+    }
+}
+
+/// Talks to one worker until it disconnects, putting the job it held (if any)
+/// back in the queue.
+async fn serve(jobs: Jobs, token: &str, stream: TcpStream) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let FromWorker::Register { name: worker, protocol_version, nonce } = recv(&mut read).await? else {
+        bail!("worker did not register");
+    };
+    if protocol_version != FARM_PROTOCOL_VERSION {
+        let reason = format!("worker speaks protocol v{protocol_version}, expected v{FARM_PROTOCOL_VERSION}");
+        send(&mut write, &FromCoordinator::Rejected { reason: reason.clone() }).await?;
+        bail!(reason);
+    }
+    let challenge = new_nonce();
+    let signature = sign(token, "coordinator", &nonce);
+    send(&mut write, &FromCoordinator::Challenge { nonce: challenge.clone(), signature }).await?;
+    let FromWorker::Prove { signature } = recv(&mut read).await? else {
+        bail!("worker {worker} did not answer the challenge");
+    };
+    if !verify(token, "worker", &challenge, &signature) {
+        send(&mut write, &FromCoordinator::Rejected { reason: "unauthorized".to_owned() }).await?;
+        bail!("worker {worker} does not hold the farm's token");
+    }
+    send(&mut write, &FromCoordinator::Welcome).await?;
+    info!("Worker {worker} registered");
+
+    let mut current = None;
+    let result = serve_jobs(&jobs, &worker, &mut read, &mut write, &mut current).await;
+    if let Some(id) = current {
+        warn!("Worker {worker} left during job {id}, requeueing");
+        set_status(&jobs, &id, FarmStatus::Queued).await;
+    }
+    result
+}
+
+async fn serve_jobs(
+    jobs: &Jobs,
+    worker: &str,
+    read: &mut BufReader<OwnedReadHalf>,
+    write: &mut OwnedWriteHalf,
+    current: &mut Option<String>,
+) -> Result<()> {
+    loop {
+        match recv(read).await? {
+            FromWorker::Pull => {
+                let next = {
+                    let mut guard = jobs.lock().await;
+                    guard.iter_mut().find(|it| matches!(it.status, FarmStatus::Queued)).map(|job| {
+                        job.status = FarmStatus::Rendering { worker: worker.to_owned() };
+                        (job.id.clone(), job.params.path.clone(), serde_json::to_value(&job.params))
+                    })
+                };
+                let Some((id, path, params)) = next else {
+                    send(write, &FromCoordinator::Idle).await?;
+                    continue;
+                };
+                *current = Some(id.clone());
+                let packed = params.map_err(anyhow::Error::from).and_then(|params| Ok((params, pack(&path)?)));
+                let (params, (chart, bytes)) = match packed {
+                    Ok(packed) => packed,
+                    Err(err) => {
+                        set_status(jobs, &id, FarmStatus::Failed { worker: worker.to_owned(), error: format!("{err:?}") }).await;
+                        *current = None;
+                        send(write, &FromCoordinator::Idle).await?;
+                        continue;
+                    }
+                };
+                let size = bytes.len() as u64;
+                if size > MAX_CHART {
+                    let error = format!("chart is larger than the {} MiB a worker accepts", MAX_CHART >> 20);
+                    set_status(jobs, &id, FarmStatus::Failed { worker: worker.to_owned(), error }).await;
+                    *current = None;
+                    send(write, &FromCoordinator::Idle).await?;
+                    continue;
+                }
+                send(write, &FromCoordinator::Job { job: id, params, chart, size }).await?;
+                write.write_all(&bytes).await?;
+                write.flush().await?;
+            }
+            FromWorker::Upload { job, name, size } => {
+                // the name comes from the worker's output template, which may
+                // sort videos into subfolders but never out of the output folder
+                if !Path::new(&name).components().all(|it| matches!(it, Component::Normal(_))) {
+                    bail!("worker uploaded to {name}, outside the output folder");
+                }
+                if size > MAX_VIDEO {
+                    bail!("worker uploaded {size} bytes, more than the {} MiB allowed", MAX_VIDEO >> 20);
+                }
+                let output = output_dir()?.join(&name);
+                if let Some(parent) = output.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                recv_file(read, size, &output).await?;
+                info!("Job {job} rendered by {worker}: {}", output.display());
+                set_status(jobs, &job, FarmStatus::Done { worker: worker.to_owned(), output }).await;
+                *current = None;
+                send(write, &FromCoordinator::Received).await?;
+            }
+            FromWorker::Failed { job, error } => {
+                warn!("Job {job} failed on {worker}: {error}");
+                set_status(jobs, &job, FarmStatus::Failed { worker: worker.to_owned(), error }).await;
+                *current = None;
+            }
+            FromWorker::Register { .. } | FromWorker::Prove { .. } => bail!("worker registered twice"),
+        }
+    }
+}
+
+/// The coordinator side, kept as app state.
+#[derive(Default)]
+pub struct Farm {
+    jobs: Jobs,
+    server: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Farm {
+    /// Starts accepting workers that hold `token` on `port` of `host` (localhost
+    /// if not given), replacing any earlier listener.
+    pub async fn start(&self, host: Option<IpAddr>, port: u16, token: String) -> Result<()> {
+        if token.is_empty() {
+            bail!("a render farm needs a token shared with its workers");
+        }
+        let host = host.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let listener = TcpListener::bind((host, port))
+            .await
+            .with_context(|| format!("failed to listen on {host}:{port}"))?;
+        let jobs = Arc::clone(&self.jobs);
+        let token = Arc::new(token);
+        let server = tokio::spawn(async move {
+            loop {
+                let Ok((stream, address)) = listener.accept().await else { continue };
+                let (jobs, token) = (Arc::clone(&jobs), Arc::clone(&token));
+                tokio::spawn(async move {
+                    if let Err(err) = serve(jobs, &token, stream).await {
+                        warn!("Worker at {address} disconnected: {err:?}");
+                    }
+                });
+            }
+        });
+        if let Some(old) = self.server.lock().await.replace(server) {
+            old.abort();
+        }
+        Ok(())
+    }
+
+    pub async fn submit(&self, mut params: RenderParams) {
+        // workers name their files after the id, so it is always a UUID
+        let id = uuid::Uuid::new_v4().to_string();
+        params.job_id = Some(id.clone());
+        self.jobs.lock().await.push(FarmJob {
+            id,
+            name: params.info.name.clone(),
+            params,
+            status: FarmStatus::Queued,
+        });
+    }
+
+    pub async fn jobs(&self) -> Vec<FarmJobView> {
+        self.jobs
+            .lock()
+            .await
+            .iter()
+            .map(|job| FarmJobView {
+                id: job.id.clone(),
+                name: job.name.clone(),
+                status: job.status.clone(),
+            })
+            .collect()
+    }
+}
+
+/// A connection to a coordinator, registered and ready to take jobs.
+pub struct Worker {
+    read: BufReader<OwnedReadHalf>,
+    write: OwnedWriteHalf,
+}
+
+impl Worker {
+    pub async fn connect(address: &str, name: &str, token: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("failed to connect to {address}"))?;
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+        let nonce = new_nonce();
+        let register = FromWorker::Register {
+            name: name.to_owned(),
+            protocol_version: FARM_PROTOCOL_VERSION,
+            nonce: nonce.clone(),
+        };
+        send(&mut write, &register).await?;
+        let challenge = match recv(&mut read).await? {
+            FromCoordinator::Challenge { nonce: challenge, signature } => {
+                if !verify(token, "coordinator", &nonce, &signature) {
+                    bail!("coordinator does not hold the same token");
+                }
+                challenge
+            }
+            FromCoordinator::Rejected { reason } => bail!("coordinator rejected this worker: {reason}"),
+            _ => bail!("unexpected reply to registration"),
+        };
+        send(&mut write, &FromWorker::Prove { signature: sign(token, "worker", &challenge) }).await?;
+        match recv(&mut read).await? {
+            FromCoordinator::Welcome => Ok(Self { read, write }),
+            FromCoordinator::Rejected { reason } => bail!("coordinator rejected this worker: {reason}"),
+            _ => bail!("unexpected reply to registration"),
+        }
+    }
+
+    /// Renders jobs through `queue` until the connection drops.
+    pub async fn run(mut self, queue: &TaskQueue) -> Result<()> {
+        let dir = ensure_dir(DATA_DIR.get().unwrap().join("farm"));
+        loop {
+            send(&mut self.write, &FromWorker::Pull).await?;
+            let FromCoordinator::Job { job, params, chart, size } = recv(&mut self.read).await? else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            };
+            // the id and the extension make up a file name on this machine
+            let job = uuid::Uuid::parse_str(&job)
+                .with_context(|| format!("job id {job:?} is not a UUID"))?
+                .to_string();
+            let extension = Path::new(&chart)
+                .extension()
+                .map(|it| it.to_string_lossy().into_owned())
+                .filter(|it| it.chars().all(|c| c.is_ascii_alphanumeric()))
+                .unwrap_or_default();
+            if size > MAX_CHART {
+                bail!("coordinator sent a chart of {size} bytes, more than the {} MiB allowed", MAX_CHART >> 20);
+            }
+            let path = dir.join(format!("{job}.{extension}"));
+            recv_file(&mut self.read, size, &path).await?;
+            let params = serde_json::from_value::<RenderParams>(params)
+                .map_err(anyhow::Error::from)
+                .and_then(|mut params| agent::confine(&mut params, &dir).map(|()| params));
+            let posted = match params {
+                Ok(mut params) => {
+                    params.path = path.clone();
+                    queue.post(params).await
+                }
+                Err(err) => Err(err),
+            };
+
+            let result = match posted {
+                Ok(id) => queue.wait(id).await,
+                Err(err) => (TaskStatus::Failed { error: format!("{err:?}"), code: None, stage: None }, PathBuf::new()),
+            };
+            let _ = std::fs::remove_file(&path);
+            match result {
+                (TaskStatus::Done { .. }, output) => self.upload(job, &output).await?,
+                (TaskStatus::Failed { error, .. }, _) => send(&mut self.write, &FromWorker::Failed { job, error }).await?,
+                _ => {
+                    let error = "canceled on the worker".to_owned();
+                    send(&mut self.write, &FromWorker::Failed { job, error }).await?;
+                }
+            }
+        }
+    }
+
+    async fn upload(&mut self, job: String, output: &Path) -> Result<()> {
+        let root = output_dir()?;
+        let name = output.strip_prefix(&root).unwrap_or(output).to_string_lossy().replace('\\', "/");
+        let mut file = tokio::fs::File::open(output)
+            .await
+            .with_context(|| format!("failed to open {}", output.display()))?;
+        let size = file.metadata().await?.len();
+        send(&mut self.write, &FromWorker::Upload { job, name, size }).await?;
+        tokio::io::copy(&mut file, &mut self.write).await?;
+        self.write.flush().await?;
+        match recv(&mut self.read).await? {
+            FromCoordinator::Received => Ok(()),
+            _ => bail!("coordinator did not confirm the upload"),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{manifest::ffmpeg_version, render::find_ffmpeg};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use minisign_verify::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+use tauri::api::http::{ClientBuilder, HttpRequestBuilder, ResponseType};
+use tracing::{info, warn};
+
+/// Lists the ffmpeg builds the recorder can download, newest first, and is
+/// signed in a `.sig` next to it.
+const INDEX_URL: &str = "https://github.com/2278535805/Phigros-Recorder/releases/download/ffmpeg/index.json";
+/// Replaces [`INDEX_URL`], e.g. for a mirror.
+const INDEX_ENV: &str = "PHI_FFMPEG_INDEX";
+/// The app's identifier, as in `tauri.conf.json`, naming its data folder.
+const APP_IDENTIFIER: &str = "com.hlmc.phi.recorder";
+
+/// Key the index is signed with: the base64 of a minisign public key file, as
+/// `tauri signer generate` prints it. A compromised index or mirror can't hand
+/// out other builds, and a build without the key can't download any.
+const INDEX_PUBLIC_KEY: Option<&str> = option_env!("PHI_FFMPEG_INDEX_PUBKEY");
+
+/// A build of ffmpeg as listed in the index.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Release {
+    pub version: String,
+    /// Zip archive with the ffmpeg binary somewhere inside
+    pub url: String,
+    /// Of the archive, trusted as far as the index signature goes
+    pub sha256: String,
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    }
+}
+
+/// Where downloaded builds live, in the app's data folder: one folder per
+/// version, and a `pinned` file naming the one to use. Found from the system
+/// rather than `DATA_DIR`, which subprocesses don't have.
+fn managed_dir() -> Result<PathBuf> {
+    let data = tauri::api::path::data_dir().context("this system has no data folder")?;
+    Ok(data.join(APP_IDENTIFIER).join("ffmpeg-managed"))
+}
+
+/// The bundled ffmpeg, next to the executable.
+pub fn bundled() -> Result<PathBuf> {
+    Ok(std::env::current_exe()?.parent().unwrap().join(binary_name()))
+}
+
+pub fn managed(version: &str) -> Result<PathBuf> {
+    // names a folder, and comes from the index or the `pinned` file
+    let valid = version.chars().all(|it| it.is_ascii_alphanumeric() || "._-+".contains(it));
+    if !valid || matches!(version, "" | "." | "..") {
+        bail!("invalid ffmpeg version {version:?}");
+    }
+    Ok(managed_dir()?.join(version).join(binary_name()))
+}
+
+/// Checks `index` against `signature`, the base64 of a minisign signature file
+/// as `tauri signer sign` writes it.
+fn verify_index(index: &[u8], signature: &[u8]) -> Result<()> {
+    let key = INDEX_PUBLIC_KEY.context("this build has no key to check the ffmpeg index with")?;
+    let decode = |base64: &[u8]| -> Result<String> {
+        Ok(String::from_utf8(STANDARD.decode(String::from_utf8_lossy(base64).trim())?)?)
+    };
+    let key = PublicKey::decode(&decode(key.as_bytes())?).context("invalid ffmpeg index key")?;
+    let signature = Signature::decode(&decode(signature)?).context("invalid ffmpeg index signature")?;
+    key.verify(index, &signature, false)
+        .context("ffmpeg index signature does not match")?;
+    Ok(())
+}
+
+/// Versions that have been downloaded.
+pub fn installed() -> Result<Vec<String>> {
+    let Ok(entries) = std::fs::read_dir(managed_dir()?) else {
+        return Ok(Vec::new());
+    };
+    let mut versions = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.path().join(binary_name()).is_file() {
+            versions.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    versions.sort();
+    Ok(versions)
+}
+
+pub fn pinned() -> Option<String> {
+    let version = std::fs::read_to_string(managed_dir().ok()?.join("pinned")).ok()?;
+    Some(version.trim().to_owned()).filter(|it| !it.is_empty())
+}
+
+/// Makes every render use the downloaded `version`, or lifts the pin.
+pub fn pin(version: Option<&str>) -> Result<()> {
+    let file = managed_dir()?.join("pinned");
+    match version {
+        Some(version) => {
+            if !managed(version)?.is_file() {
+                bail!("ffmpeg {version} is not installed");
+            }
+            std::fs::write(&file, version).with_context(|| format!("failed to write {}", file.display()))?;
+        }
+        None => {
+            if file.exists() {
+                std::fs::remove_file(&file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn download(url: &str) -> Result<Vec<u8>> {
+    let client = ClientBuilder::new().build()?;
+    let response = client
+        .send(HttpRequestBuilder::new("GET", url)?.response_type(ResponseType::Binary))
+        .await?
+        .bytes()
+        .await?;
+    if !(200..300).contains(&response.status) {
+        bail!("GET {url} returned {}", response.status);
+    }
+    Ok(response.data)
+}
+
+/// Builds available for this platform, newest first.
+pub async fn releases() -> Result<Vec<Release>> {
+    let url = std::env::var(INDEX_ENV).unwrap_or_else(|_| INDEX_URL.to_owned());
+    let index = download(&url).await?;
+    verify_index(&index, &download(&format!("{url}.sig")).await?)?;
+    // builds by `std::env::consts::OS`
+    let mut index: HashMap<String, Vec<Release>> = serde_json::from_slice(&index).context("invalid ffmpeg index")?;
+    Ok(index.remove(std::env::consts::OS).unwrap_or_default())
+}
+
+/// Downloads `release`, as listed in the signed index, checks it against its
+/// hash and unpacks its ffmpeg binary.
+pub async fn install(release: &Release) -> Result<PathBuf> {
+    let target = managed(&release.version)?;
+    info!("Downloading ffmpeg {} from {}", release.version, release.url);
+    let archive = download(&release.url).await?;
+    let hash = hex::encode(Sha256::digest(&archive));
+    if !hash.eq_ignore_ascii_case(&release.sha256) {
+        bail!("ffmpeg {} download is corrupted (sha256 {hash})", release.version);
+    }
+    let version = release.version.clone();
+    let path = target.clone();
+    tokio::task::spawn_blocking(move || extract(&archive, &path))
+        .await?
+        .with_context(|| format!("failed to unpack ffmpeg {version}"))?;
+    Ok(target)
+}
+
+fn extract(archive: &[u8], target: &Path) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))?;
+    let name = zip
+        .file_names()
+        .find(|it| it.rsplit('/').next() == Some(binary_name()))
+        .context("no ffmpeg binary in the archive")?
+        .to_owned();
+    let mut bytes = Vec::new();
+    zip.by_name(&name)?.read_to_end(&mut bytes)?;
+    std::fs::create_dir_all(target.parent().unwrap())?;
+    std::fs::write(target, bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(target, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Which ffmpeg renders go to, and what else there is.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub path: Option<String>,
+    /// First line of `ffmpeg -version`
+    pub version: Option<String>,
+    pub pinned: Option<String>,
+    pub installed: Vec<String>,
+    /// Newest build in the index, if it could be reached
+    pub latest: Option<Release>,
+}
+
+/// Looks up the ffmpeg in use, and with `check` the newest build in the index.
+pub async fn status(check: bool) -> Result<Status> {
+    let path = tokio::task::spawn_blocking(find_ffmpeg).await??;
+    let version = path.as_deref().map(ffmpeg_version);
+    let latest = if check {
+        match releases().await {
+            Ok(releases) => releases.into_iter().next(),
+            Err(err) => {
+                warn!("Failed to check for ffmpeg updates: {err:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    Ok(Status {
+        path,
+        version,
+        pinned: pinned(),
+        installed: installed()?,
+        latest,
+    })
+}
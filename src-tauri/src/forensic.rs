@@ -0,0 +1,143 @@
+use crate::render::{cmd_hidden, require_ffmpeg};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Read,
+    path::PathBuf,
+    process::Stdio,
+};
+
+/// Cells the frame is split into, each nudged slightly brighter or darker.
+///
+/// Large cells keep the pattern in the lowest frequencies, which is what
+/// survives lossy encoding and rescaling.
+pub const GRID: (usize, usize) = (32, 18);
+/// Pixels per cell in the frames decoded for detection.
+const DETECT_CELL: usize = 8;
+/// Frames per second sampled for detection.
+const DETECT_FPS: u32 = 2;
+/// Unrelated patterns the key's correlation is compared against, so that the
+/// verdict doesn't depend on how busy the picture is.
+const DECOYS: usize = 256;
+/// Standard deviations above the decoys at which the key counts as found.
+const DETECT_THRESHOLD: f64 = 6.;
+
+/// 64-bit FNV-1a, chosen over `DefaultHasher` for being stable across builds.
+fn hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Sign of every cell (row-major, `true` meaning brighter) for `key`.
+pub fn pattern(key: &str) -> Vec<bool> {
+    // splitmix64, for the same reason as `hash`
+    let mut state = hash(key);
+    (0..GRID.0 * GRID.1)
+        .map(|_| {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            (z ^ (z >> 31)) & 1 == 1
+        })
+        .collect()
+}
+
+/// Each cell minus the mean of its neighbours, which removes most of the
+/// picture's own gradients and keeps the cell-to-cell pattern.
+fn high_pass(values: &[f64]) -> Vec<f64> {
+    let (w, h) = GRID;
+    (0..w * h)
+        .map(|i| {
+            let (x, y) = (i % w, i / w);
+            let neighbours: Vec<f64> = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .into_iter()
+                .filter_map(|(dx, dy)| {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    (nx >= 0 && ny >= 0 && (nx as usize) < w && (ny as usize) < h)
+                        .then(|| values[ny as usize * w + nx as usize])
+                })
+                .collect();
+            values[i] - neighbours.iter().sum::<f64>() / neighbours.len() as f64
+        })
+        .collect()
+}
+
+/// Printed by the `detect` subcommand.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Detection {
+    pub frames: usize,
+    /// Normalized correlation between the video and the key's pattern, -1 to 1
+    pub correlation: f64,
+    /// How far `correlation` stands out from that of random patterns, in standard deviations
+    pub score: f64,
+    pub detected: bool,
+}
+
+/// `detect <asset path> <video path> <key>`: checks whether a video carries the
+/// forensic watermark of `key`, printing a [`Detection`] as one JSON line.
+pub async fn main() -> Result<()> {
+    let video: PathBuf = std::env::args().nth(3).context("missing video path")?.into();
+    let key = std::env::args().nth(4).context("missing key")?;
+    let ffmpeg = require_ffmpeg()?;
+
+    let (w, h) = (GRID.0 * DETECT_CELL, GRID.1 * DETECT_CELL);
+    let mut proc = cmd_hidden(&ffmpeg)
+        .arg("-i")
+        .arg(&video)
+        .args(
+            format!("-vf fps={DETECT_FPS},scale={w}:{h}:flags=area -f rawvideo -pix_fmt gray -loglevel error -")
+                .split_whitespace(),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run ffmpeg")?;
+    let mut stdout = proc.stdout.take().unwrap();
+
+    let mut sum = vec![0.; GRID.0 * GRID.1];
+    let mut frames = 0;
+    let mut frame = vec![0u8; w * h];
+    while stdout.read_exact(&mut frame).is_ok() {
+        let mut means = vec![0.; GRID.0 * GRID.1];
+        for (i, &luma) in frame.iter().enumerate() {
+            let (x, y) = (i % w / DETECT_CELL, i / w / DETECT_CELL);
+            means[y * GRID.0 + x] += luma as f64;
+        }
+        for (sum, value) in sum.iter_mut().zip(high_pass(&means)) {
+            *sum += value;
+        }
+        frames += 1;
+    }
+    proc.wait()?;
+    if frames == 0 {
+        bail!("no frames decoded from {}", video.display());
+    }
+
+    let correlate = |key: &str| {
+        let expected = high_pass(
+            &pattern(key)
+                .into_iter()
+                .map(|bright| if bright { 1. } else { -1. })
+                .collect::<Vec<_>>(),
+        );
+        let dot: f64 = sum.iter().zip(&expected).map(|(x, y)| x * y).sum();
+        let norm = (sum.iter().map(|it| it * it).sum::<f64>() * expected.iter().map(|it| it * it).sum::<f64>()).sqrt();
+        if norm > 0. { dot / norm } else { 0. }
+    };
+    let correlation = correlate(&key);
+    let decoys: Vec<f64> = (0..DECOYS).map(|i| correlate(&format!("{key}\0decoy{i}"))).collect();
+    let mean = decoys.iter().sum::<f64>() / DECOYS as f64;
+    let deviation = (decoys.iter().map(|it| (it - mean).powi(2)).sum::<f64>() / DECOYS as f64).sqrt();
+    let score = (correlation - mean) / deviation.max(1e-9);
+    let detection = Detection {
+        frames,
+        correlation,
+        score,
+        detected: score > DETECT_THRESHOLD,
+    };
+    println!("{}", serde_json::to_string(&detection)?);
+    Ok(())
+}
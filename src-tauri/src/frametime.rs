@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Frames the rolling average settles over before spikes are looked for.
+const WARMUP: usize = 30;
+/// Weight of a new frame in the rolling average, about a second's worth at 60fps.
+const SMOOTHING: f64 = 1. / 60.;
+/// How many times the rolling average a frame must take to count as a spike.
+const SPIKE_FACTOR: f64 = 3.;
+/// Extra time below which a slow frame isn't worth reporting, in milliseconds.
+const SPIKE_MIN: f64 = 10.;
+
+/// Time one frame took, by what it was spent on.
+#[derive(Clone, Copy, Default)]
+pub struct FrameTime {
+    /// Updating and drawing the scene and the overlay
+    pub simulate: Duration,
+    /// Starting the copy of the frame off the GPU
+    pub readback: Duration,
+    /// Handing finished frames to ffmpeg, waiting on it if its queue is full
+    pub write: Duration,
+}
+
+impl FrameTime {
+    pub fn total(&self) -> Duration {
+        self.simulate + self.readback + self.write
+    }
+}
+
+/// Summary of the frame times of a render, in milliseconds.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameStats {
+    pub frames: u64,
+    pub mean: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub worst: f64,
+    /// Index of the slowest frame in the video
+    pub worst_frame: u64,
+    /// Means of each part of [`FrameTime`]
+    pub simulate: f64,
+    pub readback: f64,
+    pub write: f64,
+    /// Frames that took far longer than the ones around them
+    #[serde(default)]
+    pub spikes: u64,
+}
+
+/// Frame times collected over a render.
+#[derive(Default)]
+pub struct FrameTimes {
+    frames: Vec<(u64, FrameTime)>,
+    /// Rolling average of the frame time, in milliseconds
+    average: f64,
+    spikes: u64,
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+impl FrameTimes {
+    /// Records a frame, returning the rolling average before it (in
+    /// milliseconds) if it stalled: usually a shader compiling or a texture
+    /// being uploaded.
+    pub fn push(&mut self, frame: u64, time: FrameTime) -> Option<f64> {
+        let total = ms(time.total());
+        let average = self.average;
+        let spike = self.frames.len() >= WARMUP && total > average * SPIKE_FACTOR && total - average > SPIKE_MIN;
+        self.frames.push((frame, time));
+        self.average = if self.frames.len() == 1 {
+            total
+        } else {
+            average + (total - average) * SMOOTHING
+        };
+        if spike {
+            self.spikes += 1;
+        }
+        spike.then_some(average)
+    }
+
+    /// `None` if no frame was rendered.
+    pub fn stats(&self) -> Option<FrameStats> {
+        let count = self.frames.len();
+        let &(worst_frame, worst) = self.frames.iter().max_by_key(|(_, time)| time.total())?;
+        let mut totals: Vec<f64> = self.frames.iter().map(|(_, time)| ms(time.total())).collect();
+        totals.sort_by(|x, y| x.total_cmp(y));
+        let percentile = |p: f64| totals[((count as f64 * p).ceil() as usize).clamp(1, count) - 1];
+        let mean = |part: fn(&FrameTime) -> Duration| {
+            self.frames.iter().map(|(_, time)| ms(part(time))).sum::<f64>() / count as f64
+        };
+        Some(FrameStats {
+            frames: count as u64,
+            mean: totals.iter().sum::<f64>() / count as f64,
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            worst: ms(worst.total()),
+            worst_frame,
+            simulate: mean(|it| it.simulate),
+            readback: mean(|it| it.readback),
+            write: mean(|it| it.write),
+            spikes: self.spikes,
+        })
+    }
+}
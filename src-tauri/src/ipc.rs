@@ -1,8 +1,26 @@
 
 pub mod client {
     use serde::Serialize;
+    use std::sync::OnceLock;
+
+    static JOB: OnceLock<String> = OnceLock::new();
+
+    /// Tags every following event with `job`.
+    pub fn set_job(job: String) {
+        let _ = JOB.set(job);
+    }
+
+    #[derive(Serialize)]
+    struct Message<'a, T> {
+        job: Option<&'a str>,
+        event: T,
+    }
 
     pub fn send<T: Serialize>(value: T) {
-        println!("{}", serde_json::to_string(&value).unwrap());
+        let message = Message {
+            job: JOB.get().map(String::as_str),
+            event: value,
+        };
+        println!("{}", serde_json::to_string(&message).unwrap());
     }
 }
@@ -0,0 +1,93 @@
+use crate::overlay::parse_color;
+use anyhow::{Context, Result};
+use image::{imageops, ImageOutputFormat, Rgba, RgbaImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{io::Cursor, path::PathBuf};
+
+/// A judge line drawn from an image strip instead of the plain bar.
+///
+/// Sizes are in pixels of RPE's 1350×900 canvas, which is how prpr sizes line
+/// textures whatever the output resolution.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LineTexture {
+    /// The strip to draw; the resource pack's `line.png` if unset
+    pub image: Option<PathBuf>,
+    /// Length of the whole line
+    pub length: u32,
+    /// Height the strip is scaled to, its own height if unset
+    pub height: Option<u32>,
+    /// Repeat the strip along the line at its own aspect ratio, rather than
+    /// stretching one copy over the whole length
+    pub tile: bool,
+    /// Radius of the glow around the line; no glow if 0
+    pub glow: f32,
+    /// `#RRGGBB` or `#RRGGBBAA`, the strip's own colors if unset
+    pub glow_color: Option<String>,
+    /// Opacity of the glow, above 1 for a glow denser than the strip
+    pub glow_strength: f32,
+}
+
+impl Default for LineTexture {
+    fn default() -> Self {
+        Self {
+            image: None,
+            // three times the width of the screen, like the plain line
+            length: 4050,
+            height: None,
+            tile: true,
+            glow: 0.,
+            glow_color: None,
+            glow_strength: 1.,
+        }
+    }
+}
+
+impl LineTexture {
+    /// Lays `strip` out along the line and adds the glow, returning the PNG that
+    /// stands in for the line.
+    pub fn render(&self, strip: &[u8]) -> Result<Vec<u8>> {
+        let strip = image::load_from_memory(strip).context("the line image is not a readable PNG")?.to_rgba8();
+        let height = self.height.unwrap_or(strip.height()).max(1);
+        let length = self.length.max(1);
+        let strip = if self.tile {
+            let width = (strip.width() as f32 * height as f32 / strip.height().max(1) as f32).round().max(1.) as u32;
+            imageops::resize(&strip, width, height, imageops::FilterType::Triangle)
+        } else {
+            imageops::resize(&strip, length, height, imageops::FilterType::Triangle)
+        };
+
+        // room for the glow to fade out above and below the strip
+        let pad = if self.glow > 0. { (self.glow * 2.).ceil() as u32 } else { 0 };
+        let mut line = RgbaImage::new(length, height + pad * 2);
+        let mut x = 0;
+        while x < length {
+            imageops::overlay(&mut line, &strip, x as i64, pad as i64);
+            x += strip.width();
+        }
+        if pad == 0 {
+            return encode(line);
+        }
+
+        let color = self.glow_color.as_deref().map(parse_color).transpose()?;
+        let mut glow = imageops::blur(&line, self.glow);
+        for Rgba(pixel) in glow.pixels_mut() {
+            if let Some(color) = color {
+                pixel[0] = (color.r * 255.) as u8;
+                pixel[1] = (color.g * 255.) as u8;
+                pixel[2] = (color.b * 255.) as u8;
+                pixel[3] = (pixel[3] as f32 * color.a) as u8;
+            }
+            pixel[3] = (pixel[3] as f32 * self.glow_strength.max(0.)).min(255.) as u8;
+        }
+        imageops::overlay(&mut glow, &line, 0, 0);
+        encode(glow)
+    }
+}
+
+fn encode(image: RgbaImage) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, ImageOutputFormat::Png)?;
+    Ok(bytes.into_inner())
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::{fmt::Write as _, path::Path};
+
+/// One timed line, in seconds of music time.
+#[derive(Clone, Debug)]
+pub struct Lyric {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Longest an LRC line stays up when the next one is far away.
+const MAX_LRC_DURATION: f64 = 8.;
+
+/// `mm:ss.xx` (LRC) or `hh:mm:ss,xxx` (SRT).
+fn parse_time(text: &str) -> Option<f64> {
+    let mut total = 0.;
+    for part in text.trim().split(':') {
+        total = total * 60. + part.replace(',', ".").parse::<f64>().ok()?;
+    }
+    Some(total)
+}
+
+fn parse_lrc(text: &str) -> Vec<Lyric> {
+    let mut offset = 0.;
+    let mut lines: Vec<(f64, String)> = Vec::new();
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        while let Some(tag) = rest.strip_prefix('[') {
+            let Some(close) = tag.find(']') else { break };
+            let (tag, after) = (&tag[..close], &tag[close + 1..]);
+            if let Some(value) = tag.strip_prefix("offset:") {
+                // milliseconds, positive means the lyrics come earlier
+                offset = value.trim().parse::<f64>().unwrap_or(0.) / 1000.;
+            } else if let Some(time) = parse_time(tag) {
+                times.push(time);
+            }
+            rest = after;
+        }
+        for time in times {
+            lines.push((time, rest.trim().to_owned()));
+        }
+    }
+    lines.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let starts: Vec<f64> = lines.iter().map(|it| it.0).collect();
+    lines
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (_, text))| !text.is_empty())
+        .map(|(i, (start, text))| {
+            let next = starts.get(i + 1).copied().unwrap_or(f64::INFINITY);
+            Lyric {
+                start: start - offset,
+                end: next.min(start + MAX_LRC_DURATION) - offset,
+                text,
+            }
+        })
+        .collect()
+}
+
+fn parse_srt(text: &str) -> Vec<Lyric> {
+    let mut result = Vec::new();
+    for block in text.replace("\r\n", "\n").split("\n\n") {
+        let mut lines = block.lines().filter(|it| !it.trim().is_empty());
+        let mut first = lines.next();
+        // the index line is optional in practice
+        if first.is_some_and(|it| !it.contains("-->")) {
+            first = lines.next();
+        }
+        let Some((start, end)) = first.and_then(|it| it.split_once("-->")) else { continue };
+        let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) else { continue };
+        let text = lines.collect::<Vec<_>>().join("\n");
+        if !text.is_empty() {
+            result.push(Lyric { start, end, text });
+        }
+    }
+    result
+}
+
+/// Loads an `.lrc` or `.srt` file, telling them apart by extension.
+pub fn load(path: &Path) -> Result<Vec<Lyric>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read lyrics {}", path.display()))?;
+    let text = text.trim_start_matches('\u{feff}');
+    let is_srt = path
+        .extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("srt"));
+    Ok(if is_srt { parse_srt(text) } else { parse_lrc(text) })
+}
+
+fn format_srt_time(time: f64) -> String {
+    let ms = (time.max(0.) * 1000.).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Writes `lyrics` as SRT, mapping each time through `time`.
+pub fn to_srt(lyrics: &[Lyric], time: impl Fn(f64) -> f64) -> String {
+    let mut srt = String::new();
+    for (i, lyric) in lyrics.iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_srt_time(time(lyric.start)),
+            format_srt_time(time(lyric.end)),
+            lyric.text
+        );
+    }
+    srt
+}
@@ -3,36 +3,69 @@
 
 prpr::tl_file!("main" mtl);
 
+mod agent;
+mod archive;
+mod audio;
+mod batch;
+mod bga;
+mod checksum;
 mod common;
+mod debug;
+mod farm;
+mod ffmpeg;
+mod forensic;
+mod frametime;
 mod ipc;
+mod line;
+mod lyrics;
+mod manifest;
+mod mods;
+mod overlay;
+mod pacing;
 mod preview;
+mod probe;
+mod readback;
 mod render;
+mod respack;
+mod schema;
+mod snapshot;
 mod task;
+mod taskbar;
+mod transcode;
+mod tray;
 
 use anyhow::{bail, Context, Result};
-use common::{ensure_dir, respack_dir, output_dir, CONFIG_DIR, DATA_DIR};
+use common::{ensure_dir, respack_dir, output_dir, Previews, CONFIG_DIR, DATA_DIR};
 use fs4::tokio::AsyncFileExt;
-use macroquad::{miniquad::conf::Icon, prelude::set_pc_assets_folder};
+use macroquad::{
+    miniquad::conf::{Icon, LinuxX11Gl, Platform},
+    prelude::set_pc_assets_folder,
+};
 use prpr::{
     fs::{self, FileSystem},
     info::ChartInfo,
 };
-use render::{find_ffmpeg, RenderConfig, RenderParams};
+use farm::{Farm, FarmJobView, Worker};
+use forensic::Detection;
+use probe::{ChartProbe, ProbeCache};
+use render::{find_ffmpeg, EncoderInfo, RenderConfig, RenderParams};
 use serde::Serialize;
 use std::{
     collections::HashMap,
+    ffi::OsStr,
     fs::File,
     future::Future,
     io::{BufRead, BufReader, BufWriter},
+    net::IpAddr,
     ops::DerefMut,
     path::{Path, PathBuf},
     process::Stdio,
     sync::OnceLock,
     time::SystemTime,
 };
-use task::{TaskQueue, TaskView};
+use task::{QueueSummary, TaskQueue, TaskView};
 use tauri::{
-    CustomMenuItem, InvokeError, Manager, State, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, WindowEvent
+    InvokeError, Manager, State, SystemTray, SystemTrayEvent, WindowEvent
 };
 use tokio::{io::AsyncWriteExt, process::Command};
 
@@ -54,7 +87,66 @@ async fn wrap_async<R>(f: impl Future<Output = Result<R>>) -> Result<R, InvokeEr
     })
 }
 
+/// Context API requested through `PHI_GL_API`: `gl` for desktop GL only, `gles`
+/// for GLES through EGL only; anything else tries desktop GL first and falls back to EGL.
+pub const GL_API_ENV: &str = "PHI_GL_API";
+
+/// Adapter index a render process encodes on (NVENC `-gpu`), set by the queue
+/// from `RenderParams::gpu`.
+pub const GPU_ENV: &str = "PHI_GPU";
+
+/// ffmpeg binary to use ahead of the pinned, system and bundled ones.
+pub const FFMPEG_ENV: &str = "PHI_FFMPEG";
+
+/// Folder for the mix, unpacked charts and other temporary files.
+const TEMP_DIR_ENV: &str = "PHI_TEMP_DIR";
+
+/// Log filter of the app and its subprocesses, such as `debug` or
+/// `warn,phi_recorder=trace`, written to stderr. Without it `info` and up go to
+/// stdout, which the queue reads from subprocesses anyway.
+const LOG_ENV: &str = "PHI_LOG";
+
+fn init_logging() {
+    let _ = match std::env::var(LOG_ENV) {
+        Ok(filter) => tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+            .with_writer(std::io::stderr)
+            .try_init(),
+        Err(_) => tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new("info"))
+            .with_writer(std::io::stdout)
+            .try_init(),
+    };
+}
+
+/// Applies what a headless or containerized deployment passes through the
+/// environment, before a subprocess does anything else.
+fn apply_env_overrides() {
+    if let Some(dir) = std::env::var_os(TEMP_DIR_ENV).map(PathBuf::from) {
+        match std::fs::create_dir_all(&dir) {
+            Ok(()) => {
+                let _ = tempfile::env::override_temp_dir(&dir);
+            }
+            Err(err) => eprintln!("Ignoring {TEMP_DIR_ENV}={}: {err}", dir.display()),
+        }
+    }
+}
+
+fn linux_x11_gl() -> LinuxX11Gl {
+    match std::env::var(GL_API_ENV).as_deref() {
+        Ok("gl") => LinuxX11Gl::GLXOnly,
+        Ok("gles") => LinuxX11Gl::EGLOnly,
+        _ => LinuxX11Gl::GLXWithEGLFallback,
+    }
+}
+
 pub fn build_conf() -> macroquad::window::Conf {
+    init_logging();
+    if std::env::args().len() > 1 {
+        // runs before the window and the tokio runtime exist, so before any
+        // other thread could read the environment
+        apply_env_overrides();
+    }
     macroquad::window::Conf {
         window_title: "Phi Recorder".to_string(),
         window_width: 1280,
@@ -68,6 +160,10 @@ pub fn build_conf() -> macroquad::window::Conf {
             std::env::args().skip(1).next().as_deref(),
             Some("tweakoffset") | Some("preview") | Some("play") | Some("--tweakoffset") | Some("--preview") | Some("--play")
         ),
+        platform: Platform {
+            linux_x11_gl: linux_x11_gl(),
+            ..Default::default()
+        },
         ..Default::default()
     }
 }
@@ -117,6 +213,39 @@ async fn main() -> Result<()> {
             Some("render") => {
                 run_wrapped(render::main(false)).await;
             }
+            Some("mix") => {
+                run_wrapped(render::mix_main()).await;
+            }
+            Some("remux") => {
+                run_wrapped(render::remux_main()).await;
+            }
+            Some("transcode") => {
+                run_wrapped(transcode::main()).await;
+            }
+            Some("trim") => {
+                run_wrapped(transcode::trim_main()).await;
+            }
+            Some("frame") => {
+                run_wrapped(render::frame_main()).await;
+            }
+            Some("probe") => {
+                run_wrapped(probe::main()).await;
+            }
+            Some("stats") => {
+                run_wrapped(probe::stats_main()).await;
+            }
+            Some("detect") => {
+                run_wrapped(forensic::main()).await;
+            }
+            Some("schema") => {
+                run_wrapped(schema::main()).await;
+            }
+            Some("snapshot") => {
+                run_wrapped(snapshot::main()).await;
+            }
+            Some("agent") => {
+                run_wrapped(agent::main()).await;
+            }
             Some("preview") | Some("play") => {
                 run_wrapped(preview::main(false, false)).await;
             }
@@ -136,7 +265,8 @@ async fn main() -> Result<()> {
                 eprintln!("Command: {cmd:?}");
                 let args = std::env::args().nth(1).unwrap_or_default();
                 let path = Path::new(&args);
-                if path.is_file() && (args.contains(".pez") || args.contains(".zip")) || path.is_dir() {
+                let archive = [".pez", ".zip", ".7z"].iter().any(|it| args.contains(it));
+                if path.is_file() && archive || path.is_dir() {
                     println!("Find a valid path, start preview");
                     let mut child = Command::new(std::env::current_exe()?)
                         .arg("--preview")
@@ -155,14 +285,12 @@ async fn main() -> Result<()> {
         hide_cmd();
     }
 
-    let tray_menu = SystemTrayMenu::new()
-        .add_item(CustomMenuItem::new("toggle".to_owned(), mtl!("tray-hide")))
-        .add_item(CustomMenuItem::new("tasks".to_owned(), mtl!("tray-tasks")))
-        .add_native_item(SystemTrayMenuItem::Separator)
-        .add_item(CustomMenuItem::new("quit".to_owned(), mtl!("tray-quit")));
     let app = tauri::Builder::default()
-        .system_tray(SystemTray::new().with_menu(tray_menu))
+        .system_tray(SystemTray::new().with_menu(tray::menu()))
         .manage(TaskQueue::new())
+        .manage(Farm::default())
+        .manage(ProbeCache::default())
+        .manage(Previews::default())
         .invoke_handler(tauri::generate_handler![
             is_the_only_instance,
             exit_program,
@@ -172,12 +300,34 @@ async fn main() -> Result<()> {
             preview_chart,
             preview_tweakoffset,
             preview_play,
+            preview_mix,
+            preview_frame,
             parse_chart,
+            probe_chart,
+            export_chart_stats,
+            detect_watermark,
             post_render,
+            rerender_manifest,
+            remux_video,
+            transcode_video,
+            trim_video,
+            import_jobs,
             get_tasks,
+            get_queue_summary,
+            set_parallel_jobs,
+            set_queue_paused,
+            start_farm,
+            submit_farm_job,
+            get_farm_jobs,
+            join_farm,
+            post_remote_render,
             cancel_task,
             get_respacks,
             open_respack_folder,
+            install_respack,
+            inspect_respack,
+            create_respack,
+            preview_respack,
             get_presets,
             add_preset,
             remove_preset,
@@ -185,6 +335,12 @@ async fn main() -> Result<()> {
             unset_rpe_dir,
             get_rpe_charts,
             test_ffmpeg,
+            verify_install,
+            get_ffmpeg_status,
+            install_ffmpeg,
+            pin_ffmpeg,
+            list_encoders,
+            suggest_bitrate,
             open_app_folder,
         ])
         .on_system_tray_event(|app, event| match event {
@@ -218,8 +374,11 @@ async fn main() -> Result<()> {
                         window.eval("window.goto('tasks')").unwrap();
                         window.set_focus().unwrap();
                     }
+                    "pause" | "cancel" | "output" => {
+                        tray::on_click(app, &id);
+                    }
                     "quit" => {
-                        exit_program();
+                        exit_program(app.clone());
                     }
                     _ => {}
                 }
@@ -252,7 +411,7 @@ async fn main() -> Result<()> {
                     .get_item("toggle")
                     .set_title(mtl!("tray-show"))
                     .unwrap();*/
-                exit_program();
+                exit_program(event.window().app_handle());
                 //event.window().hide().unwrap();
                 //api.prevent_close();
             }
@@ -300,6 +459,14 @@ async fn main() -> Result<()> {
     let asset_dir = resolver.resolve_resource("assets").unwrap();
     ASSET_PATH.set(asset_dir.clone()).unwrap();
     set_pc_assets_folder(&asset_dir.display().to_string());
+    tokio::task::spawn_blocking(move || {
+        let broken = checksum::verify_assets(&asset_dir);
+        if !broken.is_empty() {
+            tracing::warn!("Damaged assets: {}", broken.join(", "));
+        }
+    });
+
+    tray::watch(app.handle());
 
     app.run(|_, _| {});
 
@@ -312,7 +479,9 @@ fn is_the_only_instance() -> bool {
 }
 
 #[tauri::command]
-fn exit_program() {
+fn exit_program(app: tauri::AppHandle) {
+    // `process::exit` skips destructors
+    app.state::<Previews>().clear();
     /*#[cfg(target_os = "windows")]
     {
         use sysinfo::{ProcessExt, System, SystemExt, PidExt};
@@ -427,7 +596,7 @@ fn open_file(path: &Path) -> Result<(), InvokeError> {
 async fn parse_chart(path: &Path) -> Result<ChartInfo, InvokeError> {
     wrap_async(async move {
         let mut fs: Box<dyn FileSystem + Send + Sync + 'static> =
-            fs::fs_from_file(path).with_context(|| mtl!("read-chart-failed"))?;
+            archive::open(path).with_context(|| mtl!("read-chart-failed"))?;
         let info = fs::load_info(fs.deref_mut())
             .await
             .with_context(|| mtl!("load-info-failed"))?;
@@ -438,6 +607,115 @@ async fn parse_chart(path: &Path) -> Result<ChartInfo, InvokeError> {
     .await
 }
 
+#[tauri::command]
+async fn preview_frame(previews: State<'_, Previews>, params: RenderParams, time: f64) -> Result<PathBuf, InvokeError> {
+    wrap_async(async move {
+        let output = tempfile::Builder::new()
+            .prefix("frame-")
+            .suffix(".png")
+            .tempfile()?
+            .into_temp_path();
+        let mut child = cmd_hidden(std::env::current_exe()?)
+            .arg("frame")
+            .arg(ASSET_PATH.get().unwrap())
+            .arg(time.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&params)?).as_bytes())
+            .await?;
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&*output)?).as_bytes())
+            .await?;
+        drop(stdin);
+
+        let result = child.wait_with_output().await?;
+        if !result.status.success() {
+            bail!("{}", String::from_utf8_lossy(&result.stderr));
+        }
+        let path = output.to_path_buf();
+        previews.replace("frame", output);
+        Ok(path)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn probe_chart(cache: State<'_, ProbeCache>, path: PathBuf) -> Result<ChartProbe, InvokeError> {
+    wrap_async(async move {
+        // charters save while the app is open, so a cached probe is only as good as the files
+        let fingerprint = archive::fingerprint(&path)?;
+        if let Some(probe) = cache.get(&path, &fingerprint) {
+            return Ok(probe);
+        }
+        let output = cmd_hidden(std::env::current_exe()?)
+            .arg("probe")
+            .arg(ASSET_PATH.get().unwrap())
+            .arg(&path)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (line, probe) = stdout
+            .lines()
+            .rev()
+            .find_map(|line| Some((line.trim(), serde_json::from_str(line.trim()).ok()?)))
+            .context("no probe result in output")?;
+        cache.insert(path, fingerprint, line.to_owned());
+        Ok(probe)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn detect_watermark(video: PathBuf, key: String) -> Result<Detection, InvokeError> {
+    wrap_async(async move {
+        let output = cmd_hidden(std::env::current_exe()?)
+            .arg("detect")
+            .arg(ASSET_PATH.get().unwrap())
+            .arg(&video)
+            .arg(&key)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .rev()
+            .find_map(|line| serde_json::from_str(line.trim()).ok())
+            .context("no detection result in output")
+    })
+    .await
+}
+
+#[tauri::command]
+async fn export_chart_stats(path: PathBuf, output: PathBuf) -> Result<(), InvokeError> {
+    wrap_async(async move {
+        let result = cmd_hidden(std::env::current_exe()?)
+            .arg("stats")
+            .arg(ASSET_PATH.get().unwrap())
+            .arg(&path)
+            .arg(&output)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !result.status.success() {
+            bail!("{}", String::from_utf8_lossy(&result.stderr));
+        }
+        Ok(())
+    })
+    .await
+}
+
 pub fn cmd_hidden(program: impl AsRef<std::ffi::OsStr>) -> Command {
     let cmd = tokio::process::Command::new(program);
     /*#[cfg(target_os = "windows")] // Without terminal, there is no log
@@ -514,6 +792,42 @@ async fn preview_play(params: RenderParams) -> Result<(), InvokeError> {
     .await
 }
 
+#[tauri::command]
+async fn preview_mix(previews: State<'_, Previews>, params: RenderParams) -> Result<PathBuf, InvokeError> {
+    wrap_async(async move {
+        let output = tempfile::Builder::new()
+            .prefix("mix-")
+            .suffix(".wav")
+            .tempfile()?
+            .into_temp_path();
+        let mut child = cmd_hidden(std::env::current_exe()?)
+            .arg("mix")
+            .arg(ASSET_PATH.get().unwrap())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&params)?).as_bytes())
+            .await?;
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&*output)?).as_bytes())
+            .await?;
+        drop(stdin);
+
+        let result = child.wait_with_output().await?;
+        if !result.status.success() {
+            bail!("{}", String::from_utf8_lossy(&result.stderr));
+        }
+        let path = output.to_path_buf();
+        previews.replace("mix", output);
+        Ok(path)
+    })
+    .await
+}
+
 #[tauri::command]
 async fn post_render(queue: State<'_, TaskQueue>, params: RenderParams) -> Result<(), InvokeError> {
     wrap_async(async move {
@@ -523,6 +837,167 @@ async fn post_render(queue: State<'_, TaskQueue>, params: RenderParams) -> Resul
     .await
 }
 
+#[tauri::command]
+async fn import_jobs(queue: State<'_, TaskQueue>, path: PathBuf, config: RenderConfig) -> Result<usize, InvokeError> {
+    let presets = get_presets().await?;
+    wrap_async(async move {
+        let jobs = batch::load(&path, &config, &presets).await?;
+        let count = jobs.len();
+        for params in jobs {
+            queue.post(params).await?;
+        }
+        Ok(count)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn start_farm(farm: State<'_, Farm>, host: Option<IpAddr>, port: u16, token: String) -> Result<(), InvokeError> {
+    wrap_async(farm.start(host, port, token)).await
+}
+
+#[tauri::command]
+async fn submit_farm_job(farm: State<'_, Farm>, params: RenderParams) -> Result<(), InvokeError> {
+    farm.submit(params).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_farm_jobs(farm: State<'_, Farm>) -> Result<Vec<FarmJobView>, InvokeError> {
+    Ok(farm.jobs().await)
+}
+
+/// Registers with the coordinator at `address`, which must hold the same
+/// `token`, and keeps rendering its jobs in the local queue until the
+/// connection drops.
+#[tauri::command]
+async fn join_farm(app: tauri::AppHandle, address: String, name: String, token: String) -> Result<(), InvokeError> {
+    let worker = wrap_async(Worker::connect(&address, &name, &token)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = worker.run(&app.state::<TaskQueue>()).await {
+            tracing::warn!("Left render farm at {address}: {err:?}");
+        }
+    });
+    Ok(())
+}
+
+/// Renders on a remote agent, forwarding its status as `remote-status` events.
+#[tauri::command]
+async fn post_remote_render(
+    window: tauri::Window,
+    address: String,
+    user: String,
+    token: String,
+    params: RenderParams,
+) -> Result<PathBuf, InvokeError> {
+    wrap_async(agent::submit(&address, &user, &token, &params, |status| {
+        let _ = window.emit("remote-status", status);
+    }))
+    .await
+}
+
+#[tauri::command]
+async fn rerender_manifest(
+    queue: State<'_, TaskQueue>,
+    manifest: PathBuf,
+    overrides: Option<serde_json::Value>,
+) -> Result<(), InvokeError> {
+    wrap_async(async move {
+        queue.post(crate::manifest::load_params(&manifest, overrides.as_ref())?).await?;
+        Ok(())
+    })
+    .await
+}
+
+/// Lays a fresh mix, with `overrides` on the manifest's config, onto `video` and
+/// returns the path of the new file, written next to it.
+#[tauri::command]
+async fn remux_video(manifest: PathBuf, video: PathBuf, overrides: Option<serde_json::Value>) -> Result<PathBuf, InvokeError> {
+    wrap_async(async move {
+        let params = crate::manifest::load_params(&manifest, overrides.as_ref())?;
+        let stem = video.file_stem().context("invalid video path")?.to_string_lossy();
+        let output = video.with_file_name(format!("{stem}.remux.{}", params.config.container()));
+        let mut child = cmd_hidden(std::env::current_exe()?)
+            .arg("remux")
+            .arg(ASSET_PATH.get().unwrap())
+            .arg(&video)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&params)?).as_bytes())
+            .await?;
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&output)?).as_bytes())
+            .await?;
+        drop(stdin);
+
+        let result = child.wait_with_output().await?;
+        if !result.status.success() {
+            bail!("{}", String::from_utf8_lossy(&result.stderr));
+        }
+        Ok(output)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn transcode_video(params: transcode::TranscodeParams) -> Result<PathBuf, InvokeError> {
+    wrap_async(async move { tokio::task::spawn_blocking(move || transcode::run(&params)).await? }).await
+}
+
+/// Runs `trim` on a [`transcode::TrimParams`]; the chart has to be loaded, which
+/// needs a process of its own.
+#[tauri::command]
+async fn trim_video(params: serde_json::Value) -> Result<PathBuf, InvokeError> {
+    wrap_async(async move {
+        let mut child = cmd_hidden(std::env::current_exe()?)
+            .arg("trim")
+            .arg(ASSET_PATH.get().unwrap())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(format!("{}\n", serde_json::to_string(&params)?).as_bytes())
+            .await?;
+        drop(stdin);
+
+        let result = child.wait_with_output().await?;
+        if !result.status.success() {
+            bail!("{}", String::from_utf8_lossy(&result.stderr));
+        }
+        // logs go to stdout as well
+        String::from_utf8_lossy(&result.stdout)
+            .lines()
+            .rev()
+            .find_map(|line| serde_json::from_str::<transcode::TrimResult>(line.trim()).ok())
+            .map(|it| it.output)
+            .context("no trim result in output")
+    })
+    .await
+}
+
+#[tauri::command]
+fn set_parallel_jobs(queue: State<'_, TaskQueue>, count: usize) {
+    queue.set_parallel(count);
+}
+
+#[tauri::command]
+fn set_queue_paused(queue: State<'_, TaskQueue>, paused: bool) {
+    queue.set_paused(paused);
+}
+
+#[tauri::command]
+async fn get_queue_summary(queue: State<'_, TaskQueue>) -> Result<QueueSummary, InvokeError> {
+    wrap_async(async move { Ok(queue.summary().await) }).await
+}
+
 #[tauri::command]
 async fn get_tasks(queue: State<'_, TaskQueue>) -> Result<Vec<TaskView>, InvokeError> {
     wrap_async(async move { Ok(queue.tasks().await) }).await
@@ -547,7 +1022,8 @@ fn get_respacks() -> Result<Vec<RespackInfo>, InvokeError> {
             .read_dir()?
             .filter_map(|it| {
                 it.ok()
-                    .filter(|it| it.path().is_file())
+                    // installed packs keep their hash alongside
+                    .filter(|it| it.path().is_file() && it.path().extension() != Some(OsStr::new("sha256")))
                     .map(|it| RespackInfo {
                         name: it.file_name().to_str().unwrap().to_owned(),
                         path: it.path().canonicalize().unwrap().display().to_string(),
@@ -560,6 +1036,141 @@ fn get_respacks() -> Result<Vec<RespackInfo>, InvokeError> {
     .map_err(InvokeError::from_anyhow)
 }
 
+/// Copies a resource pack (zip or folder) into the respack folder, converting its
+/// hitsounds on the way.
+#[tauri::command]
+async fn install_respack(path: PathBuf, overwrite: bool) -> Result<RespackInfo, InvokeError> {
+    wrap_async(async move {
+        let Some(ffmpeg) = find_ffmpeg()? else {
+            bail!("FFmpeg not found");
+        };
+        let dir = respack_dir()?;
+        let target = tokio::task::spawn_blocking(move || respack::install(&ffmpeg, &path, &dir, overwrite)).await??;
+        Ok(RespackInfo {
+            name: target.file_name().unwrap().to_string_lossy().into_owned(),
+            path: target.canonicalize()?.display().to_string(),
+        })
+    })
+    .await
+}
+
+#[tauri::command]
+async fn inspect_respack(path: PathBuf) -> Result<respack::RespackContents, InvokeError> {
+    wrap_async(async move { respack::inspect(&path) }).await
+}
+
+/// Assembles a resource pack into the respack folder from loose files, optionally
+/// on top of an existing pack.
+#[tauri::command]
+async fn create_respack(spec: respack::RespackSpec) -> Result<RespackInfo, InvokeError> {
+    wrap_async(async move {
+        let Some(ffmpeg) = find_ffmpeg()? else {
+            bail!("FFmpeg not found");
+        };
+        let dir = respack_dir()?;
+        let target = tokio::task::spawn_blocking(move || respack::create(&ffmpeg, &spec, &dir)).await??;
+        Ok(RespackInfo {
+            name: target.file_name().unwrap().to_string_lossy().into_owned(),
+            path: target.canonicalize()?.display().to_string(),
+        })
+    })
+    .await
+}
+
+/// Video time of the preview thumbnail, when most of the sample chart is on screen.
+const RESPACK_THUMBNAIL_TIME: f64 = 3.;
+
+#[derive(Serialize)]
+struct RespackPreview {
+    video: PathBuf,
+    thumbnail: PathBuf,
+}
+
+/// Runs a `render`-like subcommand (`render` or `frame <time>`) of the sample
+/// chart to `output`.
+async fn render_sample(args: &[&OsStr], params: &RenderParams, output: &Path) -> Result<()> {
+    let mut child = cmd_hidden(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(format!("{}\n", serde_json::to_string(params)?).as_bytes())
+        .await?;
+    stdin
+        .write_all(format!("{}\n", serde_json::to_string(output)?).as_bytes())
+        .await?;
+    drop(stdin);
+    let result = child.wait_with_output().await?;
+    if !result.status.success() {
+        bail!("{}", String::from_utf8_lossy(&result.stderr));
+    }
+    Ok(())
+}
+
+/// Renders a short sample chart with `respack` on top of `config`, for judging a
+/// skin without a full render.
+#[tauri::command]
+async fn preview_respack(
+    previews: State<'_, Previews>,
+    respack: String,
+    config: RenderConfig,
+) -> Result<RespackPreview, InvokeError> {
+    wrap_async(async move {
+        // ffmpeg writes the sample's music, which would hold up a runtime worker
+        let (scratch, chart) = tokio::task::spawn_blocking(|| -> Result<_> {
+            let Some(ffmpeg) = find_ffmpeg()? else {
+                bail!("FFmpeg not found");
+            };
+            let scratch = tempfile::Builder::new().prefix("respack-preview-").tempdir()?;
+            let chart = scratch.path().join("chart");
+            std::fs::create_dir(&chart)?;
+            respack::write_sample_chart(&ffmpeg, &chart)?;
+            Ok((scratch, chart))
+        })
+        .await??;
+        let dir = scratch.path().to_owned();
+
+        let mut config = serde_json::to_value(config)?;
+        if let Some(config) = config.as_object_mut() {
+            config.insert("resPackPath".into(), respack.into());
+            config.insert("disableLoading".into(), true.into());
+            config.insert("endingLength".into(), 0.into());
+            config.insert("manifest".into(), false.into());
+            config.insert("encoderFallback".into(), serde_json::Value::Array(Vec::new()));
+        }
+        let mut fs = archive::open(&chart)?;
+        let info = fs::load_info(fs.deref_mut()).await?;
+        let params = RenderParams {
+            path: chart,
+            info,
+            config: serde_json::from_value(config)?,
+            progress_frames: None,
+            progress_millis: None,
+            job_id: None,
+            subtitles: None,
+            overlays: Vec::new(),
+            output_name: None,
+            gpu: None,
+            resume: None,
+            offset_delta: 0.,
+            strict: false,
+        };
+
+        let asset = ASSET_PATH.get().unwrap().as_os_str();
+        let thumbnail = dir.join("thumbnail.png");
+        let time = RESPACK_THUMBNAIL_TIME.to_string();
+        render_sample(&[OsStr::new("frame"), asset, OsStr::new(&time)], &params, &thumbnail).await?;
+        let video = dir.join(format!("preview.{}", params.config.container()));
+        render_sample(&[OsStr::new("render"), asset], &params, &video).await?;
+        previews.replace("respack", scratch);
+        Ok(RespackPreview { video, thumbnail })
+    })
+    .await
+}
+
 #[tauri::command]
 fn open_respack_folder() -> Result<(), InvokeError> {
     (|| {
@@ -808,6 +1419,69 @@ fn test_ffmpeg() -> Result<bool, InvokeError> {
     (|| Ok(find_ffmpeg()?.is_some()))().map_err(InvokeError::from_anyhow)
 }
 
+/// Bundled assets, and the resource pack at `respack` if given, that are damaged.
+#[tauri::command]
+async fn verify_install(respack: Option<PathBuf>) -> Result<Vec<String>, InvokeError> {
+    wrap_async(async move {
+        Ok(tokio::task::spawn_blocking(move || {
+            let mut problems = checksum::verify_assets(ASSET_PATH.get().unwrap());
+            if let Some(Err(err)) = respack.as_deref().map(checksum::verify_respack) {
+                problems.push(format!("{err:#}"));
+            }
+            problems
+        })
+        .await?)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn get_ffmpeg_status(check: bool) -> Result<ffmpeg::Status, InvokeError> {
+    wrap_async(ffmpeg::status(check)).await
+}
+
+/// Downloads `version` from the index, or the newest build, and pins it.
+#[tauri::command]
+async fn install_ffmpeg(version: Option<String>) -> Result<ffmpeg::Status, InvokeError> {
+    wrap_async(async move {
+        let releases = ffmpeg::releases().await?;
+        let release = match &version {
+            Some(version) => releases.iter().find(|it| it.version == *version),
+            None => releases.first(),
+        }
+        .context("no such ffmpeg build")?;
+        ffmpeg::install(release).await?;
+        ffmpeg::pin(Some(&release.version))?;
+        ffmpeg::status(false).await
+    })
+    .await
+}
+
+#[tauri::command]
+async fn pin_ffmpeg(version: Option<String>) -> Result<ffmpeg::Status, InvokeError> {
+    wrap_async(async move {
+        ffmpeg::pin(version.as_deref())?;
+        ffmpeg::status(false).await
+    })
+    .await
+}
+
+#[tauri::command]
+fn suggest_bitrate(resolution: (u32, u32), fps: u32, encoder: String) -> String {
+    render::suggest_bitrate(resolution, fps, &encoder, false)
+}
+
+#[tauri::command]
+async fn list_encoders() -> Result<Vec<EncoderInfo>, InvokeError> {
+    wrap_async(async move {
+        let Some(ffmpeg) = find_ffmpeg()? else {
+            bail!("FFmpeg not found");
+        };
+        Ok(tokio::task::spawn_blocking(move || render::list_encoders(&ffmpeg)).await?)
+    })
+    .await
+}
+
 #[tauri::command]
 fn open_app_folder() -> Result<(), InvokeError> {
     (|| {
@@ -0,0 +1,145 @@
+use crate::{
+    audio::Loudness,
+    frametime::FrameStats,
+    render::{cmd_hidden, RenderParams},
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever [`Manifest`] changes in a way older readers can't handle.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Wall-clock time spent in each stage, in seconds.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub loading: f64,
+    pub mixing: f64,
+    /// From setting up the scene until ffmpeg exits
+    pub rendering: f64,
+    pub total: f64,
+    /// Per-frame times of the rendering stage; missing in manifests from before they were measured
+    #[serde(default)]
+    pub frames: Option<FrameStats>,
+}
+
+/// Written next to every video, recording what it was made from.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub manifest_version: u32,
+    pub recorder_version: String,
+    /// The `RenderParams` of the job, as received
+    pub params: Value,
+    /// SHA-256 of the chart file, or of every file in a chart folder
+    pub chart_hash: String,
+    pub respack_hash: Option<String>,
+    /// First line of `ffmpeg -version`
+    pub ffmpeg_version: String,
+    /// Which ffmpeg binary it was, a downloaded build's path naming its version;
+    /// missing in manifests from before it was recorded
+    #[serde(default)]
+    pub ffmpeg_path: String,
+    pub encoder: String,
+    pub frames: u64,
+    pub timings: Timings,
+    /// Of the final mix; missing in manifests from before it was measured
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+}
+
+fn hash_into(hasher: &mut Sha256, root: &Path, path: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        entries.sort();
+        for entry in entries {
+            hash_into(hasher, root, &entry)?;
+        }
+    } else {
+        // the relative path goes in too, so renaming a file changes the hash
+        let name = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update(std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?);
+    }
+    Ok(())
+}
+
+/// SHA-256 of a file, or of the names and contents of everything in a folder.
+pub fn hash_path(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        hash_into(&mut hasher, path, path)?;
+    } else {
+        hasher.update(std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn ffmpeg_version(ffmpeg: &str) -> String {
+    cmd_hidden(ffmpeg)
+        .arg("-version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).lines().next().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+impl Manifest {
+    pub fn new(
+        params: Value,
+        ffmpeg: &str,
+        encoder: &str,
+        frames: u64,
+        timings: Timings,
+        loudness: Option<Loudness>,
+    ) -> Result<Self> {
+        let chart = params["path"].as_str().context("job has no chart path")?;
+        let chart_hash = hash_path(Path::new(chart))?;
+        let respack_hash = match params["config"]["resPackPath"].as_str() {
+            Some(path) if !path.is_empty() => Some(hash_path(Path::new(path))?),
+            _ => None,
+        };
+        Ok(Self {
+            manifest_version: MANIFEST_VERSION,
+            recorder_version: env!("CARGO_PKG_VERSION").to_owned(),
+            params,
+            chart_hash,
+            respack_hash,
+            ffmpeg_version: ffmpeg_version(ffmpeg),
+            ffmpeg_path: ffmpeg.to_owned(),
+            encoder: encoder.to_owned(),
+            frames,
+            timings,
+            loudness,
+        })
+    }
+}
+
+/// Reads the manifest at `path` back into the job it was written for, checking
+/// that the chart is still the same and laying `overrides` over its config.
+pub fn load_params(path: &Path, overrides: Option<&Value>) -> Result<RenderParams> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest: Manifest = serde_json::from_str(&text).context("invalid manifest")?;
+    if manifest.manifest_version > MANIFEST_VERSION {
+        bail!("manifest version {} is newer than this recorder supports", manifest.manifest_version);
+    }
+    let mut params = manifest.params;
+    let chart = params["path"].as_str().context("manifest has no chart path")?;
+    if hash_path(Path::new(chart))? != manifest.chart_hash {
+        bail!("{chart} has changed since the manifest was written");
+    }
+    if let (Some(config), Some(Value::Object(overrides))) = (params["config"].as_object_mut(), overrides) {
+        config.extend(overrides.clone());
+    }
+    if let Some(params) = params.as_object_mut() {
+        // a new render is a new job
+        params.remove("jobId");
+    }
+    serde_json::from_value(params).context("manifest params don't fit this recorder")
+}
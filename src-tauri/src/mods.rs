@@ -0,0 +1,197 @@
+use anyhow::Result;
+use prpr::fs::FileSystem;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{any::Any, sync::Arc};
+
+/// Where the chart finds the texture of [`ChartMods::line_texture`].
+pub const LINE_TEXTURE: &str = "recorder-line.png";
+
+/// Kind every note (holds included) is turned into.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteConversion {
+    Click,
+    Flick,
+}
+
+/// Changes made to the chart file before prpr parses it, so that the scene and
+/// everything the recorder derives from the chart (mix, stats, overlays) agree.
+///
+/// Only official and RPE JSON charts are rewritten; PEC charts are passed through.
+#[derive(Debug, Clone, Default)]
+pub struct ChartMods {
+    pub convert: Option<NoteConversion>,
+    pub remove_fake: bool,
+    /// Indices of the only judge lines to draw, if not empty
+    pub only_lines: Vec<usize>,
+    /// Indices of judge lines not to draw
+    pub hidden_lines: Vec<usize>,
+    /// Draw plain judge lines with the texture served at [`LINE_TEXTURE`]; only
+    /// RPE charts can have textured lines
+    pub line_texture: bool,
+}
+
+impl ChartMods {
+    pub fn is_empty(&self) -> bool {
+        self.convert.is_none()
+            && !self.remove_fake
+            && !self.line_texture
+            && self.only_lines.is_empty()
+            && self.hidden_lines.is_empty()
+    }
+
+    fn is_hidden(&self, line: usize) -> bool {
+        self.hidden_lines.contains(&line) || !self.only_lines.is_empty() && !self.only_lines.contains(&line)
+    }
+
+    /// Makes `line` invisible and takes its notes away. The line itself stays,
+    /// so that lines parented to it still move as they should.
+    fn hide(line: &mut Value, rpe: bool) {
+        if rpe {
+            let event = serde_json::json!({
+                "bezier": 0,
+                "bezierPoints": [0., 0., 0., 0.],
+                "easingLeft": 0.,
+                "easingRight": 1.,
+                "easingType": 1,
+                "start": 0,
+                "end": 0,
+                "startTime": [0, 0, 1],
+                "endTime": [100000, 0, 1],
+                "linkgroup": 0
+            });
+            if let Some(layers) = line.get_mut("eventLayers").and_then(Value::as_array_mut) {
+                // alpha adds up across layers
+                for (i, layer) in layers.iter_mut().enumerate() {
+                    if let Some(layer) = layer.as_object_mut() {
+                        let events = if i == 0 { vec![event.clone()] } else { Vec::new() };
+                        layer.insert("alphaEvents".to_owned(), Value::Array(events));
+                    }
+                }
+            }
+            line["notes"] = Value::Array(Vec::new());
+        } else {
+            line["judgeLineDisappearEvents"] = serde_json::json!([
+                { "startTime": -999999., "endTime": 1e9, "start": 0., "end": 0. }
+            ]);
+            line["notesAbove"] = Value::Array(Vec::new());
+            line["notesBelow"] = Value::Array(Vec::new());
+        }
+    }
+
+    /// Note `type` values of (official, RPE) charts.
+    fn kinds(conversion: NoteConversion) -> (u64, u64) {
+        match conversion {
+            NoteConversion::Click => (1, 1),
+            NoteConversion::Flick => (4, 3),
+        }
+    }
+
+    pub fn apply(&self, bytes: Vec<u8>) -> Vec<u8> {
+        let Ok(mut chart) = serde_json::from_slice::<Value>(&bytes) else {
+            return bytes;
+        };
+        let rpe = chart.get("META").is_some();
+        let Some(lines) = chart.get_mut("judgeLineList").and_then(Value::as_array_mut) else {
+            return bytes;
+        };
+        for (index, line) in lines.iter_mut().enumerate() {
+            if self.is_hidden(index) {
+                Self::hide(line, rpe);
+                continue;
+            }
+            if self.line_texture && rpe {
+                // lines that already have a texture of their own keep it
+                if line.get("Texture").and_then(Value::as_str).map_or(true, |it| it == "line.png") {
+                    line["Texture"] = LINE_TEXTURE.into();
+                }
+            }
+            let lists: &[&str] = if rpe { &["notes"] } else { &["notesAbove", "notesBelow"] };
+            for list in lists {
+                let Some(notes) = line.get_mut(*list).and_then(Value::as_array_mut) else { continue };
+                if self.remove_fake {
+                    // only RPE has fake notes
+                    notes.retain(|note| note.get("isFake").and_then(Value::as_u64).unwrap_or(0) == 0);
+                }
+                if let Some(conversion) = self.convert {
+                    let (official, rpe_kind) = Self::kinds(conversion);
+                    for note in notes {
+                        note["type"] = (if rpe { rpe_kind } else { official }).into();
+                    }
+                }
+            }
+        }
+        serde_json::to_vec(&chart).unwrap_or(bytes)
+    }
+}
+
+/// Whether `bytes` is an RPE chart, the only kind with textured lines.
+pub fn is_rpe(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(bytes).map_or(false, |it| it.get("META").is_some())
+}
+
+/// Serves the chart file of `inner` through [`ChartMods::apply`], and the line
+/// texture at [`LINE_TEXTURE`].
+struct ModdedFs {
+    inner: Box<dyn FileSystem>,
+    chart: String,
+    mods: ChartMods,
+    line_texture: Option<Arc<Vec<u8>>>,
+}
+
+#[async_trait::async_trait]
+impl FileSystem for ModdedFs {
+    async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        if let (LINE_TEXTURE, Some(texture)) = (path, &self.line_texture) {
+            return Ok(texture.as_ref().clone());
+        }
+        let bytes = self.inner.load_file(path).await?;
+        Ok(if path == self.chart { self.mods.apply(bytes) } else { bytes })
+    }
+
+    async fn exists(&mut self, path: &str) -> Result<bool> {
+        if path == LINE_TEXTURE && self.line_texture.is_some() {
+            return Ok(true);
+        }
+        self.inner.exists(path).await
+    }
+
+    fn list_root(&self) -> Result<Vec<String>> {
+        self.inner.list_root()
+    }
+
+    fn clone_box(&self) -> Box<dyn FileSystem> {
+        Box::new(Self {
+            inner: self.inner.clone_box(),
+            chart: self.chart.clone(),
+            mods: self.mods.clone(),
+            line_texture: self.line_texture.clone(),
+        })
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Wraps `fs` so that `chart` comes out with `mods` applied, its plain lines
+/// drawn with `line_texture` if given.
+pub fn wrap(
+    fs: Box<dyn FileSystem>,
+    chart: &str,
+    mut mods: ChartMods,
+    line_texture: Option<Vec<u8>>,
+) -> Box<dyn FileSystem> {
+    mods.line_texture = line_texture.is_some();
+    if mods.is_empty() {
+        return fs;
+    }
+    Box::new(ModdedFs {
+        inner: fs,
+        chart: chart.to_owned(),
+        mods,
+        line_texture: line_texture.map(Arc::new),
+    })
+}
@@ -0,0 +1,528 @@
+use crate::render::ProgressBarStyle;
+use anyhow::{anyhow, bail, Result};
+use macroquad::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub fn load_font(bytes: &[u8]) -> Result<Font> {
+    load_ttf_font_from_bytes(bytes).map_err(|err| anyhow!("failed to load overlay font: {err:?}"))
+}
+
+/// Parses `#RRGGBB` or `#RRGGBBAA`.
+pub fn parse_color(hex: &str) -> Result<Color> {
+    let hex = hex.trim().trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        bail!("expected #RRGGBB or #RRGGBBAA");
+    }
+    let channel = |i: usize| -> Result<f32> { Ok(u8::from_str_radix(&hex[i..i + 2], 16)? as f32 / 255.) };
+    Ok(Color::new(
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+        if hex.len() == 8 { channel(6)? } else { 1. },
+    ))
+}
+
+const VERTEX_SHADER: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+varying lowp vec2 uv;
+varying lowp vec4 color;
+uniform mat4 Model;
+uniform mat4 Projection;
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"#version 100
+varying lowp vec4 color;
+varying lowp vec2 uv;
+uniform sampler2D Texture;
+void main() {
+    gl_FragColor = color * texture2D(Texture, uv);
+}
+"#;
+
+/// The default material with another blend state.
+fn blend_material(blend: BlendState) -> Result<Material> {
+    load_material(
+        VERTEX_SHADER,
+        FRAGMENT_SHADER,
+        MaterialParams {
+            pipeline_params: PipelineParams {
+                color_blend: Some(blend),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .map_err(|err| anyhow!("failed to build blend material: {err:?}"))
+}
+
+/// "Screen" blending, i.e. `src * (1 - dst) + dst`: shows fully over black and
+/// only brightens what is already drawn, which lets a background be laid under
+/// a finished frame.
+pub fn screen_material() -> Result<Material> {
+    blend_material(BlendState::new(
+        Equation::Add,
+        BlendFactor::OneMinusValue(BlendValue::DestinationColor),
+        BlendFactor::One,
+    ))
+}
+
+/// Adds the drawn color to the frame, or takes it away when `subtract` is set.
+pub fn additive_material(subtract: bool) -> Result<Material> {
+    let equation = if subtract { Equation::ReverseSubtract } else { Equation::Add };
+    blend_material(BlendState::new(equation, BlendFactor::One, BlendFactor::One))
+}
+
+/// One entry of the overlay script in `RenderParams::overlays`, timed in video seconds.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlayItem {
+    pub start: f64,
+    pub end: f64,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Drawn instead of `text` when both are set
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    /// Where the anchor of the item lands, in fractions of the frame
+    #[serde(default = "OverlayItem::default_position")]
+    pub position: (f32, f32),
+    #[serde(default)]
+    pub style: OverlayStyle,
+}
+
+impl OverlayItem {
+    fn default_position() -> (f32, f32) {
+        (0.5, 0.5)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OverlayStyle {
+    /// Line height of the text, or height of the image, in fractions of the frame height
+    pub size: f32,
+    /// `#RRGGBB` or `#RRGGBBAA`, tinting images as well
+    pub color: Option<String>,
+    /// Point of the item that is placed on `position`, in fractions of its own box
+    pub anchor: (f32, f32),
+    /// Seconds to fade in and out over
+    pub fade: f32,
+    /// Put text on a translucent backdrop
+    pub backdrop: bool,
+}
+
+impl Default for OverlayStyle {
+    fn default() -> Self {
+        Self {
+            size: 0.05,
+            color: None,
+            anchor: (0.5, 0.5),
+            fade: 0.2,
+            backdrop: false,
+        }
+    }
+}
+
+/// Recorder-side drawing on top of the frames produced by prpr, in pixel
+/// coordinates of the output video (origin at the top left).
+pub struct Overlay {
+    font: Font,
+    width: f32,
+    height: f32,
+}
+
+impl Overlay {
+    pub fn new(font: &[u8], (width, height): (u32, u32)) -> Result<Self> {
+        Ok(Self {
+            font: load_font(font)?,
+            width: width as f32,
+            height: height as f32,
+        })
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Directs the following draw calls to `target`.
+    pub fn begin(&self, target: RenderTarget) {
+        set_camera(&Camera2D {
+            render_target: Some(target),
+            viewport: Some((0, 0, self.width as i32, self.height as i32)),
+            ..Camera2D::from_display_rect(Rect::new(0., 0., self.width, self.height))
+        });
+    }
+
+    pub fn end(&self) {
+        set_default_camera();
+    }
+
+    /// Draws a single line of text so that the point `anchor` (fractions of the text
+    /// box) lands on (`x`, `y`), returning the occupied rectangle.
+    pub fn text(&self, text: &str, x: f32, y: f32, size: f32, anchor: (f32, f32), color: Color) -> Rect {
+        let font_size = size.max(1.) as u16;
+        let dim = measure_text(text, Some(self.font), font_size, 1.);
+        let left = x - dim.width * anchor.0;
+        let top = y - dim.height * anchor.1;
+        draw_text_ex(
+            text,
+            left,
+            top + dim.offset_y,
+            TextParams {
+                font: self.font,
+                font_size,
+                color,
+                ..Default::default()
+            },
+        );
+        Rect::new(left, top, dim.width, dim.height)
+    }
+
+    /// Watermark text anchored like [`Overlay::text`], optionally on a pill-shaped backdrop.
+    pub fn watermark(&self, text: &str, x: f32, y: f32, size: f32, anchor: (f32, f32), color: Color, pill: bool) {
+        if !pill {
+            self.text(text, x, y, size, anchor, color);
+            return;
+        }
+        let dim = measure_text(text, Some(self.font), size.max(1.) as u16, 1.);
+        let (pad_x, pad_y) = (size * 0.6, size * 0.3);
+        let (width, height) = (dim.width + pad_x * 2., dim.height + pad_y * 2.);
+        let (left, top) = (x - width * anchor.0, y - height * anchor.1);
+        let backdrop = Color::new(0., 0., 0., 0.45 * color.a);
+        let r = height / 2.;
+        draw_rectangle(left + r, top, width - r * 2., height, backdrop);
+        draw_circle(left + r, top + r, r, backdrop);
+        draw_circle(left + width - r, top + r, r, backdrop);
+        self.text(text, left + pad_x, top + pad_y, size, (0., 0.), color);
+    }
+
+    /// Fills the whole frame with `texture`, cropping it to keep its aspect ratio.
+    pub fn cover(&self, texture: Texture2D, color: Color) {
+        let (tw, th) = (texture.width(), texture.height());
+        let scale = (self.width / tw).max(self.height / th);
+        let (w, h) = (tw * scale, th * scale);
+        draw_texture_ex(
+            texture,
+            (self.width - w) / 2.,
+            (self.height - h) / 2.,
+            color,
+            DrawTextureParams {
+                dest_size: Some(vec2(w, h)),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Renders `texture`, cropped to the frame, into a copy `factor` times smaller.
+    pub fn downsample(&self, texture: Texture2D, factor: f32) -> Texture2D {
+        let (w, h) = ((self.width / factor).max(1.), (self.height / factor).max(1.));
+        let target = render_target(w as u32, h as u32);
+        target.texture.set_filter(FilterMode::Linear);
+        set_camera(&Camera2D {
+            render_target: Some(target),
+            viewport: Some((0, 0, w as i32, h as i32)),
+            ..Camera2D::from_display_rect(Rect::new(0., 0., self.width, self.height))
+        });
+        self.cover(texture, WHITE);
+        // drawn once at setup, outside the frame loop that flushes everything else
+        unsafe { get_internal_gl() }.flush();
+        set_default_camera();
+        target.texture
+    }
+
+    /// Lays a [`Overlay::downsample`]d background under what has been drawn,
+    /// blown up by `zoom` around the centre and moved by `offset` (fractions of
+    /// the frame).
+    pub fn backdrop(&self, texture: Texture2D, material: Material, zoom: f32, offset: (f32, f32), brightness: f32) {
+        let (w, h) = (self.width * zoom, self.height * zoom);
+        gl_use_material(material);
+        draw_texture_ex(
+            texture,
+            (self.width - w) / 2. + offset.0 * self.width,
+            (self.height - h) / 2. + offset.1 * self.height,
+            Color::new(brightness, brightness, brightness, 1.),
+            DrawTextureParams {
+                dest_size: Some(vec2(w, h)),
+                // render targets come out upside down
+                flip_y: true,
+                ..Default::default()
+            },
+        );
+        gl_use_default_material();
+    }
+
+    /// Brightens or darkens each cell of a `grid` over the whole frame by
+    /// `strength` (0 to 1), row-major `pattern` telling which.
+    pub fn cells(&self, grid: (usize, usize), pattern: &[bool], (brighten, darken): (Material, Material), strength: f32) {
+        let color = Color::new(strength, strength, strength, 0.);
+        let cell = |i: usize| {
+            let (x, y) = (i % grid.0, i / grid.0);
+            let left = self.width * x as f32 / grid.0 as f32;
+            let top = self.height * y as f32 / grid.1 as f32;
+            let right = self.width * (x + 1) as f32 / grid.0 as f32;
+            let bottom = self.height * (y + 1) as f32 / grid.1 as f32;
+            draw_rectangle(left, top, right - left, bottom - top, color);
+        };
+        for (material, bright) in [(brighten, true), (darken, false)] {
+            gl_use_material(material);
+            for (i, _) in pattern.iter().enumerate().filter(|(_, it)| **it == bright) {
+                cell(i);
+            }
+        }
+        gl_use_default_material();
+    }
+
+    /// Draws `lines` on a translucent backdrop, anchored like [`Overlay::text`].
+    pub fn panel(&self, lines: &[String], x: f32, y: f32, size: f32, anchor: (f32, f32), alpha: f32) -> Rect {
+        let font_size = size.max(1.) as u16;
+        let line_height = size * 1.3;
+        let padding = size * 0.6;
+        let width = lines
+            .iter()
+            .map(|line| measure_text(line, Some(self.font), font_size, 1.).width)
+            .fold(0., f32::max)
+            + padding * 2.;
+        let height = line_height * lines.len() as f32 + padding * 2.;
+        let rect = Rect::new(x - width * anchor.0, y - height * anchor.1, width, height);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::new(0., 0., 0., 0.5 * alpha));
+        for (i, line) in lines.iter().enumerate() {
+            self.text(
+                line,
+                rect.x + padding,
+                rect.y + padding + line_height * i as f32,
+                size,
+                (0., 0.),
+                Color::new(1., 1., 1., alpha),
+            );
+        }
+        rect
+    }
+
+    /// Plots each series of normalized `(x, y)` points inside `rect`.
+    pub fn graph(&self, rect: Rect, series: &[(Vec<(f32, f32)>, Color)], alpha: f32) {
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, Color::new(0., 0., 0., 0.5 * alpha));
+        let thickness = (rect.h / 80.).max(1.);
+        let point = |(x, y): (f32, f32)| (rect.x + x * rect.w, rect.y + (1. - y) * rect.h);
+        for (points, color) in series {
+            let color = Color::new(color.r, color.g, color.b, color.a * alpha);
+            for pair in points.windows(2) {
+                let (x1, y1) = point(pair[0]);
+                let (x2, y2) = point(pair[1]);
+                draw_line(x1, y1, x2, y2, thickness, color);
+            }
+        }
+    }
+
+    /// Song progress indicator for the styles prpr doesn't draw itself.
+    pub fn progress(&self, style: ProgressBarStyle, progress: f32) {
+        let (w, h) = (self.width, self.height);
+        let color = Color::new(1., 1., 1., 0.8);
+        match style {
+            ProgressBarStyle::Line => {
+                draw_rectangle(0., 0., w * progress, (h * 0.004).max(1.), color);
+            }
+            ProgressBarStyle::Circle => {
+                let (cx, cy, r) = (w - h * 0.06, h * 0.14, h * 0.025);
+                let thickness = (h * 0.005).max(1.);
+                draw_circle_lines(cx, cy, r, thickness, Color::new(1., 1., 1., 0.25));
+                let segments = (64. * progress).ceil() as usize;
+                for i in 0..segments {
+                    let angle = |i: usize| {
+                        (i as f32 / segments as f32 * progress - 0.25) * std::f32::consts::TAU
+                    };
+                    let (a1, a2) = (angle(i), angle(i + 1));
+                    draw_line(
+                        cx + r * a1.cos(),
+                        cy + r * a1.sin(),
+                        cx + r * a2.cos(),
+                        cy + r * a2.sin(),
+                        thickness,
+                        color,
+                    );
+                }
+            }
+            ProgressBarStyle::Bar | ProgressBarStyle::Hidden => {}
+        }
+    }
+
+    /// Combo counter at the top centre, `scale` multiplying its usual size.
+    pub fn combo(&self, combo: usize, label: &str, scale: f32) {
+        let (w, h) = (self.width, self.height);
+        let rect = self.text(&combo.to_string(), w / 2., h * 0.02, h * 0.07 * scale, (0.5, 0.), WHITE);
+        self.text(label, w / 2., rect.y + rect.h + h * 0.005, h * 0.022, (0.5, 0.), WHITE);
+    }
+
+    /// Notes-per-second readout at the bottom centre, over `curve` (if any) with
+    /// a cursor at `progress`.
+    pub fn nps(&self, nps: usize, curve: &[(Vec<(f32, f32)>, Color)], progress: f32) {
+        let (w, h) = (self.width, self.height);
+        let label = format!("{nps} NPS");
+        if curve.is_empty() {
+            self.text(&label, w / 2., h * 0.97, h * 0.03, (0.5, 1.), WHITE);
+            return;
+        }
+        let rect = Rect::new(w * 0.38, h * 0.91, w * 0.24, h * 0.06);
+        self.graph(rect, curve, 1.);
+        let x = rect.x + rect.w * progress;
+        draw_line(x, rect.y, x, rect.y + rect.h, (h / 400.).max(1.), Color::new(1., 0.85, 0.3, 1.));
+        self.text(&label, w / 2., rect.y - h * 0.005, h * 0.025, (0.5, 1.), WHITE);
+    }
+
+    /// Ruler along the bottom edge with `beats` (chart times of every beat)
+    /// scrolling past a cursor at `now`, `span` seconds either side of it. Every
+    /// `per_measure`th beat is a numbered measure line.
+    pub fn beat_grid(&self, beats: &[f32], now: f32, span: f32, per_measure: usize) {
+        let (w, h) = (self.width, self.height);
+        let (top, height) = (h * 0.955, h * 0.045);
+        draw_rectangle(0., top, w, height, Color::new(0., 0., 0., 0.35));
+        let start = beats.partition_point(|&it| it < now - span);
+        for (i, &time) in beats.iter().enumerate().skip(start) {
+            if time > now + span {
+                break;
+            }
+            let x = w / 2. + (time - now) / span * w / 2.;
+            if i % per_measure == 0 {
+                draw_line(x, top, x, h, (h / 300.).max(1.), Color::new(1., 1., 1., 0.9));
+                self.text(&(i / per_measure + 1).to_string(), x + h * 0.004, top, height * 0.45, (0., 0.), WHITE);
+            } else {
+                draw_line(x, top + height * 0.5, x, h, (h / 600.).max(1.), Color::new(1., 1., 1., 0.5));
+            }
+        }
+        draw_line(w / 2., top - h * 0.01, w / 2., h, (h / 300.).max(1.), Color::new(1., 0.85, 0.3, 1.));
+    }
+
+    /// Audio visualizer along the bottom edge, `height` being a fraction of the
+    /// frame height: bars for band levels, or a line through waveform samples.
+    pub fn visualizer(&self, values: &[f32], waveform: bool, height: f32) {
+        if values.is_empty() {
+            return;
+        }
+        let (w, h) = (self.width, self.height);
+        let height = h * height;
+        let color = Color::new(1., 1., 1., 0.45);
+        if waveform {
+            let mid = h - height / 2.;
+            let step = w / (values.len() - 1).max(1) as f32;
+            let thickness = (h / 400.).max(1.);
+            for (i, pair) in values.windows(2).enumerate() {
+                let x = step * i as f32;
+                draw_line(x, mid - pair[0] * height / 2., x + step, mid - pair[1] * height / 2., thickness, color);
+            }
+        } else {
+            let slot = w / values.len() as f32;
+            for (i, &value) in values.iter().enumerate() {
+                let bar = height * value;
+                draw_rectangle(slot * i as f32 + slot * 0.15, h - bar, slot * 0.7, bar, color);
+            }
+        }
+    }
+
+    /// Timed lyrics centred on `position` (fractions of the frame), each
+    /// `(text, alpha)` stacked below the previous one.
+    pub fn lyrics(&self, lines: &[(&str, f32)], font: Option<Font>, position: (f32, f32), size: f32, color: Color) {
+        let size = self.height * size;
+        let font_size = size.max(1.) as u16;
+        let font = font.unwrap_or(self.font);
+        let rows: Vec<(&str, f32)> = lines
+            .iter()
+            .flat_map(|&(text, alpha)| text.lines().map(move |line| (line, alpha)))
+            .collect();
+        let line_height = size * 1.25;
+        let top = self.height * position.1 - line_height * rows.len() as f32 / 2.;
+        for (i, (line, alpha)) in rows.into_iter().enumerate() {
+            let dim = measure_text(line, Some(font), font_size, 1.);
+            let x = self.width * position.0 - dim.width / 2.;
+            let y = top + line_height * i as f32 + dim.offset_y;
+            let params = |color: Color| TextParams {
+                font,
+                font_size,
+                color,
+                ..Default::default()
+            };
+            // cheap outline so the text reads on any background
+            let shadow = Color::new(0., 0., 0., 0.6 * alpha * color.a);
+            let d = (size / 24.).max(1.);
+            for (dx, dy) in [(-d, 0.), (d, 0.), (0., -d), (0., d)] {
+                draw_text_ex(line, x + dx, y + dy, params(shadow));
+            }
+            draw_text_ex(line, x, y, params(Color::new(color.r, color.g, color.b, color.a * alpha)));
+        }
+    }
+
+    /// Credits scrolling from the bottom to the top of the right half of the
+    /// frame as `progress` goes from 0 to 1.
+    pub fn roll(&self, lines: &[String], progress: f32) {
+        let (w, h) = (self.width, self.height);
+        let size = h * 0.032;
+        let line_height = size * 1.5;
+        let total = line_height * lines.len() as f32;
+        let top = h - (h + total) * progress.clamp(0., 1.);
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + line_height * i as f32;
+            if y < -line_height || y > h {
+                continue;
+            }
+            // fade near the edges
+            let alpha = (y.min(h - y) / (h * 0.1)).clamp(0., 1.);
+            self.text(line, w * 0.78, y, size, (0.5, 0.), Color::new(1., 1., 1., alpha));
+        }
+    }
+
+    /// Draws a scripted [`OverlayItem`], with `texture` being its loaded image if it has one.
+    pub fn item(&self, item: &OverlayItem, texture: Option<Texture2D>, color: Color, alpha: f32) {
+        let (w, h) = (self.width, self.height);
+        let (x, y) = (w * item.position.0, h * item.position.1);
+        let size = h * item.style.size;
+        let anchor = item.style.anchor;
+        let color = Color::new(color.r, color.g, color.b, color.a * alpha);
+        if let Some(texture) = texture {
+            let height = size;
+            let width = height * texture.width() / texture.height().max(1.);
+            draw_texture_ex(
+                texture,
+                x - width * anchor.0,
+                y - height * anchor.1,
+                color,
+                DrawTextureParams {
+                    dest_size: Some(vec2(width, height)),
+                    ..Default::default()
+                },
+            );
+        } else if let Some(text) = &item.text {
+            let lines: Vec<String> = text.lines().map(str::to_owned).collect();
+            if item.style.backdrop {
+                self.panel(&lines, x, y, size, anchor, alpha);
+                return;
+            }
+            let line_height = size * 1.3;
+            let top = y - line_height * lines.len() as f32 * anchor.1;
+            for (i, line) in lines.iter().enumerate() {
+                self.text(line, x, top + line_height * i as f32, size, (anchor.0, 0.), color);
+            }
+        }
+    }
+
+    /// Title card shown in place of the loading scene when it is disabled.
+    pub fn splash(&self, background: Option<Texture2D>, title: &str, subtitle: &str) {
+        let (w, h) = (self.width, self.height);
+        draw_rectangle(0., 0., w, h, BLACK);
+        if let Some(background) = background {
+            self.cover(background, Color::new(0.5, 0.5, 0.5, 1.));
+        }
+        self.text(title, w / 2., h / 2., h * 0.08, (0.5, 1.), WHITE);
+        self.text(subtitle, w / 2., h / 2. + h * 0.02, h * 0.04, (0.5, 0.), Color::new(1., 1., 1., 0.8));
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    io::{self, Write},
+    process::ChildStdin,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Memory the queued frames may take when no size is configured.
+const QUEUE_BUDGET: usize = 256 << 20;
+const MAX_QUEUE: usize = 16;
+/// Span over which the time spent waiting on ffmpeg is measured.
+const WINDOW: Duration = Duration::from_secs(5);
+/// Share of a window spent waiting that counts as backpressure.
+const BACKPRESSURE_SHARE: f32 = 0.5;
+/// Windows in a row with backpressure before it is reported.
+const SUSTAINED_WINDOWS: u32 = 3;
+
+/// Number of frames the queue holds: `count` if given, otherwise as many as fit
+/// into [`QUEUE_BUDGET`].
+pub fn queue_size((width, height): (u32, u32), count: Option<u32>) -> usize {
+    if let Some(count) = count {
+        return (count as usize).max(1);
+    }
+    let byte_size = width as usize * height as usize * 4;
+    (QUEUE_BUDGET / byte_size.max(1)).clamp(2, MAX_QUEUE)
+}
+
+/// Frames on their way to ffmpeg, written from a thread of their own so that a
+/// slow encoder holds up the render loop only once the queue is full.
+///
+/// Every `write` call is taken as one frame, which is how `Readback::drain`
+/// hands them over.
+pub struct FrameQueue {
+    sender: Option<SyncSender<Vec<u8>>>,
+    recycled: Receiver<Vec<u8>>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    window: Instant,
+    waited: Duration,
+    sustained: u32,
+    reported: bool,
+}
+
+impl FrameQueue {
+    pub fn new(mut output: ChildStdin, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(capacity);
+        let (recycle, recycled) = mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            for frame in receiver {
+                output.write_all(&frame)?;
+                let _ = recycle.send(frame);
+            }
+            output.flush()
+        });
+        Self {
+            sender: Some(sender),
+            recycled,
+            writer: Some(writer),
+            window: Instant::now(),
+            waited: Duration::ZERO,
+            sustained: 0,
+            reported: false,
+        }
+    }
+
+    /// The error that stopped the writer, once it has stopped.
+    fn writer_error(&mut self) -> io::Error {
+        self.sender = None;
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(Err(err))) => err,
+            _ => io::ErrorKind::BrokenPipe.into(),
+        }
+    }
+
+    /// Share of the time spent waiting on ffmpeg, the first time it has stayed
+    /// above [`BACKPRESSURE_SHARE`] for [`SUSTAINED_WINDOWS`] windows.
+    pub fn backpressure(&mut self) -> Option<f32> {
+        let elapsed = self.window.elapsed();
+        if elapsed < WINDOW {
+            return None;
+        }
+        let share = self.waited.as_secs_f32() / elapsed.as_secs_f32();
+        self.window = Instant::now();
+        self.waited = Duration::ZERO;
+        self.sustained = if share > BACKPRESSURE_SHARE { self.sustained + 1 } else { 0 };
+        if self.sustained >= SUSTAINED_WINDOWS && !self.reported {
+            self.reported = true;
+            return Some(share);
+        }
+        None
+    }
+
+    /// Waits for every queued frame to be written and closes ffmpeg's stdin.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.sender = None;
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::new(io::ErrorKind::Other, "frame writer panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for FrameQueue {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(sender) = &self.sender else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+        let mut frame = self.recycled.try_recv().unwrap_or_default();
+        frame.clear();
+        frame.extend_from_slice(buf);
+        match sender.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(frame)) => {
+                let start = Instant::now();
+                if sender.send(frame).is_err() {
+                    return Err(self.writer_error());
+                }
+                self.waited += start.elapsed();
+            }
+            Err(TrySendError::Disconnected(_)) => return Err(self.writer_error()),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for FrameQueue {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
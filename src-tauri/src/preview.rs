@@ -1,10 +1,15 @@
-use crate::render::{build_player, RenderConfig, RenderParams};
+use crate::{
+    archive,
+    mods,
+    render::{build_player, line_texture, RenderConfig, RenderParams},
+};
 use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use prpr::{
     config::{Config, Mods}, core::init_assets, fs, scene::{show_error, GameMode, LoadingScene, NextScene, Scene}, time::TimeManager, ui::{FontArc, TextPainter, Ui}, Main
 };
 use std::{io::BufRead, ops::DerefMut};
+use tracing::{info, warn};
 
 struct BaseScene(Option<NextScene>, bool);
 impl Scene for BaseScene {
@@ -67,7 +72,7 @@ pub async fn main(cmd: bool, tweak_offset: bool) -> Result<()> {
         };
         let path = std::env::args().nth(2).unwrap();
 
-        let mut fs = fs::fs_from_file(path.as_ref())?;
+        let mut fs = archive::open(path.as_ref())?;
         let info = fs::load_info(fs.deref_mut()).await?;
 
         (fs, config, info)
@@ -80,16 +85,18 @@ pub async fn main(cmd: bool, tweak_offset: bool) -> Result<()> {
     
         let mut line = String::new();
         stdin.read_line(&mut line)?;
-        let params: RenderParams = serde_json::from_str(line.trim())?;
+        let mut params: RenderParams = serde_json::from_str(line.trim())?;
+        params.apply_offset_delta();
         let path = params.path;
     
-        let fs = fs::fs_from_file(&path)?;
+        let fs = archive::open(&path)?;
     
         let config = params.config;
         let info = params.info;
 
         (fs, config, info)
     };
+    let fs = mods::wrap(fs, &info.chart, config.chart_mods(), line_texture(&config)?);
 
 
     let mut prpr_config: Config = config.to_config();
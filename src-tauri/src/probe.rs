@@ -0,0 +1,316 @@
+prpr::tl_file!("render");
+
+use crate::archive;
+use anyhow::{Context, Result};
+use macroquad::prelude::*;
+use prpr::{
+    core::{Chart, NoteKind},
+    fs,
+    info::ChartInfo,
+    scene::GameScene,
+};
+use sasa::AudioClip;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    ops::DerefMut,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteCounts {
+    pub tap: usize,
+    pub drag: usize,
+    pub hold: usize,
+    pub flick: usize,
+    /// Fake notes, not included in the other counts
+    pub fake: usize,
+    pub total: usize,
+}
+
+impl NoteCounts {
+    pub fn of(chart: &Chart) -> Self {
+        let mut counts = Self::default();
+        for note in chart.lines.iter().flat_map(|line| line.notes.iter()) {
+            if note.fake {
+                counts.fake += 1;
+                continue;
+            }
+            match note.kind {
+                NoteKind::Click => counts.tap += 1,
+                NoteKind::Drag => counts.drag += 1,
+                NoteKind::Hold { .. } => counts.hold += 1,
+                NoteKind::Flick => counts.flick += 1,
+            }
+            counts.total += 1;
+        }
+        counts
+    }
+}
+
+/// What the UI wants to know about a chart before rendering it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartProbe {
+    pub info: ChartInfo,
+    /// Length of the music, in seconds
+    pub music_length: f64,
+    /// Chart time at which the last note is done, in seconds
+    pub chart_length: f32,
+    /// Offset stored in the chart file itself, on top of `info.offset`
+    pub offset: f32,
+    pub line_count: usize,
+    pub notes: NoteCounts,
+}
+
+/// Probes of the charts opened in the app, as printed by the `probe` subcommand,
+/// each kept with the [`archive::fingerprint`] of the chart it was made from.
+#[derive(Default)]
+pub struct ProbeCache(Mutex<HashMap<PathBuf, (String, String)>>);
+
+impl ProbeCache {
+    /// The probe of `path`, unless the chart has changed since.
+    pub fn get(&self, path: &Path, fingerprint: &str) -> Option<ChartProbe> {
+        let cache = self.0.lock().unwrap();
+        let (cached, probe) = cache.get(path)?;
+        (cached == fingerprint).then(|| serde_json::from_str(probe).ok()).flatten()
+    }
+
+    pub fn insert(&self, path: PathBuf, fingerprint: String, probe: String) {
+        self.0.lock().unwrap().insert(path, (fingerprint, probe));
+    }
+}
+
+/// Chart time at which the last note (including hold tails) is done.
+pub fn chart_length(chart: &Chart) -> f32 {
+    chart
+        .lines
+        .iter()
+        .flat_map(|line| line.notes.iter())
+        .map(|note| match note.kind {
+            NoteKind::Hold { end_time, .. } => end_time,
+            _ => note.time,
+        })
+        .fold(0., f32::max)
+}
+
+pub async fn load(path: &Path) -> Result<(ChartInfo, Chart, AudioClip)> {
+    let mut fs = archive::open(path)?;
+    let info = fs::load_info(fs.deref_mut()).await?;
+    let (chart, ..) = GameScene::load_chart(fs.deref_mut(), &info)
+        .await
+        .with_context(|| tl!("load-chart-failed"))?;
+    let music: Result<_> = async { AudioClip::new(fs.load_file(&info.music).await?) }.await;
+    let music = music.with_context(|| tl!("load-music-failed"))?;
+    Ok((info, chart, music))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BpmSection {
+    /// Chart time the section starts at, in seconds
+    pub start: f32,
+    pub bpm: f32,
+}
+
+/// Density report written by the `stats` subcommand.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartStats {
+    pub notes: NoteCounts,
+    pub length: f32,
+    /// Notes hit in each whole second of the chart
+    pub nps: Vec<u32>,
+    pub average_nps: f32,
+    /// Most notes within any one-second window, and where that window starts
+    pub peak_nps: u32,
+    pub peak_time: f32,
+    /// Share of the chart's length during which at least one hold is held
+    pub hold_coverage: f32,
+    pub bpm_sections: Vec<BpmSection>,
+}
+
+/// Step used to sample the BPM curve, in seconds.
+const BPM_STEP: f32 = 0.05;
+
+impl ChartStats {
+    pub fn of(chart: &Chart) -> Self {
+        let length = chart_length(chart);
+        let real = || {
+            chart
+                .lines
+                .iter()
+                .flat_map(|line| line.notes.iter())
+                .filter(|note| !note.fake)
+        };
+
+        let mut times: Vec<f32> = real().map(|note| note.time).collect();
+        times.sort_by(|x, y| x.total_cmp(y));
+        let mut nps = vec![0; length.ceil().max(1.) as usize];
+        for &time in &times {
+            if let Some(count) = nps.get_mut(time.max(0.) as usize) {
+                *count += 1;
+            }
+        }
+        let (mut peak_nps, mut peak_time) = (0, 0.);
+        let mut end = 0;
+        for (start, &time) in times.iter().enumerate() {
+            while end < times.len() && times[end] < time + 1. {
+                end += 1;
+            }
+            if (end - start) as u32 > peak_nps {
+                peak_nps = (end - start) as u32;
+                peak_time = time;
+            }
+        }
+
+        let mut holds: Vec<(f32, f32)> = real()
+            .filter_map(|note| match note.kind {
+                NoteKind::Hold { end_time, .. } => Some((note.time, end_time)),
+                _ => None,
+            })
+            .collect();
+        holds.sort_by(|x, y| x.0.total_cmp(&y.0));
+        let mut covered = 0.;
+        let mut current: Option<(f32, f32)> = None;
+        for (start, end) in holds {
+            match &mut current {
+                Some(range) if start <= range.1 => range.1 = range.1.max(end),
+                _ => {
+                    if let Some((s, e)) = current {
+                        covered += e - s;
+                    }
+                    current = Some((start, end));
+                }
+            }
+        }
+        if let Some((s, e)) = current {
+            covered += e - s;
+        }
+
+        let mut bpm_sections: Vec<BpmSection> = Vec::new();
+        {
+            let mut bpm_list = chart.bpm_list.borrow_mut();
+            let mut time = 0.;
+            while time < length.max(BPM_STEP) {
+                let bpm = (bpm_list.beat(time + BPM_STEP) - bpm_list.beat(time)) / BPM_STEP * 60.;
+                let bpm = (bpm * 100.).round() / 100.;
+                if !bpm_sections.last().is_some_and(|it| (it.bpm - bpm).abs() <= 0.01) {
+                    bpm_sections.push(BpmSection { start: time, bpm });
+                }
+                time += BPM_STEP;
+            }
+        }
+
+        Self {
+            notes: NoteCounts::of(chart),
+            length,
+            average_nps: if length > 0. { times.len() as f32 / length } else { 0. },
+            nps,
+            peak_nps,
+            peak_time,
+            hold_coverage: if length > 0. { (covered / length).min(1.) } else { 0. },
+            bpm_sections,
+        }
+    }
+}
+
+/// One note of the chart as exported by `noteTimeline`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteEvent {
+    pub line: usize,
+    pub kind: &'static str,
+    /// Chart time of the hit, in seconds
+    pub time: f32,
+    /// Chart time at which a hold is released
+    pub end_time: Option<f32>,
+    /// Time of the hit in the video, and the frame it lands on
+    pub video_time: f64,
+    pub frame: u64,
+    /// Horizontal offset along the line at the time of the hit, in prpr's units
+    pub position_x: f32,
+    pub above: bool,
+    pub fake: bool,
+}
+
+/// Every note of `chart` in hit order, `video_time` mapping chart time to video time.
+pub fn note_events(chart: &Chart, video_time: impl Fn(f32) -> f64, fps: u32) -> Vec<NoteEvent> {
+    let mut events: Vec<NoteEvent> = chart
+        .lines
+        .iter()
+        .enumerate()
+        .flat_map(|(line, it)| it.notes.iter().map(move |note| (line, note)))
+        .map(|(line, note)| {
+            let (kind, end_time) = match note.kind {
+                NoteKind::Click => ("tap", None),
+                NoteKind::Drag => ("drag", None),
+                NoteKind::Hold { end_time, .. } => ("hold", Some(end_time)),
+                NoteKind::Flick => ("flick", None),
+            };
+            let mut x = note.object.translation.0.clone();
+            x.set_time(note.time);
+            let video_time = video_time(note.time);
+            NoteEvent {
+                line,
+                kind,
+                time: note.time,
+                end_time,
+                video_time,
+                frame: (video_time.max(0.) * fps as f64).round() as u64,
+                position_x: x.now(),
+                above: note.above,
+                fake: note.fake,
+            }
+        })
+        .collect();
+    events.sort_by(|x, y| x.time.total_cmp(&y.time));
+    events
+}
+
+pub fn note_events_csv(events: &[NoteEvent]) -> String {
+    let mut csv = String::from("line,kind,time,endTime,videoTime,frame,positionX,above,fake\n");
+    for it in events {
+        let end_time = it.end_time.map(|it| it.to_string()).unwrap_or_default();
+        let _ = writeln!(
+            csv,
+            "{},{},{},{end_time},{},{},{},{},{}",
+            it.line, it.kind, it.time, it.video_time, it.frame, it.position_x, it.above, it.fake
+        );
+    }
+    csv
+}
+
+/// `stats <asset path> <chart path> <output path>`: writes a [`ChartStats`] report as JSON.
+pub async fn stats_main() -> Result<()> {
+    set_pc_assets_folder(&std::env::args().nth(2).unwrap());
+    let path: PathBuf = std::env::args().nth(3).context("missing chart path")?.into();
+    let output: PathBuf = std::env::args().nth(4).context("missing output path")?.into();
+
+    let (_, chart, _) = load(&path).await?;
+    let stats = ChartStats::of(&chart);
+    std::fs::write(&output, serde_json::to_string_pretty(&stats)?)
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    Ok(())
+}
+
+/// `probe <asset path> <chart path>`: prints a [`ChartProbe`] as one JSON line.
+pub async fn main() -> Result<()> {
+    set_pc_assets_folder(&std::env::args().nth(2).unwrap());
+    let path: PathBuf = std::env::args().nth(3).context("missing chart path")?.into();
+
+    let (info, chart, music) = load(&path).await?;
+    let probe = ChartProbe {
+        music_length: music.length() as f64,
+        chart_length: chart_length(&chart),
+        offset: chart.offset,
+        line_count: chart.lines.len(),
+        notes: NoteCounts::of(&chart),
+        info,
+    };
+    println!("{}", serde_json::to_string(&probe)?);
+    Ok(())
+}
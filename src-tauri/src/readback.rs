@@ -0,0 +1,250 @@
+use anyhow::Result;
+use macroquad::miniquad::gl::*;
+use std::{ffi::CStr, io::Write};
+use tracing::warn;
+
+/// Upper bound for the PBO ring, which was fixed at this size before it became configurable.
+const MAX_PBOS: usize = 60;
+/// Share of the memory reported by the driver that the PBO ring may take.
+const PBO_VRAM_SHARE: usize = 4;
+const PBO_BUDGET: usize = 512 << 20;
+
+const GL_MAP_READ_BIT: GLbitfield = 0x0001;
+const GL_MAP_PERSISTENT_BIT: GLbitfield = 0x0040;
+const GL_MAP_COHERENT_BIT: GLbitfield = 0x0080;
+const GL_SYNC_GPU_COMMANDS_COMPLETE: GLenum = 0x9117;
+const GL_SYNC_FLUSH_COMMANDS_BIT: GLbitfield = 0x0001;
+const GL_TIMEOUT_EXPIRED: GLenum = 0x911B;
+const GL_WAIT_FAILED: GLenum = 0x911D;
+
+/// Number of pixel buffers to cycle through for readback: `count` if given,
+/// otherwise as many frames as fit into [`PBO_BUDGET`] (or a quarter of free VRAM
+/// when the driver reports it).
+pub fn ring_size((width, height): (u32, u32), count: Option<u32>) -> usize {
+    if let Some(count) = count {
+        return (count as usize).max(2);
+    }
+    let byte_size = width as usize * height as usize * 4;
+    let mut budget = PBO_BUDGET;
+    unsafe {
+        // GL_GPU_MEMORY_INFO_CURRENT_AVAILABLE_VIDMEM_NVX, in KiB
+        let mut available: GLint = 0;
+        glGetIntegerv(0x9049, &mut available);
+        glGetError();
+        if available > 0 {
+            budget = budget.min(available as usize * 1024 / PBO_VRAM_SHARE);
+        }
+    }
+    (budget / byte_size.max(1)).clamp(2, MAX_PBOS)
+}
+
+fn gl_string(name: GLenum) -> String {
+    unsafe {
+        let ptr = glGetString(name);
+        if ptr.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr(ptr as *const _).to_string_lossy().into_owned()
+    }
+}
+
+/// The kind of context the window ended up with.
+pub struct GlInfo {
+    pub es: bool,
+    pub major: u32,
+    pub minor: u32,
+    pub renderer: String,
+    pub version: String,
+}
+
+impl GlInfo {
+    pub fn current() -> Self {
+        let version = gl_string(GL_VERSION);
+        let renderer = gl_string(GL_RENDERER);
+        // e.g. "4.6.0 NVIDIA 535.54", "OpenGL ES 3.0 (ANGLE 2.1.0)"
+        let es = version.starts_with("OpenGL ES");
+        let mut numbers = version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|it| !it.is_empty())
+            .map(|it| it.parse().unwrap_or(0));
+        Self {
+            es,
+            major: numbers.next().unwrap_or(0),
+            minor: numbers.next().unwrap_or(0),
+            renderer,
+            version,
+        }
+    }
+
+    /// Whether `glBufferStorage` is core, which is never the case on GLES.
+    pub fn has_buffer_storage(&self) -> bool {
+        !self.es && (self.major, self.minor) >= (4, 4)
+    }
+}
+
+/// Ring of pixel pack buffers that frames are read into asynchronously and
+/// drained from a few frames later.
+///
+/// On desktop GL 4.4+ the buffers are allocated with immutable storage and stay
+/// mapped for their whole lifetime, with a fence per slot telling when the copy
+/// has landed; otherwise (older GL, GLES) every drain maps and unmaps the buffer.
+/// Only `glMapBufferRange` is used for mapping since GLES has no `glMapBuffer`.
+pub struct Readback {
+    pbos: Vec<GLuint>,
+    mapped: Vec<*const u8>,
+    fences: Vec<GLsync>,
+    width: u32,
+    height: u32,
+    byte_size: usize,
+}
+
+impl Readback {
+    pub fn new(count: usize, resolution: (u32, u32), gl: &GlInfo) -> Self {
+        if gl.has_buffer_storage() {
+            if let Some(this) = Self::persistent(count, resolution) {
+                return this;
+            }
+            warn!("Persistent buffer mapping failed, falling back to per-frame mapping");
+        }
+        Self::allocate(count, resolution, false)
+    }
+
+    fn persistent(count: usize, resolution: (u32, u32)) -> Option<Self> {
+        let mut this = Self::allocate(count, resolution, true);
+        let flags = GL_MAP_READ_BIT | GL_MAP_PERSISTENT_BIT | GL_MAP_COHERENT_BIT;
+        unsafe {
+            for &pbo in &this.pbos {
+                glBindBuffer(GL_PIXEL_PACK_BUFFER, pbo);
+                let ptr = glMapBufferRange(GL_PIXEL_PACK_BUFFER, 0, this.byte_size as _, flags);
+                if ptr.is_null() {
+                    glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+                    // unmaps whatever did get mapped
+                    this.release();
+                    return None;
+                }
+                this.mapped.push(ptr as *const u8);
+            }
+            glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+        }
+        this.fences = vec![std::ptr::null_mut(); count];
+        Some(this)
+    }
+
+    fn allocate(count: usize, (width, height): (u32, u32), immutable: bool) -> Self {
+        let byte_size = width as usize * height as usize * 4;
+        let mut pbos = vec![0; count];
+        unsafe {
+            glGenBuffers(count as _, pbos.as_mut_ptr());
+            for &pbo in &pbos {
+                glBindBuffer(GL_PIXEL_PACK_BUFFER, pbo);
+                if immutable {
+                    let flags = GL_MAP_READ_BIT | GL_MAP_PERSISTENT_BIT | GL_MAP_COHERENT_BIT;
+                    glBufferStorage(GL_PIXEL_PACK_BUFFER, byte_size as _, std::ptr::null(), flags);
+                } else {
+                    glBufferData(GL_PIXEL_PACK_BUFFER, byte_size as _, std::ptr::null(), GL_STREAM_READ);
+                }
+            }
+            glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+        }
+        Self {
+            pbos,
+            mapped: Vec::new(),
+            fences: Vec::new(),
+            width,
+            height,
+            byte_size,
+        }
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        !self.mapped.is_empty()
+    }
+
+    /// Starts copying the color attachment of `framebuffer` into slot `index % len`.
+    pub fn read(&mut self, index: usize, framebuffer: GLuint) {
+        let slot = index % self.pbos.len();
+        unsafe {
+            glBindFramebuffer(GL_READ_FRAMEBUFFER, framebuffer);
+            glBindBuffer(GL_PIXEL_PACK_BUFFER, self.pbos[slot]);
+            glReadPixels(
+                0,
+                0,
+                self.width as _,
+                self.height as _,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                std::ptr::null_mut(),
+            );
+            glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+            if self.is_persistent() {
+                if !self.fences[slot].is_null() {
+                    glDeleteSync(self.fences[slot]);
+                }
+                self.fences[slot] = glFenceSync(GL_SYNC_GPU_COMMANDS_COMPLETE, 0);
+            }
+        }
+    }
+
+    /// Writes the frame held by slot `index % len` to `output` once the GPU is done with it.
+    pub fn drain(&mut self, index: usize, output: &mut impl Write) -> Result<()> {
+        let slot = index % self.pbos.len();
+        unsafe {
+            if self.is_persistent() {
+                let fence = std::mem::replace(&mut self.fences[slot], std::ptr::null_mut());
+                if !fence.is_null() {
+                    loop {
+                        let status = glClientWaitSync(fence, GL_SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000);
+                        if status != GL_TIMEOUT_EXPIRED {
+                            if status == GL_WAIT_FAILED {
+                                warn!("glClientWaitSync failed, frame may be incomplete");
+                            }
+                            break;
+                        }
+                    }
+                    glDeleteSync(fence);
+                }
+                output.write_all(std::slice::from_raw_parts(self.mapped[slot], self.byte_size))?;
+            } else {
+                glBindBuffer(GL_PIXEL_PACK_BUFFER, self.pbos[slot]);
+                let src = glMapBufferRange(GL_PIXEL_PACK_BUFFER, 0, self.byte_size as _, GL_MAP_READ_BIT);
+                if !src.is_null() {
+                    let result = output.write_all(std::slice::from_raw_parts(src as *const u8, self.byte_size));
+                    glUnmapBuffer(GL_PIXEL_PACK_BUFFER);
+                    glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+                    result?;
+                } else {
+                    glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn release(&mut self) {
+        unsafe {
+            for fence in self.fences.drain(..) {
+                if !fence.is_null() {
+                    glDeleteSync(fence);
+                }
+            }
+            if !self.mapped.is_empty() {
+                for &pbo in &self.pbos[..self.mapped.len()] {
+                    glBindBuffer(GL_PIXEL_PACK_BUFFER, pbo);
+                    glUnmapBuffer(GL_PIXEL_PACK_BUFFER);
+                }
+                glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+                self.mapped.clear();
+            }
+            if !self.pbos.is_empty() {
+                glDeleteBuffers(self.pbos.len() as _, self.pbos.as_ptr());
+                self.pbos.clear();
+            }
+        }
+    }
+}
+
+impl Drop for Readback {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
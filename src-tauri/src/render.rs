@@ -1,13 +1,33 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 prpr::tl_file!("render");
 
-use crate::common::{ensure_dir, let_output_dir, output_dir, DATA_DIR};
+use crate::{
+    archive,
+    audio::{self, envelope_coeff, limit, TpdfDither},
+    bga::{self, Bga},
+    checksum,
+    common::{ensure_dir, let_output_dir, lower_priority, output_dir, pin_to_cores, KeepAwake, DATA_DIR},
+    debug::DebugOverlay,
+    ffmpeg,
+    forensic,
+    frametime::{FrameStats, FrameTime, FrameTimes},
+    line::LineTexture,
+    lyrics,
+    manifest::{Manifest, Timings},
+    mods::{self, ChartMods, NoteConversion},
+    overlay::{self, Overlay, OverlayItem},
+    probe::{self, ChartStats},
+    pacing::{queue_size, FrameQueue},
+    readback::{ring_size, GlInfo, Readback},
+    respack,
+    FFMPEG_ENV, GPU_ENV,
+};
 use chrono::Local;
 use anyhow::{bail, Context, Result};
-use macroquad::{miniquad::gl::GLuint, prelude::*};
+use macroquad::prelude::*;
 use prpr::{
     config::{ChallengeModeColor, Config, Mods},
-    core::{init_assets, internal_id, MSRenderTarget, HitSound, Note},
+    core::{init_assets, internal_id, Chart, MSRenderTarget, HitSound, Note, NoteKind},
     fs,
     info::ChartInfo,
     scene::{BasicPlayer, GameMode, GameScene, LoadingScene, EndingScene},
@@ -15,23 +35,32 @@ use prpr::{
     ui::{FontArc, TextPainter},
     Main,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use sasa::AudioClip;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
+    collections::{HashMap, VecDeque},
     io::{BufRead, BufWriter, Write},
     ops::DerefMut,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     rc::Rc,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::Instant,
 };
 use std::{ffi::OsStr, fmt::Write as _};
 use tempfile::NamedTempFile;
+use tracing::{info, warn};
 
-#[derive(Deserialize, Serialize, Default, Debug)]
-#[serde(rename_all = "camelCase")]
+/// Fields left out take their value from [`RenderConfig::default`], so presets
+/// saved by older versions and frontends that don't know newer fields still work.
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase", default)]
 pub struct RenderConfig {
     resolution: (u32, u32),
     ffmpeg_preset: String,
@@ -39,17 +68,48 @@ pub struct RenderConfig {
     disable_loading: bool,
     hires: bool,
     chart_debug: bool,
+    /// Labels the recorder writes next to each judge line, for hunting down events
+    debug_overlay: DebugOverlay,
     chart_ratio: f32,
     all_good: bool,
     all_bad: bool,
     fps: u32,
     hardware_accel: bool,
+    /// Encode with libx264/libx265 when hardware acceleration is on but no
+    /// hardware encoder works, instead of failing with `no-hwacc`
+    software_fallback: bool,
+    /// What to do when NVENC is the only hardware encoder and all its sessions are taken
+    nvenc_busy: NvencBusy,
+    /// Encoders to move on to, in order, when the one in use dies mid-render.
+    /// Setting this encodes in segments, so a restart only redoes the last one
+    encoder_fallback: Vec<String>,
+    /// Length of those segments, in seconds
+    segment_length: u32,
     hevc: bool,
     mpeg4: bool,
     bitrate_control: String,
     bitrate: String,
+    draft: bool,
+    quality: Option<Quality>,
+    /// AAC bitrate, 320k if unset
+    audio_bitrate: Option<String>,
+    /// Seconds between keyframes, left to the encoder if unset
+    keyframe_interval: Option<f32>,
+    /// Only place keyframes on the interval, not on scene changes
+    disable_scene_cut: bool,
+    b_frames: Option<u32>,
+    rc_lookahead: Option<u32>,
+    /// x264, and x265 for `animation` and `grain`
+    tune: Option<EncoderTune>,
+    /// NVENC only
+    multipass: Option<Multipass>,
+    chroma: Chroma,
+    /// Degrees (clockwise) players should rotate the video by, stored as metadata
+    /// instead of transposing the frames
+    display_rotation: i32,
 
     aggressive: bool,
+    #[schemars(with = "crate::schema::ChallengeModeColor")]
     challenge_color: ChallengeModeColor,
     challenge_rank: u32,
     disable_effect: bool,
@@ -63,19 +123,80 @@ pub struct RenderConfig {
     player_rks: f32,
     sample_count: u32,
     res_pack_path: Option<String>,
+    /// Single images or hitsounds (by file name in the pack, e.g. `flick.png`,
+    /// `hit_fx.png` or `flick.ogg`) laid over the resource pack, or over the
+    /// built-in one if none is chosen
+    skin_overrides: HashMap<String, PathBuf>,
     speed: f32,
+    speed_resample: bool,
     volume_music: f32,
     volume_sfx: f32,
     compression_ratio: f32,
+    compression_threshold: f32,
+    compression_attack: f32,
+    compression_release: f32,
     force_limit: bool,
     limit_threshold: f32,
+    limit_lookahead: Option<f32>,
+    limit_release: Option<f32>,
+    audio_bit_depth: AudioBitDepth,
+    music_fade_in: f32,
+    music_fade_out: Option<f32>,
+    splash_duration: f32,
+    splash_image: Option<String>,
+    credit_duration: f32,
+    /// What to do with `RenderParams::subtitles`
+    lyrics_mode: LyricsMode,
+    /// TTF/OTF for burned-in lyrics, the UI font if unset
+    lyrics_font: Option<String>,
+    /// Centre of the lyrics as fractions of the frame, (0.5, 0.85) if unset
+    lyrics_position: Option<(f32, f32)>,
+    /// Text height as a fraction of the frame height, 0.045 if unset
+    lyrics_size: Option<f32>,
+    /// `#RRGGBB` or `#RRGGBBAA`, white if unset
+    lyrics_color: Option<String>,
+    ending_hide_avatar: bool,
+    /// prpr prints the RKS on the player card of the results screen, so this
+    /// leaves the whole card out
+    ending_hide_rks: bool,
+    ending_show_stats: bool,
+    ending_text: String,
+    ending_graph: bool,
+    /// Scroll chart and recorder credits up the screen during the ending
+    ending_credits: bool,
+    /// Lines appended to the credits roll
+    ending_credits_extra: Vec<String>,
+    phira_mode: bool,
+    /// Overrides `phira_mode` for scoring, regardless of the chart format
+    score_formula: Option<ScoreFormula>,
     watermark: String,
+    /// Draw the watermark in the overlay with this look instead of leaving it to prpr
+    watermark_style: Option<WatermarkStyle>,
+    /// Hides a pattern derived from this string in the frames, found again by `detect`
+    forensic_key: Option<String>,
+    /// How far the pattern moves pixel values, out of 255; 2 if unset
+    forensic_strength: Option<f32>,
     roman: bool,
     chinese: bool,
     combo: String,
     difficulty: String,
     judge_offset: f32,
+    /// Flip the chart horizontally, like Phira's mirror mod
+    mirror: bool,
+    /// Turn every note into this kind
+    convert_notes: Option<NoteConversion>,
+    remove_fake_notes: bool,
+    /// Judge lines (by index in the chart) to draw, leaving out the rest
+    only_lines: Vec<usize>,
+    /// Judge lines to leave out, along with their notes
+    hidden_lines: Vec<usize>,
     simple_file_name: bool,
+    /// Write a manifest of the job, inputs and encoder next to the video
+    manifest: bool,
+    /// Keep the video-only encode and the mixed WAV next to the video
+    keep_stems: bool,
+    /// Also write every note's time, frame and position next to the video
+    note_timeline: Option<NoteTimelineFormat>,
 
     render_line: bool,
     render_line_extra: bool,
@@ -83,12 +204,65 @@ pub struct RenderConfig {
     render_ui_pause: bool,
     render_ui_score: bool,
     render_ui_combo: bool,
+    combo_min: Option<u32>,
+    combo_punch: f32,
+    combo_scale: Option<f32>,
+    /// Shake at every `shake_every`-th combo, as a fraction of the frame height
+    shake_intensity: f32,
+    /// 100 if unset
+    shake_every: Option<u32>,
+    /// Zoom in by this fraction on every `zoom_pulse_every` beats and settle back
+    zoom_pulse: f32,
+    /// 1 if unset
+    zoom_pulse_every: Option<u32>,
     render_ui_bar: bool,
     render_bg: bool,
+    /// Moves the illustration behind the chart; replaces prpr's own background
+    background_motion: BackgroundMotion,
+    /// Strength of the motion as a fraction of the frame size, 0.03 if unset
+    background_motion_amplitude: Option<f32>,
+    /// Plays a video found in the chart package behind the chart, in place of
+    /// the illustration
+    bga: bool,
+    /// Gaussian blur of the background video, in pixels of the output
+    bga_blur: f32,
+    /// Brightness of the background video, 0.4 if unset
+    bga_brightness: Option<f32>,
+    /// Chart time at which the background video starts
+    bga_offset: f32,
+    progress_bar_style: ProgressBarStyle,
+    nps_overlay: NpsOverlay,
+    /// Ruler of beats and measures from the chart's BPM events along the bottom edge
+    beat_grid: bool,
+    /// Beats in a measure of the beat grid, 4 if unset
+    beats_per_measure: Option<u32>,
+    visualizer: Visualizer,
+    /// Height of the visualizer as a fraction of the frame height, 0.12 if unset
+    visualizer_height: Option<f32>,
 
     max_particles: usize,
 
     fade: f32,
+
+    pbo_count: Option<u32>,
+    /// Frames waiting to be written to ffmpeg; sized by resolution if unset
+    frame_queue: Option<u32>,
+    low_priority: bool,
+    cpu_affinity: Vec<usize>,
+    /// Percentage of the CPU cores to run on when `cpu_affinity` is empty
+    cpu_limit: Option<u32>,
+    /// `-threads` for the software encoders, chosen by ffmpeg if unset
+    encoder_threads: Option<u32>,
+    /// Keeps the machine from sleeping until the render ends
+    keep_awake: bool,
+
+    hitsound_jitter: f32,
+    hitsound_jitter_seed: u64,
+    /// Place hitsounds earlier by the silence or soft attack at their start
+    hitsound_onset: bool,
+    /// Draws the judge lines from an image strip; also used, with default
+    /// settings, when the resource pack has a `line.png`
+    line_texture: Option<LineTexture>,
 }
 
 impl RenderConfig {
@@ -116,7 +290,11 @@ impl RenderConfig {
             chart_ratio: self.chart_ratio,
             all_good: self.all_good,
             all_bad: self.all_bad,
-            watermark: self.watermark.clone(),
+            watermark: if self.watermark_style.is_some() {
+                String::new()
+            } else {
+                self.watermark.clone()
+            },
             roman: self.roman,
             chinese: self.chinese,
             combo: self.combo.clone(),
@@ -129,17 +307,157 @@ impl RenderConfig {
             render_note: self.render_note,
             render_ui_pause: self.render_ui_pause,
             render_ui_score: self.render_ui_score,
-            render_ui_combo: self.render_ui_combo,
-            render_ui_bar: self.render_ui_bar,
-            render_bg: self.render_bg,
+            render_ui_combo: self.render_ui_combo && !self.custom_combo(),
+            render_ui_bar: self.render_ui_bar && self.progress_bar_style == ProgressBarStyle::Bar,
+            render_bg: self.render_bg && self.background_motion == BackgroundMotion::Off,
 
             max_particles: self.max_particles,
             fade: self.fade,
+            phira_mode: match self.score_formula {
+                Some(ScoreFormula::Phigros) => false,
+                Some(ScoreFormula::Phira) => true,
+                None => self.phira_mode,
+            },
+            mods: if self.mirror { Mods::FLIP_X } else { Mods::empty() },
             ..Default::default()
         }
     }
 
-    pub fn default() -> RenderConfig {
+    /// Files the render reads besides the chart, the resource pack, subtitles
+    /// and overlays.
+    pub fn input_files(&self) -> Vec<PathBuf> {
+        [&self.player_avatar, &self.splash_image, &self.lyrics_font]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from)
+            .chain(self.skin_overrides.values().cloned())
+            .chain(self.line_texture.as_ref().and_then(|it| it.image.clone()))
+            .collect()
+    }
+
+    /// Checks the values that go onto the ffmpeg command line as they are, for
+    /// params that come from another machine.
+    pub fn check_ffmpeg_values(&self) -> Result<()> {
+        fn is_bitrate(value: &str) -> bool {
+            let number = value.strip_suffix(['k', 'K', 'm', 'M']).unwrap_or(value);
+            let (whole, fraction) = number.split_once('.').unwrap_or((number, "0"));
+            [whole, fraction]
+                .iter()
+                .all(|it| !it.is_empty() && it.bytes().all(|b| b.is_ascii_digit()))
+        }
+        if !self.bitrate.eq_ignore_ascii_case("auto") && !is_bitrate(&self.bitrate) {
+            bail!("invalid bitrate {}", self.bitrate);
+        }
+        if let Some(bitrate) = self.audio_bitrate.as_deref().filter(|it| !is_bitrate(it)) {
+            bail!("invalid audio bitrate {bitrate}");
+        }
+        if let Some(encoder) = self.encoder_fallback.iter().find(|it| !ENCODERS.iter().any(|known| known.0 == *it)) {
+            bail!("unknown encoder {encoder}");
+        }
+        Ok(())
+    }
+
+    /// Cores to pin the render to: `cpu_affinity` as given, or the first
+    /// `cpu_limit` percent of the cores (at least one).
+    fn cpu_cores(&self) -> Vec<usize> {
+        match (self.cpu_affinity.is_empty(), self.cpu_limit) {
+            (true, Some(limit)) if limit < 100 => {
+                let cores = std::thread::available_parallelism().map_or(1, |it| it.get());
+                let count = ((cores * limit as usize + 99) / 100).max(1);
+                (0..count).collect()
+            }
+            _ => self.cpu_affinity.clone(),
+        }
+    }
+
+    /// Encoder to restart with after `encoder` died mid-render, if any is left.
+    pub fn next_encoder(&self, encoder: &str) -> Option<&str> {
+        let next = self.encoder_fallback.iter().position(|it| it == encoder).map_or(0, |i| i + 1);
+        self.encoder_fallback.get(next).map(String::as_str)
+    }
+
+    /// Whether a render failing with `nvenc-busy` should be queued again.
+    pub fn waits_for_nvenc(&self) -> bool {
+        self.nvenc_busy == NvencBusy::Wait
+    }
+
+    /// Container of the final video, which is also its extension.
+    pub fn container(&self) -> &'static str {
+        if self.hires {
+            "mov"
+        } else {
+            "mp4"
+        }
+    }
+
+    pub fn chart_mods(&self) -> ChartMods {
+        ChartMods {
+            convert: self.convert_notes,
+            remove_fake: self.remove_fake_notes,
+            only_lines: self.only_lines.clone(),
+            hidden_lines: self.hidden_lines.clone(),
+            line_texture: false,
+        }
+    }
+
+    /// Expands `quality` into the settings it stands for, overriding whatever
+    /// was set by hand.
+    pub fn apply_quality(&mut self) {
+        let Some(quality) = self.quality else { return };
+        // (MSAA samples, CRF, presets for software/nvenc/qsv/amf, AAC bitrate)
+        let (sample_count, crf, presets, audio) = match quality {
+            Quality::Draft => {
+                self.draft = true;
+                return;
+            }
+            Quality::Standard => (4, 23, "medium p4 medium balanced", "192k"),
+            Quality::High => (8, 18, "slow p6 slower quality", "320k"),
+            Quality::Archival => (16, 12, "veryslow p7 veryslow quality", "320k"),
+        };
+        self.sample_count = sample_count;
+        self.bitrate_control = "CRF".to_owned();
+        self.bitrate = crf.to_string();
+        self.ffmpeg_preset = presets.to_owned();
+        self.audio_bitrate = Some(audio.to_owned());
+        if quality == Quality::Archival {
+            self.audio_bit_depth = AudioBitDepth::F32;
+        }
+    }
+
+    /// With `draft` set, swaps in cheap settings for a quick look at the whole chart:
+    /// at most 720p at 30 fps, the fastest preset of every encoder at a high
+    /// constant quality value, no MSAA and no particles.
+    pub fn apply_draft(&mut self) {
+        if !self.draft {
+            return;
+        }
+        let (w, h) = self.resolution;
+        let short = w.min(h);
+        if short > 720 {
+            // keep the aspect ratio, encoders want even dimensions
+            self.resolution = (
+                (w as u64 * 720 / short as u64) as u32 & !1,
+                (h as u64 * 720 / short as u64) as u32 & !1,
+            );
+        }
+        self.fps = self.fps.min(30);
+        // software, nvenc, qsv, amf
+        self.ffmpeg_preset = "ultrafast p1 veryfast speed".to_owned();
+        self.bitrate_control = "CRF".to_owned();
+        self.bitrate = "32".to_owned();
+        self.sample_count = 1;
+        self.particle = false;
+    }
+
+    /// Whether the combo counter is drawn by the recorder instead of prpr.
+    fn custom_combo(&self) -> bool {
+        self.combo_min.is_some() || self.combo_punch > 0. || self.combo_scale.is_some()
+    }
+
+}
+
+impl Default for RenderConfig {
+    fn default() -> RenderConfig {
         RenderConfig {
             resolution: (1920, 1080),
             ffmpeg_preset: "medium".to_string(),
@@ -148,10 +466,25 @@ impl RenderConfig {
             hires: false,
             fps: 60,
             hardware_accel: true,
+            software_fallback: false,
+            nvenc_busy: NvencBusy::Wait,
+            encoder_fallback: Vec::new(),
+            segment_length: 10,
             hevc: false,
             mpeg4: false,
             bitrate_control: "CRF".to_string(),
             bitrate: "1000k".to_string(),
+            draft: false,
+            quality: None,
+            audio_bitrate: None,
+            keyframe_interval: None,
+            disable_scene_cut: false,
+            b_frames: None,
+            rc_lookahead: None,
+            tune: None,
+            multipass: None,
+            chroma: Chroma::Yuv420,
+            display_rotation: 0,
             aggressive: true,
             challenge_color: ChallengeModeColor::Rainbow,
             challenge_rank: 45,
@@ -164,24 +497,63 @@ impl RenderConfig {
             player_rks: 16.0,
             sample_count: 8,
             res_pack_path: None,
+            skin_overrides: HashMap::new(),
             speed: 1.0,
+            speed_resample: false,
             volume_music: 1.0,
             volume_sfx: 0.7,
             compression_ratio: 100.,
+            compression_threshold: 1.0,
+            compression_attack: 0.,
+            compression_release: 0.,
             force_limit: false,
             limit_threshold: 1.0,
+            limit_lookahead: None,
+            limit_release: None,
+            audio_bit_depth: AudioBitDepth::F32,
+            music_fade_in: 0.,
+            music_fade_out: None,
+            splash_duration: 0.,
+            splash_image: None,
+            credit_duration: 0.,
+            lyrics_mode: LyricsMode::Soft,
+            lyrics_font: None,
+            lyrics_position: None,
+            lyrics_size: None,
+            lyrics_color: None,
+            ending_hide_avatar: false,
+            ending_hide_rks: false,
+            ending_show_stats: false,
+            ending_text: "".to_string(),
+            ending_graph: false,
+            ending_credits: false,
+            ending_credits_extra: Vec::new(),
+            phira_mode: false,
+            score_formula: None,
             chart_debug: false,
+            debug_overlay: DebugOverlay::default(),
             chart_ratio: 1.0,
             all_good: false,
             all_bad: false,
             watermark: "".to_string(),
+            watermark_style: None,
+            forensic_key: None,
+            forensic_strength: None,
             roman: false,
             chinese: false,
             combo: "AUTOPLAY".to_string(),
             difficulty: "".to_string(),
             player_avatar: None,
             judge_offset: 0.,
+            mirror: false,
+            convert_notes: None,
+            remove_fake_notes: false,
+            only_lines: Vec::new(),
+            hidden_lines: Vec::new(),
             simple_file_name: false,
+            manifest: true,
+            keep_stems: false,
+            note_timeline: None,
 
             render_line: true,
             render_line_extra: true,
@@ -189,30 +561,507 @@ impl RenderConfig {
             render_ui_pause: true,
             render_ui_score: true,
             render_ui_combo: true,
+            combo_min: None,
+            combo_punch: 0.,
+            combo_scale: None,
+            shake_intensity: 0.,
+            shake_every: None,
+            zoom_pulse: 0.,
+            zoom_pulse_every: None,
             render_ui_bar: true,
             render_bg: true,
+            background_motion: BackgroundMotion::Off,
+            background_motion_amplitude: None,
+            bga: true,
+            bga_blur: 0.,
+            bga_brightness: None,
+            bga_offset: 0.,
+            progress_bar_style: ProgressBarStyle::Bar,
+            nps_overlay: NpsOverlay::Off,
+            beat_grid: false,
+            beats_per_measure: None,
+            visualizer: Visualizer::Off,
+            visualizer_height: None,
 
             max_particles: 100000,
             fade: 0.0,
+
+            pbo_count: None,
+            frame_queue: None,
+            low_priority: false,
+            cpu_affinity: Vec::new(),
+            cpu_limit: None,
+            encoder_threads: None,
+            keep_awake: true,
+
+            hitsound_jitter: 0.,
+            hitsound_jitter_seed: 0,
+            hitsound_onset: true,
+            line_texture: None,
+        }
+    }
+}
+
+/// `-tune` of the software encoders.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderTune {
+    Film,
+    Animation,
+    Grain,
+    StillImage,
+}
+
+impl EncoderTune {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Film => "film",
+            Self::Animation => "animation",
+            Self::Grain => "grain",
+            Self::StillImage => "stillimage",
+        }
+    }
+}
+
+/// Chroma subsampling of the output video.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chroma {
+    #[default]
+    #[serde(rename = "420")]
+    Yuv420,
+    /// Full-resolution color, which keeps thin colored lines and small text sharp
+    #[serde(rename = "444")]
+    Yuv444,
+}
+
+/// NVENC `-multipass`.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Multipass {
+    Disabled,
+    Qres,
+    Fullres,
+}
+
+/// How lyrics passed in `RenderParams::subtitles` end up in the video.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsMode {
+    /// A subtitle track players can toggle
+    #[default]
+    Soft,
+    /// Drawn into the frames
+    Burn,
+    Both,
+}
+
+/// Bundles of encoder and rendering settings, from quickest to best looking.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    Draft,
+    Standard,
+    High,
+    Archival,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBitDepth {
+    #[default]
+    F32,
+    S24,
+    S16,
+}
+
+/// How the song progress is shown. Everything but `Bar` (prpr's own) is drawn
+/// by the recorder overlay.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressBarStyle {
+    #[default]
+    Bar,
+    Line,
+    Circle,
+    Hidden,
+}
+
+/// Live notes-per-second display at the bottom of the screen.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NpsOverlay {
+    #[default]
+    Off,
+    /// Just the current value
+    Meter,
+    /// The current value over the density curve of the whole chart
+    Graph,
+}
+
+/// File written next to the video by `note_timeline`.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteTimelineFormat {
+    Json,
+    Csv,
+}
+
+/// Motion of the illustration drawn by the recorder in place of prpr's static background.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundMotion {
+    #[default]
+    Off,
+    /// Slow pan in a loop, whatever the chart does
+    Drift,
+    /// Zooms in with the note density
+    Pulse,
+}
+
+/// Audio visualizer along the bottom edge, computed from the final mix.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visualizer {
+    #[default]
+    Off,
+    /// Bars for log-spaced frequency bands
+    Spectrum,
+    Waveform,
+}
+
+/// Corner of the frame the watermark sits in.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl Corner {
+    /// The corner as fractions of the frame, which is also the anchor of the text box.
+    pub fn anchor(self) -> (f32, f32) {
+        match self {
+            Self::TopLeft => (0., 0.),
+            Self::TopRight => (1., 0.),
+            Self::BottomLeft => (0., 1.),
+            Self::BottomRight => (1., 1.),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WatermarkStyle {
+    /// Text height as a fraction of the frame height
+    pub size: f32,
+    /// `#RRGGBB` or `#RRGGBBAA`
+    pub color: String,
+    pub corner: Corner,
+    /// Distance from the edges as a fraction of the frame height
+    pub margin: f32,
+    /// Put the text on a translucent rounded backdrop
+    pub pill: bool,
+    pub motion: WatermarkMotion,
+    /// Seconds between hops, or the period of blinking
+    pub interval: f32,
+}
+
+/// Keeps the watermark from sitting still where it is easy to crop or paint out.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkMotion {
+    #[default]
+    Static,
+    /// Wanders slowly across the whole frame
+    Drift,
+    /// Jumps to another corner every `interval`
+    Hop,
+    /// Shown for the first half of every `interval`
+    Blink,
+}
+
+impl WatermarkStyle {
+    /// Where the watermark is at video time `time`, as fractions of the frame
+    /// (which also serve as its anchor), and how visible it is.
+    pub fn place(&self, time: f64) -> ((f32, f32), f32) {
+        let interval = self.interval.max(1.) as f64;
+        // fade over 0.3s around every change
+        let fade = |t: f64, length: f64| (t.min(length - t) / 0.3).clamp(0., 1.) as f32;
+        match self.motion {
+            WatermarkMotion::Static => (self.corner.anchor(), 1.),
+            WatermarkMotion::Drift => {
+                // bounce between the edges, with periods that don't line up
+                let triangle = |period: f64| {
+                    let phase = (time / period).fract();
+                    (1. - (phase * 2. - 1.).abs()) as f32
+                };
+                ((triangle(47.), triangle(31.)), 1.)
+            }
+            WatermarkMotion::Hop => {
+                const ORDER: [Corner; 4] = [Corner::TopLeft, Corner::BottomRight, Corner::TopRight, Corner::BottomLeft];
+                let start = ORDER.iter().position(|&it| it == self.corner).unwrap_or(0);
+                let hop = (time / interval) as usize;
+                let corner = ORDER[(start + hop) % ORDER.len()];
+                (corner.anchor(), fade(time % interval, interval))
+            }
+            WatermarkMotion::Blink => {
+                let t = time % interval;
+                let alpha = if t < interval / 2. { fade(t, interval / 2.) } else { 0. };
+                (self.corner.anchor(), alpha)
+            }
+        }
+    }
+}
+
+impl Default for WatermarkStyle {
+    fn default() -> Self {
+        Self {
+            size: 0.025,
+            color: "#FFFFFFB0".to_owned(),
+            corner: Corner::BottomRight,
+            margin: 0.02,
+            pill: false,
+            motion: WatermarkMotion::Static,
+            interval: 20.,
         }
     }
 }
 
-#[derive(Deserialize, Serialize)]
+/// Consumer NVIDIA cards only open a few NVENC sessions at once; past that,
+/// ffmpeg fails with a cryptic `OpenEncodeSessionEx` error.
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NvencBusy {
+    /// Fail with `nvenc-busy`, and have the queue retry once a session frees up
+    #[default]
+    Wait,
+    /// Encode on the CPU instead
+    Software,
+    /// Fail with `nvenc-busy`
+    Fail,
+}
+
+/// Which game's conventions the score counter and results follow.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreFormula {
+    Phigros,
+    Phira,
+}
+
+impl ScoreFormula {
+    /// Score out of 1,000,000 and accuracy of a run, Goods counting as 65% of a
+    /// Perfect in both.
+    fn score(self, perfect: usize, good: usize, max_combo: usize, notes: usize) -> (u32, f64) {
+        let notes = notes.max(1) as f64;
+        let accuracy = (perfect as f64 + good as f64 * 0.65) / notes;
+        let score = match self {
+            // 90% for accuracy, 10% for the longest combo
+            Self::Phigros => accuracy * 900000. + max_combo as f64 / notes * 100000.,
+            // accuracy alone, which is also printed on the results
+            Self::Phira => accuracy * 1000000.,
+        };
+        (score.round() as u32, accuracy)
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RenderParams {
     pub path: PathBuf,
+    /// The chart's `info.yml`, as prpr reads it
+    #[schemars(with = "serde_json::Value")]
     pub info: ChartInfo,
     pub config: RenderConfig,
+
+    /// Send at most one progress event per this many frames...
+    #[serde(default)]
+    pub progress_frames: Option<u32>,
+    /// ...or per this many milliseconds, whichever comes first. Every frame is
+    /// reported when neither is set.
+    #[serde(default)]
+    pub progress_millis: Option<u32>,
+
+    /// Echoed back in every [`IPCMessage`] so the host can tell jobs apart.
+    #[serde(default)]
+    pub job_id: Option<String>,
+
+    /// `.lrc` or `.srt` muxed into the video as a subtitle track, timed to the music
+    #[serde(default)]
+    pub subtitles: Option<PathBuf>,
+
+    /// Timed text and images composited over the video
+    #[serde(default)]
+    pub overlays: Vec<OverlayItem>,
+
+    /// File name (without extension) of the video in the output folder, with
+    /// `{name}`, `{level}`, `{composer}`, `{charter}` and `{date}` filled in
+    #[serde(default)]
+    pub output_name: Option<String>,
+
+    /// NVENC device to encode on when several are installed, passed as `-gpu`.
+    /// Rendering stays on the adapter the system picks; NVENC indices don't
+    /// match Mesa's `DRI_PRIME`, which is left to the user's environment
+    #[serde(default)]
+    pub gpu: Option<u32>,
+
+    /// Set by the queue when restarting a segmented render whose encoder died
+    #[serde(default)]
+    pub resume: Option<Resume>,
+
+    /// Seconds added to `info.offset` for this job only, for charts that ship
+    /// slightly off; the chart file is left alone
+    #[serde(default)]
+    pub offset_delta: f32,
+
+    /// Fail on fields the renderer doesn't know, which are otherwise only
+    /// logged, to catch typos in hand-written params
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl RenderParams {
+    /// Folds `offset_delta` into `info`, so that the params written to the
+    /// manifest give the same timing when rendered again.
+    pub fn apply_offset_delta(&mut self) {
+        if self.offset_delta != 0. {
+            info!("Chart offset adjusted by {:+.3}s", self.offset_delta);
+            self.info.offset += std::mem::take(&mut self.offset_delta);
+        }
+    }
+}
+
+/// Where a segmented render picks up again, see `RenderConfig::encoder_fallback`.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Resume {
+    /// First frame not in a finished segment
+    pub frame: u64,
+    /// Number of the first segment to write
+    pub segment: u64,
+    pub encoder: String,
+}
+
+/// Bumped whenever [`IPCEvent`] changes in a way an older host can't parse.
+pub const IPC_PROTOCOL_VERSION: u32 = 4;
+
+/// One line of render process output. `job` is missing until the params have
+/// been read, i.e. for [`IPCEvent::Hello`] and errors reading them.
+#[derive(Deserialize)]
+pub struct IPCMessage {
+    pub job: Option<String>,
+    pub event: IPCEvent,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum IPCEvent {
+    /// First event of every render process.
+    Hello {
+        protocol_version: u32,
+        renderer_version: String,
+    },
     Loading,
     StartMixing,
     StartRender(u64),
     Frame,
-    Done(f64),
+    /// This many frames were rendered since the last progress event.
+    Frames(u64),
+    Done {
+        duration: f64,
+        frame_stats: Option<FrameStats>,
+    },
+    /// The encoder of a segmented render exited early; everything before
+    /// `frame` (segments below `segment`) is safely on disk.
+    EncoderDied {
+        frame: u64,
+        segment: u64,
+        encoder: String,
+    },
+    /// Measured on the final mix, before it is written out.
+    Loudness(audio::Loudness),
+    /// Something the user should know about that didn't stop the render.
+    Warning {
+        code: String,
+        message: String,
+    },
+    /// Sent right before the process exits with an error.
+    Error {
+        code: String,
+        stage: String,
+        message: String,
+        detail: String,
+    },
+}
+
+/// Batches per-frame progress into [`IPCEvent::Frames`].
+struct FrameReporter {
+    pending: u64,
+    last: Instant,
+    frames: Option<u64>,
+    interval: Option<std::time::Duration>,
+}
+
+impl FrameReporter {
+    fn new(frames: Option<u32>, millis: Option<u32>) -> Self {
+        Self {
+            pending: 0,
+            last: Instant::now(),
+            frames: frames.map(|it| it.max(1) as u64),
+            interval: millis.map(|it| std::time::Duration::from_millis(it as u64)),
+        }
+    }
+
+    fn frame(&mut self) {
+        self.pending += 1;
+        let due = match (self.frames, self.interval) {
+            (None, None) => true,
+            (frames, interval) => {
+                frames.is_some_and(|it| self.pending >= it)
+                    || interval.is_some_and(|it| self.last.elapsed() >= it)
+            }
+        };
+        if due {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            crate::ipc::client::send(IPCEvent::Frames(self.pending));
+            self.pending = 0;
+        }
+        self.last = Instant::now();
+    }
+}
+
+/// Error context naming the step that failed, so the host gets something more
+/// specific than the error chain. `code` is the locale key of `message`.
+#[derive(Debug)]
+pub struct Failure {
+    pub stage: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+macro_rules! failure {
+    ($stage:literal, $code:literal $(, $($args:tt)*)?) => {
+        Failure {
+            stage: $stage,
+            code: $code,
+            message: tl!($code $(, $($args)*)?).to_string(),
+        }
+    };
 }
 
 pub async fn build_player(config: &RenderConfig) -> Result<BasicPlayer> {
@@ -222,7 +1071,7 @@ pub async fn build_player(config: &RenderConfig) -> Result<BasicPlayer> {
                 Texture2D::from_file_with_format(
                     &tokio::fs::read(path)
                         .await
-                        .with_context(|| tl!("load-avatar-failed"))?,
+                        .with_context(|| failure!("loading", "load-avatar-failed"))?,
                     None,
                 )
                 .into(),
@@ -235,7 +1084,80 @@ pub async fn build_player(config: &RenderConfig) -> Result<BasicPlayer> {
     })
 }
 
-fn cmd_hidden(program: impl AsRef<OsStr>) -> Command {
+/// The texture standing in for the judge lines, from `line_texture` or the
+/// resource pack's `line.png`.
+pub fn line_texture(config: &RenderConfig) -> Result<Option<Vec<u8>>> {
+    let strip = match config.line_texture.as_ref().and_then(|it| it.image.as_ref()) {
+        Some(path) => Some(std::fs::read(path).with_context(|| failure!("loading", "line-texture-failed"))?),
+        None => config.res_pack_path.as_deref().and_then(|it| respack::read_file(Path::new(it), "line.png")),
+    };
+    match (strip, &config.line_texture) {
+        (Some(strip), style) => Ok(Some(
+            style
+                .clone()
+                .unwrap_or_default()
+                .render(&strip)
+                .with_context(|| failure!("loading", "line-texture-failed"))?,
+        )),
+        (None, Some(_)) => bail!(failure!("loading", "line-texture-failed")),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Lines of ffmpeg's stderr kept for diagnosing a failure.
+const FFMPEG_LOG_LINES: usize = 30;
+
+/// ffmpeg's stderr, passed through to ours while the last lines are kept to
+/// explain a failure.
+struct FfmpegLog {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    reader: std::thread::JoinHandle<()>,
+}
+
+impl FfmpegLog {
+    fn capture(proc: &mut std::process::Child) -> Self {
+        let stderr = proc.stderr.take().expect("ffmpeg stderr is piped");
+        let lines: Arc<Mutex<VecDeque<String>>> = Arc::default();
+        let reader = std::thread::spawn({
+            let lines = Arc::clone(&lines);
+            move || {
+                for line in std::io::BufReader::new(stderr).lines().map_while(Result::ok) {
+                    eprintln!("{line}");
+                    let mut lines = lines.lock().unwrap();
+                    if lines.len() == FFMPEG_LOG_LINES {
+                        lines.pop_front();
+                    }
+                    lines.push_back(line);
+                }
+            }
+        });
+        Self { lines, reader }
+    }
+
+    /// Turns what ffmpeg said before it exited into the failure most likely to
+    /// tell the user what to change. Call once ffmpeg has exited.
+    fn failure(self, cause: impl std::fmt::Display) -> anyhow::Error {
+        let _ = self.reader.join();
+        let log = self.lines.lock().unwrap().iter().map(String::as_str).collect::<Vec<_>>().join("\n");
+        let has = |text: &str| log.contains(text);
+        let failure = if has("Unknown encoder") || has("Encoder not found") {
+            failure!("ffmpeg", "ffmpeg-unknown-encoder")
+        } else if has("No space left on device") {
+            failure!("ffmpeg", "ffmpeg-no-space")
+        } else if has("Permission denied") {
+            failure!("ffmpeg", "ffmpeg-permission-denied")
+        } else if has("Error setting option") || has("Unable to parse option value") || has("Invalid value") {
+            failure!("ffmpeg", "ffmpeg-invalid-option")
+        } else if has("Error while opening encoder") || has("Error initializing output stream") {
+            failure!("ffmpeg", "ffmpeg-encoder-failed")
+        } else {
+            failure!("ffmpeg", "ffmpeg-exited")
+        };
+        anyhow::anyhow!("{cause}\n{log}").context(failure)
+    }
+}
+
+pub fn cmd_hidden(program: impl AsRef<OsStr>) -> Command {
     let cmd = Command::new(program);
     #[cfg(target_os = "windows")]
     {
@@ -248,21 +1170,278 @@ fn cmd_hidden(program: impl AsRef<OsStr>) -> Command {
     cmd
 }
 
+/// Reads what a `render`/`mix` subprocess should work on.
+///
+/// `--params <file>` (a [`RenderParams`] in JSON, YAML or TOML, see
+/// [`read_params_file`]) and `--output <path>` may follow the
+/// first `skip` arguments; whichever of the two is missing is read from stdin as
+/// one JSON line, params first. `--offset-delta <seconds>` shifts the chart
+/// offset on top of the params' own `offsetDelta`, and `--strict` turns on the
+/// params' `strict`.
+fn read_job(skip: usize) -> Result<(RenderParams, PathBuf)> {
+    let mut params_file = None;
+    let mut output = None;
+    let mut offset_delta = 0.;
+    let mut strict = false;
+    let mut args = std::env::args().skip(skip);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--params" => params_file = Some(args.next().context("missing value for --params")?),
+            "--output" => output = Some(args.next().context("missing value for --output")?),
+            "--strict" => strict = true,
+            "--offset-delta" => {
+                let value = args.next().context("missing value for --offset-delta")?;
+                offset_delta = value.parse::<f32>().with_context(|| format!("invalid offset delta {value}"))?;
+            }
+            _ => bail!("unknown argument: {arg}"),
+        }
+    }
+
+    let mut stdin = std::io::stdin().lock();
+    let mut line = String::new();
+    let mut params = if let Some(file) = params_file {
+        read_params_file(Path::new(&file), strict).with_context(|| format!("invalid params in {file}"))?
+    } else {
+        stdin.read_line(&mut line)?;
+        parse_params(line.trim(), strict)?
+    };
+    let output_path: PathBuf = if let Some(output) = output {
+        output.into()
+    } else {
+        line.clear();
+        stdin.read_line(&mut line)?;
+        serde_json::from_str(line.trim())?
+    };
+    params.offset_delta += offset_delta;
+    params.apply_offset_delta();
+    Ok((params, output_path))
+}
+
+/// Parses [`RenderParams`], naming the field that didn't fit (e.g.
+/// `config.resolution[0]`) rather than just a line and column. Fields the
+/// renderer doesn't know are logged, or rejected with `strict`.
+fn parse_params(text: &str, strict: bool) -> Result<RenderParams> {
+    let mut unknown = Vec::new();
+    let mut json = serde_json::Deserializer::from_str(text);
+    let ignored = serde_ignored::Deserializer::new(&mut json, |path| unknown.push(path.to_string()));
+    let params: RenderParams = serde_path_to_error::deserialize(ignored).map_err(|err| {
+        let field = err.path().to_string();
+        let error = err.into_inner();
+        let failure = failure!("loading", "invalid-params", "field" => field, "error" => error.to_string());
+        anyhow::Error::new(error).context(failure)
+    })?;
+    json.end()?;
+    if !unknown.is_empty() {
+        let fields = unknown.join(", ");
+        if strict || params.strict {
+            bail!(failure!("loading", "unknown-params", "fields" => fields));
+        }
+        warn!("Ignoring unknown params: {fields}");
+    }
+    Ok(params)
+}
+
+/// Reads params written by hand: YAML for `.yml`/`.yaml` files, TOML for
+/// `.toml` files and JSON otherwise. Field names are the same in all three.
+fn read_params_file(path: &Path, strict: bool) -> Result<RenderParams> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let extension = path.extension().map(|it| it.to_string_lossy().to_lowercase()).unwrap_or_default();
+    // converted to JSON so that errors and unknown fields are reported the same way
+    let json = match extension.as_str() {
+        "yml" | "yaml" => serde_json::to_string(&serde_yaml::from_str::<serde_json::Value>(&text)?)?,
+        "toml" => serde_json::to_string(&toml::from_str::<serde_json::Value>(&text)?)?,
+        _ => text,
+    };
+    parse_params(&json, strict)
+}
+
+/// `author` from the `info.yml` of a resource pack, either a folder or a zip.
+fn respack_author(path: &str) -> Option<String> {
+    let text = String::from_utf8(respack::read_file(Path::new(path), "info.yml")?).ok()?;
+    let info: serde_yaml::Value = serde_yaml::from_str(&text).ok()?;
+    Some(info.get("author")?.as_str()?.to_owned())
+}
+
+/// Picks a bitrate (or, with `constant_quality`, a quality value) for `encoder`
+/// when the user asked for `auto`.
+///
+/// Gameplay footage is high motion with lots of thin edges, so the target is
+/// generous: 0.1 bits per pixel per frame for H.264 at 30 fps, slightly less per
+/// frame as the frame rate goes up, and less again for the more efficient codecs.
+pub fn suggest_bitrate(
+    (width, height): (u32, u32),
+    fps: u32,
+    encoder: &str,
+    constant_quality: bool,
+) -> String {
+    let efficiency = if encoder.contains("av1") {
+        0.5
+    } else if encoder.contains("hevc") || encoder.contains("265") {
+        0.65
+    } else if encoder == "mpeg4" {
+        1.6
+    } else {
+        1.
+    };
+    if constant_quality {
+        // mpeg4's -q is 1..31 with a different scale altogether
+        return if encoder == "mpeg4" {
+            "3".to_owned()
+        } else if efficiency < 1. {
+            "24".to_owned()
+        } else {
+            "20".to_owned()
+        };
+    }
+    let fps = fps.max(1) as f64;
+    let per_frame = 0.1 * (30. / fps).sqrt().min(1.);
+    let bits = width as f64 * height as f64 * fps * per_frame * efficiency;
+    format!("{}k", (bits / 1000.).round().max(500.) as u64)
+}
+
+/// Whether `ffmpeg` knows the option `-name`.
+fn ffmpeg_has_option(ffmpeg: &str, name: &str) -> bool {
+    Command::new(ffmpeg)
+        .args(["-hide_banner", "-h", "full"])
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("-{name} ")))
+}
+
+/// Video encoders the recorder knows how to drive: `(ffmpeg name, codec, backend)`.
+const ENCODERS: &[(&str, &str, &str)] = &[
+    ("libx264", "h264", "software"),
+    ("h264_nvenc", "h264", "nvenc"),
+    ("h264_qsv", "h264", "qsv"),
+    ("h264_amf", "h264", "amf"),
+    ("h264_videotoolbox", "h264", "videotoolbox"),
+    ("h264_vaapi", "h264", "vaapi"),
+    ("libx265", "hevc", "software"),
+    ("hevc_nvenc", "hevc", "nvenc"),
+    ("hevc_qsv", "hevc", "qsv"),
+    ("hevc_amf", "hevc", "amf"),
+    ("hevc_videotoolbox", "hevc", "videotoolbox"),
+    ("hevc_vaapi", "hevc", "vaapi"),
+    ("libsvtav1", "av1", "software"),
+    ("av1_nvenc", "av1", "nvenc"),
+    ("av1_qsv", "av1", "qsv"),
+    ("av1_amf", "av1", "amf"),
+    ("av1_vaapi", "av1", "vaapi"),
+    ("mpeg4", "mpeg4", "software"),
+];
+
+/// Whether `encoder` runs on the CPU.
+fn software_encoder(encoder: &str) -> bool {
+    ENCODERS.iter().any(|it| it.0 == encoder && it.2 == "software")
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncoderInfo {
+    pub name: &'static str,
+    pub codec: &'static str,
+    pub backend: &'static str,
+    pub available: bool,
+}
+
+/// Messages NVENC fails with when the card has no session left to open.
+const NVENC_BUSY_ERRORS: &[&str] = &[
+    "OpenEncodeSessionEx failed: out of memory",
+    "OpenEncodeSessionEx failed: incompatible client key",
+];
+
+fn encoder_probe(ffmpeg: &str, encoder: &str) -> Command {
+    let mut command = Command::new(ffmpeg);
+    command.args(["-f", "lavfi", "-i", "color=c=black:s=320x240:d=0"]);
+    if encoder.ends_with("_vaapi") {
+        command.args([
+            "-init_hw_device",
+            "vaapi=va",
+            "-filter_hw_device",
+            "va",
+            "-vf",
+            "format=nv12,hwupload",
+        ]);
+    }
+    command
+        .args(["-c:v", encoder, "-f", "null", "-"])
+        .arg("-hide_banner")
+        .stdout(Stdio::null());
+    command
+}
+
+/// Whether `encoder` can encode a frame on this machine, which for hardware
+/// encoders is stricter than ffmpeg merely having been built with them.
+pub fn test_encoder(ffmpeg: &str, encoder: &str) -> bool {
+    encoder_probe(ffmpeg, encoder)
+        .arg("-loglevel")
+        .arg("fatal")
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Whether `encoder` (an NVENC one) fails only because every session is in use.
+pub fn nvenc_busy(ffmpeg: &str, encoder: &str) -> bool {
+    encoder_probe(ffmpeg, encoder)
+        .arg("-loglevel")
+        .arg("error")
+        .stderr(Stdio::piped())
+        .output()
+        .is_ok_and(|output| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            !output.status.success() && NVENC_BUSY_ERRORS.iter().any(|it| stderr.contains(it))
+        })
+}
+
+/// Probes every entry of [`ENCODERS`], in parallel since hardware probes can take a while.
+pub fn list_encoders(ffmpeg: &str) -> Vec<EncoderInfo> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = ENCODERS
+            .iter()
+            .map(|&(name, codec, backend)| {
+                scope.spawn(move || EncoderInfo {
+                    name,
+                    codec,
+                    backend,
+                    available: test_encoder(ffmpeg, name),
+                })
+            })
+            .collect();
+        handles.into_iter().map(|it| it.join().unwrap()).collect()
+    })
+}
+
+/// The first working encoder for `codec` (as in [`ENCODERS`]), hardware ones
+/// before software.
+pub fn pick_encoder(ffmpeg: &str, codec: &str) -> Option<&'static str> {
+    let candidates = ENCODERS.iter().filter(|it| it.1 == codec);
+    let (software, hardware): (Vec<_>, Vec<_>) = candidates.partition(|it| it.2 == "software");
+    hardware.into_iter().chain(software).map(|it| it.0).find(|it| test_encoder(ffmpeg, it))
+}
+
 pub fn find_ffmpeg() -> Result<Option<String>> {
     fn test(path: impl AsRef<OsStr>) -> bool {
         matches!(cmd_hidden(path).arg("-version").output(), Ok(_))
     }
+    if let Some(ffmpeg) = std::env::var_os(FFMPEG_ENV) {
+        if test(&ffmpeg) {
+            return Ok(Some(ffmpeg.to_string_lossy().into_owned()));
+        }
+        warn!("{FFMPEG_ENV}={} doesn't run, looking for another", ffmpeg.to_string_lossy());
+    }
+    if let Some(version) = ffmpeg::pinned() {
+        let ffmpeg = ffmpeg::managed(&version)?;
+        if test(&ffmpeg) {
+            return Ok(Some(ffmpeg.display().to_string()));
+        }
+        warn!("Pinned ffmpeg {version} doesn't run, looking for another");
+    }
     if test("ffmpeg") {
         return Ok(Some("ffmpeg".to_owned()));
     }
     eprintln!("Failed to find global ffmpeg. Using bundled ffmpeg");
-    let exe_dir = std::env::current_exe()?.parent().unwrap().to_owned();
-    let ffmpeg = if cfg!(target_os = "windows") {
-        "ffmpeg.exe"
-    } else {
-        "ffmpeg"
-    };
-    let ffmpeg = exe_dir.join(ffmpeg);
+    let ffmpeg = ffmpeg::bundled()?;
     Ok(if test(&ffmpeg) {
         Some(ffmpeg.display().to_string())
     } else {
@@ -270,161 +1449,125 @@ pub fn find_ffmpeg() -> Result<Option<String>> {
     })
 }
 
-pub async fn main(cmd: bool) -> Result<()> {
-    let loading_time = Instant::now();
+/// [`find_ffmpeg`] for the subcommands that can't do without it, failing with
+/// `ffmpeg-not-found` when there is none.
+pub fn require_ffmpeg() -> Result<String> {
+    find_ffmpeg()?.ok_or_else(|| failure!("ffmpeg", "ffmpeg-not-found").into())
+}
 
-    let (mut fs, output_path, config, info) = 
-    if cmd {
-        init_assets();
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// Where gameplay, music and the ending land in the video, in seconds.
+#[derive(Clone, Copy)]
+struct Timeline {
+    /// Video time at which the chart's time 0 happens
+    o: f64,
+    offset: f32,
+    length: f64,
+    track_length: f64,
+    video_length: f64,
+    /// Playback speed applied to the audio, 1.0 unless `speed_resample` is set
+    speed: f64,
+    /// Video time by which the scene clock lags behind, to make room for the splash card
+    shift: f64,
+    /// Video time cut from the start of the output (`-ss`)
+    cut: f64,
+}
 
-        #[cfg(target_os = "windows")]
-        {
-            let app_data_dir = std::env::var("APPDATA").unwrap();
-            let data_dir = PathBuf::from(app_data_dir).join("com.hlmc.phi.recorder");
-            DATA_DIR.set(ensure_dir(data_dir.clone())).unwrap();
-        }
+impl Timeline {
+    fn new(config: &RenderConfig, chart: &Chart, info: &ChartInfo, music: &AudioClip) -> Self {
+        let before: f64 = if config.disable_loading {
+            GameScene::BEFORE_DURATION as f64
+        } else {
+            LoadingScene::TOTAL_TIME as f64 + GameScene::BEFORE_DURATION as f64
+        };
+        // the splash card covers the lead-in and, if longer, delays the whole scene
+        let (shift, cut) = if config.disable_loading {
+            let splash = config.splash_duration.max(0.) as f64;
+            ((splash - before).max(0.), (before - splash).max(0.))
+        } else {
+            (0., 0.)
+        };
+        let o = before + shift;
+        // the video ends this long before the music does, whatever its fade-out;
+        // the fade itself counts back from the end of the music in `mix_audio`
+        let a: f64 = -0.5;
+        let track_length = music.length() as f64;
+        let speed = if config.speed_resample && config.speed > 0. {
+            config.speed as f64
+        } else {
+            1.
+        };
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            DATA_DIR
-                .set(ensure_dir(std::env::current_dir().unwrap().to_owned()))
-                .unwrap();
+        let offset = chart.offset + info.offset;
+        let length = (track_length - offset.min(0.) as f64) / speed + 1.;
+        let video_length = o + length + a + config.ending_length;
+        Self {
+            o,
+            offset,
+            length,
+            track_length,
+            video_length,
+            speed,
+            shift,
+            cut,
         }
+    }
 
-        let config = match (|| -> Result<RenderConfig> {
-            Ok(serde_yaml::from_str(
-                &std::fs::read_to_string("config.yml").context("error reading config")?,
-            )?)
-        })() {
-            Err(err) => {
-                warn!("error loading config: {:?}", err);
-                RenderConfig::default()
-            }
-            Ok(config) => config,
-        };
-        let path = std::env::args().nth(2).unwrap();
+    /// Video time at which the given chart time is shown.
+    fn video_time(&self, time: f32) -> f64 {
+        self.o + time as f64 / self.speed
+    }
 
-        let mut fs = fs::fs_from_file(path.as_ref())?;
-        let info = fs::load_info(fs.deref_mut()).await?;
-        let level: String = info
-            .level
-            .split_whitespace()
-            .next()
-            .unwrap_or("UK")
-            .to_string();
-        let safe_name: String = info
-            .name
-            .chars()
-            .filter(|&it| it == '-' || it == '_' || it.is_alphanumeric())
-            .collect();
-        let safe_name2: String = info
-            .composer
-            .chars()
-            .filter(|&it| it == '-' || it == '_' || it.is_alphanumeric())
-            .collect();
-        let format = if config.hires { "mov" } else { "mp4" };
-
-        let file_name = if config.simple_file_name {
-            format!(
-                "{safe_name}.{safe_name2}_{level}.{format}",
-            )
-        } else {
-            format!(
-                "{} {safe_name}_{level}.{format}",
-                Local::now().format("%Y-%m-%d %H-%M-%S")
-            )
-        };
-        let output_path = if std::env::args().len() > 3 {
-            let dir = std::env::args().nth(3).unwrap();
-            let output_dir = PathBuf::from(dir);
-            info!("output dir: {:?}", output_dir);
-            let_output_dir(output_dir)?.join(file_name)
-        } else {
-            output_dir()?.join(file_name)
-        };
-
-        (fs, output_path, config, info)
+    /// Chart time shown at the given video time.
+    fn chart_time(&self, time: f64) -> f32 {
+        ((time - self.o) * self.speed) as f32
     }
-    else {
-        set_pc_assets_folder(&std::env::args().nth(2).unwrap());
-    
-        let mut stdin = std::io::stdin().lock();
-        let stdin = &mut stdin;
-    
-        let mut line = String::new();
-        stdin.read_line(&mut line)?;
-        let params: RenderParams = serde_json::from_str(line.trim())?;
-        let path = params.path;
-    
-        line.clear();
-        stdin.read_line(&mut line)?;
-        let output_path: PathBuf = serde_json::from_str(line.trim())?;
-    
-        let fs = fs::fs_from_file(&path)?;
-    
-        let config = params.config;
-        let info = params.info;
-
-        (fs, output_path, config, info)
-    };
 
+    /// Video time at which the given time of the music is heard.
+    fn music_video_time(&self, time: f64) -> f64 {
+        self.o + (time - self.offset as f64) / self.speed
+    }
 
-    use crate::ipc::client::*;
-    let ipc = if cmd { false } else { true };
-    let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
-    let mut painter = TextPainter::new(font);
-    let mut prpr_config = config.to_config();
-    prpr_config.mods = Mods::AUTOPLAY;
-    prpr_config.disable_audio = true;
-    let Some(ffmpeg) = find_ffmpeg()? else {
-        bail!("FFmpeg not found")
-    };
-    info!("ffmpeg: {}", &ffmpeg);
+    /// Video time at which the results screen appears.
+    fn ending_start(&self) -> f64 {
+        self.o + self.length + GameScene::WAIT_AFTER_TIME as f64 - self.offset as f64
+    }
+}
 
-    let (mut chart, ..) = GameScene::load_chart(fs.deref_mut(), &info)
-        .await
-        .with_context(|| tl!("load-chart-failed"))?;
+/// Mixes music, hitsounds and the ending loop into interleaved stereo samples at
+/// [`SAMPLE_RATE`], covering the whole video.
+async fn mix_audio(
+    config: &RenderConfig,
+    chart: &mut Chart,
+    music: &AudioClip,
+    timeline: &Timeline,
+) -> Result<Vec<f32>> {
     macro_rules! ld {
-            ($path:literal) => {
-                AudioClip::new(load_file($path).await?)
-                    .with_context(|| tl!("load-sfx-failed", "name" => $path))?
-            };
-        }
-    let music: Result<_> = async { AudioClip::new(fs.load_file(&info.music).await?) }.await;
-    let music = music.with_context(|| tl!("load-music-failed"))?;
+        ($path:literal) => {
+            AudioClip::new(load_file($path).await?)
+                .with_context(|| failure!("loading", "load-sfx-failed", "name" => $path))?
+        };
+    }
     let ending = ld!("ending.ogg");
-    let track_length = music.length() as f64;
     let sfx_click = ld!("click.ogg");
     let sfx_drag = ld!("drag.ogg");
     let sfx_flick = ld!("flick.ogg");
-
-    let mut gl = unsafe { get_internal_gl() };
-
-    let volume_music = config.volume_music;
-    let volume_sfx = config.volume_sfx;
-
-    let o: f64 = if config.disable_loading {
-        GameScene::BEFORE_DURATION as f64
-    } else {
-        LoadingScene::TOTAL_TIME as f64 + GameScene::BEFORE_DURATION as f64
+    // the resource pack's own, where it has them in the mixer's format
+    let from_pack = |name: &str| {
+        let pack = config.res_pack_path.as_deref()?;
+        let clip = AudioClip::new(respack::read_file(Path::new(pack), &format!("{name}.ogg"))?).ok()?;
+        if clip.sample_rate() != SAMPLE_RATE {
+            warn!("{name}.ogg of the resource pack is {} Hz, reinstall the pack to convert it", clip.sample_rate());
+            return None;
+        }
+        Some(clip)
     };
-    let a: f64 = -0.5; // fade out time
-    let musica: f64 = GameScene::WAIT_AFTER_TIME as f64 + EndingScene::BPM_WAIT_TIME;
-
-    let offset = chart.offset + info.offset;
-    let length = track_length - offset.min(0.) as f64 + 1.;
-    let video_length = o + length + a + config.ending_length;
-
-    info!("Loading Resources Time:{:.2?}", loading_time.elapsed());
-    info!("video length: {:.2}s", video_length);
-
-    let render_start_time = Instant::now();
+    let sfx_click = from_pack("click").unwrap_or(sfx_click);
+    let sfx_drag = from_pack("drag").unwrap_or(sfx_drag);
+    let sfx_flick = from_pack("flick").unwrap_or(sfx_flick);
 
-    if ipc {
-        send(IPCEvent::StartMixing);
-    }
-    let mixing_output = NamedTempFile::new()?;
-    let sample_rate = 48000;
+    let sample_rate = SAMPLE_RATE;
     let sample_rate_f64 = sample_rate as f64;
     assert_eq!(
         sample_rate,
@@ -455,40 +1598,65 @@ pub async fn main(cmd: bool) -> Result<()> {
         sfx_flick.sample_rate()
     );
 
+    let Timeline {
+        o,
+        offset,
+        length,
+        track_length,
+        video_length,
+        speed,
+        ..
+    } = *timeline;
+    let fade_in = config.music_fade_in.max(0.) as f64;
+    let fade_out = config.music_fade_out.unwrap_or(0.5).max(0.) as f64;
+    let musica: f64 = GameScene::WAIT_AFTER_TIME as f64 + EndingScene::BPM_WAIT_TIME;
+    let volume_music = config.volume_music;
+    let volume_sfx = config.volume_sfx;
+
     let mut output = vec![0.0_f32; (video_length * sample_rate_f64).ceil() as usize * 2];
     let mut output2 = vec![0.0_f32; (video_length * sample_rate_f64).ceil() as usize * 2];
     let mut output2_agg = vec![0.0_f32; (video_length * sample_rate_f64).ceil() as usize];
     let agg = config.aggressive;
 
     // let stereo_sfx = false; // TODO stereo sound effects
+    // hitsounds rarely start on a sample boundary; split the fractional part
+    // between neighbouring frames (linear fractional delay) instead of rounding
     let mut place = |pos: f64, clip: &AudioClip, volume: f32| {
-        let position = (pos * sample_rate_f64).round() as usize * 2;
-        if position >= output2.len() {
+        let position = pos.max(0.) * sample_rate_f64;
+        let start = position.floor() as usize;
+        let frac = (position - start as f64) as f32;
+        if start * 2 >= output2.len() {
             return 0;
         }
-        let slice = &mut output2[position..];
-        let len = (slice.len() / 2).min(clip.frame_count());
+        let slice = &mut output2[start * 2..];
+        let len = (slice.len() / 2).min(clip.frame_count() + 1);
 
         let frames = clip.frames();
         for i in 0..len {
-            slice[i * 2] += frames[i].0 * volume;
-            slice[i * 2 + 1] += frames[i].1 * volume;
+            let (l0, r0) = if i > 0 { (frames[i - 1].0, frames[i - 1].1) } else { (0., 0.) };
+            let (l1, r1) = frames.get(i).map_or((0., 0.), |it| (it.0, it.1));
+            slice[i * 2] += (l1 + (l0 - l1) * frac) * volume;
+            slice[i * 2 + 1] += (r1 + (r0 - r1) * frac) * volume;
         }
 
         return len;
     };
 
     let mut place_agg = |pos: f64, clip: &AudioClip, volume: f32| {
-        let position = (pos * sample_rate_f64).round() as usize;
-        if position >= output2_agg.len() {
+        let position = pos.max(0.) * sample_rate_f64;
+        let start = position.floor() as usize;
+        let frac = (position - start as f64) as f32;
+        if start >= output2_agg.len() {
             return 0;
         }
-        let slice = &mut output2_agg[position..];
-        let len = (slice.len()).min(clip.frame_count());
+        let slice = &mut output2_agg[start..];
+        let len = (slice.len()).min(clip.frame_count() + 1);
 
         let frames = clip.frames();
         for i in 0..len {
-            slice[i] += frames[i].0 * volume;
+            let l0 = if i > 0 { frames[i - 1].0 } else { 0. };
+            let l1 = frames.get(i).map_or(0., |it| it.0);
+            slice[i] += (l1 + (l0 - l1) * frac) * volume;
         }
 
         return len;
@@ -496,16 +1664,30 @@ pub async fn main(cmd: bool) -> Result<()> {
 
     if volume_music != 0.0 {
         let music_time = Instant::now();
-        let pos = o - offset.min(0.) as f64;
-        let len = ((track_length + config.ending_length) * sample_rate_f64) as usize;
-        let start_index = (pos * sample_rate_f64).round() as usize * 2;
+        // with `speed_resample` the music is simply played back faster (or slower),
+        // pitch included, the same way the chart is
+        let pos = o - offset.min(0.) as f64 / speed;
+        let len = ((track_length / speed + config.ending_length) * sample_rate_f64) as usize;
+        // start on the first whole sample at or after `pos` and sample the music at the
+        // exact (fractional) time that sample corresponds to
+        let start = (pos * sample_rate_f64).ceil() as usize;
+        let skew = start as f64 / sample_rate_f64 - pos;
         let ratio = 1.0 / sample_rate_f64;
-        let slice = &mut output[start_index..];
+        let slice = &mut output[start * 2..];
         for i in 0..len.min(slice.len() / 2) {
-            let position = i as f64 * ratio + offset.max(0.) as f64;
+            let played = i as f64 * ratio + skew;
+            let position = played * speed + offset.max(0.) as f64;
+            let mut gain = volume_music;
+            if played < fade_in {
+                gain *= (played / fade_in) as f32;
+            }
+            let remaining = (track_length - position) / speed;
+            if remaining < fade_out {
+                gain *= (remaining / fade_out).max(0.) as f32;
+            }
             let frame = music.sample(position as f32).unwrap_or_default();
-            slice[i * 2] += frame.0 * volume_music;
-            slice[i * 2 + 1] += frame.1 * volume_music;
+            slice[i * 2] += frame.0 * gain;
+            slice[i * 2 + 1] += frame.1 * gain;
         }
         //ending
         let mut pos = o + length + musica - offset as f64;
@@ -523,11 +1705,10 @@ pub async fn main(cmd: bool) -> Result<()> {
         info!("Render Music Time:{:.2?}", music_time.elapsed())
     }
 
-    let threshold = 1.0;
-    let attack_time = 0.0;
-    let release_time = 0.0;
-    let attack_coeff = (1.0 - (-2.0 / (attack_time * sample_rate as f32)).exp()).min(1.0);
-    let release_coeff = (1.0 - (-2.0 / (release_time * sample_rate as f32)).exp()).min(1.0);
+    let threshold = config.compression_threshold;
+    // output2 is interleaved stereo, so the envelope advances twice per frame
+    let attack_coeff = envelope_coeff(config.compression_attack, sample_rate as f32 * 2.);
+    let release_coeff = envelope_coeff(config.compression_release, sample_rate as f32 * 2.);
     let mut gain_reduction = 1.0;
 
     fn apply_compressor(
@@ -557,32 +1738,61 @@ pub async fn main(cmd: bool) -> Result<()> {
         sample * *gain_reduction
     }
 
-    type AudioMap = std::collections::HashMap<String, AudioClip>;
+    type AudioMap = HashMap<String, AudioClip>;
     let mut extra_sfxs: AudioMap = AudioMap::new();
 
     chart.hitsounds.drain().for_each(|(name, clip)| {
         extra_sfxs.insert(name, clip);
     });
 
+    // how much earlier each clip is placed, so its attack lands on the note
+    let onset = |name: &str, clip: &AudioClip| {
+        if !config.hitsound_onset {
+            return 0.;
+        }
+        let onset = audio::onset(clip.frames(), sample_rate);
+        if onset > 0. {
+            info!("Hitsound {} starts {:.1}ms in", name, onset * 1000.);
+        }
+        onset
+    };
+    let onsets = [
+        onset("click", &sfx_click),
+        onset("drag", &sfx_drag),
+        onset("flick", &sfx_flick),
+    ];
+    let extra_onsets: HashMap<&str, f64> =
+        extra_sfxs.iter().map(|(name, clip)| (name.as_str(), onset(name, clip))).collect();
+
     let get_hitsound = |note: &Note| {
         match &note.hitsound {
             HitSound::None => None,
-            HitSound::Click => Some(&sfx_click),
-            HitSound::Flick => Some(&sfx_flick),
-            HitSound::Drag => Some(&sfx_drag),
-            HitSound::Custom(s) => extra_sfxs.get(s)
+            HitSound::Click => Some((&sfx_click, onsets[0])),
+            HitSound::Drag => Some((&sfx_drag, onsets[1])),
+            HitSound::Flick => Some((&sfx_flick, onsets[2])),
+            HitSound::Custom(s) => extra_sfxs.get(s).map(|it| (it, extra_onsets[s.as_str()])),
         }
     };
 
     if volume_sfx != 0.0 {
         let sfx_time = Instant::now();
         let offset = config.judge_offset as f64;
+        // humanization, in seconds. Seeded so re-renders sound identical
+        let jitter = config.hitsound_jitter as f64 / 1000.;
+        let mut rng = StdRng::seed_from_u64(config.hitsound_jitter_seed);
+        let mut jitter_time = || {
+            if jitter > 0. {
+                rng.gen_range(-jitter..=jitter)
+            } else {
+                0.
+            }
+        };
         if agg {
             for line in &chart.lines {
                 for note in &line.notes {
                     if !note.fake {
-                        if let Some(sfx) = get_hitsound(note) {
-                            place_agg(o + note.time as f64 + offset, sfx, volume_sfx);
+                        if let Some((sfx, onset)) = get_hitsound(note) {
+                            place_agg(timeline.video_time(note.time) + offset - onset + jitter_time(), sfx, volume_sfx);
                         }
                     }
                 }
@@ -591,8 +1801,8 @@ pub async fn main(cmd: bool) -> Result<()> {
             for line in &chart.lines {
                 for note in &line.notes {
                     if !note.fake {
-                        if let Some(sfx) = get_hitsound(note) {
-                            place(o + note.time as f64 + offset, sfx, volume_sfx);
+                        if let Some((sfx, onset)) = get_hitsound(note) {
+                            place(timeline.video_time(note.time) + offset - onset + jitter_time(), sfx, volume_sfx);
                         }
                     }
                 }
@@ -603,29 +1813,33 @@ pub async fn main(cmd: bool) -> Result<()> {
 
     {
         let mixing_time = Instant::now();
-        if config.force_limit {
-            if config.aggressive {
-                for i in 0..output2_agg.len() {
-                    output2_agg[i] = output2_agg[i]
-                        .clamp(-config.limit_threshold, config.limit_threshold)
+        if !config.force_limit && config.compression_ratio > 1. {
+            if agg {
+                // the aggressive hitsounds are mono, one sample per frame
+                let attack_coeff = envelope_coeff(config.compression_attack, sample_rate as f32);
+                let release_coeff = envelope_coeff(config.compression_release, sample_rate as f32);
+                for sample in &mut output2_agg {
+                    *sample = apply_compressor(
+                        *sample,
+                        threshold,
+                        config.compression_ratio,
+                        attack_coeff,
+                        release_coeff,
+                        &mut gain_reduction,
+                    );
                 }
             } else {
                 for i in 0..output2.len() {
-                    output2[i] = output2[i]
-                        .clamp(-config.limit_threshold, config.limit_threshold)
+                    output2[i] = apply_compressor(
+                        output2[i],
+                        threshold,
+                        config.compression_ratio,
+                        attack_coeff,
+                        release_coeff,
+                        &mut gain_reduction,
+                    );
                 }
             }
-        } else if config.compression_ratio > 1. {
-            for i in 0..output2.len() {
-                output2[i] = apply_compressor(
-                    output2[i],
-                    threshold,
-                    config.compression_ratio,
-                    attack_coeff,
-                    release_coeff,
-                    &mut gain_reduction,
-                );
-            }
         }
 
         if agg {
@@ -639,6 +1853,17 @@ pub async fn main(cmd: bool) -> Result<()> {
             }
         }
 
+        if config.force_limit {
+            let limit_time = Instant::now();
+            limit(
+                &mut output,
+                config.limit_threshold,
+                (config.limit_lookahead.unwrap_or(5.) / 1000. * sample_rate as f32) as usize,
+                envelope_coeff(config.limit_release.unwrap_or(60.), sample_rate as f32),
+            );
+            info!("Limiter Time:{:.2?}", limit_time.elapsed());
+        }
+
         if !config.hires {
             for i in 0..output.len() {
                 output[i] = output[i].clamp(-config.limit_threshold, config.limit_threshold);
@@ -647,264 +1872,971 @@ pub async fn main(cmd: bool) -> Result<()> {
         info!("Mixing Time:{:.2?}", mixing_time.elapsed());
     }
 
-    {
-        let output_audio_time = Instant::now();
-        let mut proc = cmd_hidden(&ffmpeg)
-            .args(
-                format!(
-                    "-y -f f32le -ar {} -ac 2 -i - -c:a pcm_f32le -f wav",
-                    sample_rate
-                )
-                .split_whitespace(),
-            )
-            .arg(mixing_output.path())
-            .arg("-loglevel")
-            .arg("warning")
-            .stdin(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .with_context(|| tl!("run-ffmpeg-failed"))?;
-        let input = proc.stdin.as_mut().unwrap();
-        let mut writer = BufWriter::new(input);
-        for sample in output.into_iter() {
-            writer.write_all(&sample.to_le_bytes())?;
-        }
-        drop(writer);
-        proc.wait()?;
-        info!("Output Audio Time:{:.2?}", output_audio_time.elapsed());
-    }
-
-    if ipc {
-        send(IPCEvent::Loading);
-    }
+    Ok(output)
+}
 
-    let preparing_render_time = Instant::now();
-    let (vw, vh) = config.resolution;
-    let mst = Rc::new(MSRenderTarget::new((vw, vh), config.sample_count));
-    let my_time: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.));
-    let tm = TimeManager::manual(Box::new({
-        let my_time = Rc::clone(&my_time);
-        move || *(*my_time).borrow()
-    }));
-    static MSAA: AtomicBool = AtomicBool::new(false);
-    let player = build_player(&config).await?;
-    let mut main = Main::new(
-        Box::new(
-            LoadingScene::new(
-                GameMode::Normal,
-                info,
-                &prpr_config,
-                fs,
-                Some(player),
-                None,
-                None,
+/// Writes the mix to a WAV file at `path` in the requested sample format.
+fn write_audio(ffmpeg: &str, output: Vec<f32>, bit_depth: AudioBitDepth, path: &Path) -> Result<()> {
+    let output_audio_time = Instant::now();
+    let (sample_format, codec) = match bit_depth {
+        AudioBitDepth::F32 => ("f32le", "pcm_f32le"),
+        AudioBitDepth::S24 => ("s24le", "pcm_s24le"),
+        AudioBitDepth::S16 => ("s16le", "pcm_s16le"),
+    };
+    let mut proc = cmd_hidden(ffmpeg)
+        .args(
+            format!(
+                "-y -f {} -ar {} -ac 2 -i - -c:a {} -f wav",
+                sample_format, SAMPLE_RATE, codec
             )
-            .await?,
-        ),
-        tm,
-        {
-            let mut cnt = 0;
-            let mst = Rc::clone(&mst);
-            move || {
-                cnt += 1;
-                if cnt == 1 || cnt == 3 {
-                    MSAA.store(true, Ordering::SeqCst);
-                    Some(mst.input())
-                } else {
-                    MSAA.store(false, Ordering::SeqCst);
-                    Some(mst.output())
-                }
+            .split_whitespace(),
+        )
+        .arg(path)
+        .arg("-loglevel")
+        .arg("warning")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| failure!("ffmpeg", "run-ffmpeg-failed"))?;
+    let input = proc.stdin.as_mut().unwrap();
+    let mut writer = BufWriter::new(input);
+    let mut dither = TpdfDither::new();
+    match bit_depth {
+        AudioBitDepth::F32 => {
+            for sample in output.into_iter() {
+                writer.write_all(&sample.to_le_bytes())?;
             }
-        },
-    )
-    .await?;
-    main.top_level = false;
-    main.viewport = Some((0, 0, vw as _, vh as _));
-
-    let fps = config.fps;
-    let frames = (video_length * fps as f64 + N as f64 - 1.).ceil() as u64;
-
-
-    let test_encoder = |encoder: &str| -> bool {
-        let output = Command::new(&ffmpeg)
-            .args(&["-f", "lavfi", "-i", "color=c=black:s=320x240:d=0", "-c:v", encoder, "-f", "null", "-"])
-            .arg("-loglevel")
-            .arg("fatal")
-            .arg("-hide_banner")
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Failed to test encoder");
-    
-        output.status.success()
-    };
-
-    let use_cuda = config.hardware_accel && test_encoder("h264_nvenc");
-    let has_qsv = config.hardware_accel && test_encoder("h264_qsv");
-    let has_amf = config.hardware_accel && test_encoder("h264_amf");
+        }
+        AudioBitDepth::S24 => {
+            for sample in output.into_iter() {
+                writer.write_all(&dither.quantize(sample, 24).to_le_bytes()[..3])?;
+            }
+        }
+        AudioBitDepth::S16 => {
+            for sample in output.into_iter() {
+                writer.write_all(&(dither.quantize(sample, 16) as i16).to_le_bytes())?;
+            }
+        }
+    }
+    drop(writer);
+    proc.wait()?;
+    info!("Output Audio Time:{:.2?}", output_audio_time.elapsed());
+    Ok(())
+}
 
-    let use_cuda_hevc = config.hardware_accel && config.hevc && test_encoder("hevc_nvenc");
-    let has_qsv_hevc = config.hardware_accel && config.hevc && test_encoder("hevc_qsv");
-    let has_amf_hevc = config.hardware_accel && config.hevc && test_encoder("hevc_amf");
+/// Fade in and out of burned-in lyrics, in seconds.
+const LYRICS_FADE: f64 = 0.15;
 
-    let ffmpeg_preset = "-preset";
-    let mut ffmpeg_preset_name_list = config.ffmpeg_preset.split_whitespace();
+/// Step the simulation advances by while seeking to a still frame, in seconds.
+const STILL_STEP: f64 = 0.1;
 
-    if config.hardware_accel && !config.mpeg4 {
-        if !(use_cuda_hevc || has_qsv_hevc || has_amf_hevc) && config.hevc {
-            bail!(tl!("no-hwacc"));
-        } else if !(use_cuda || has_qsv || has_amf) {
-            bail!(tl!("no-hwacc"));
-        }
-    }
+/// Step used to find the beats for zoom pulses, in seconds.
+const BEAT_STEP: f32 = 0.005;
 
-    let ffmpeg_encoder = if config.mpeg4 {
-        "mpeg4"
-    } else if use_cuda_hevc {
-        "hevc_nvenc"
-    } else if use_cuda {
-        "h264_nvenc"
-    } else if has_qsv_hevc {
-        "hevc_qsv"
-    } else if has_qsv {
-        "h264_qsv"
-    } else if has_amf_hevc {
-        "hevc_amf"
-    } else if has_amf {
-        "h264_amf"
-    } else {
-        if config.hevc {
-            "libx265"
-        } else {
-            "libx264"
-        }
-    };
+/// Chart time either side of the cursor shown by the beat grid, in seconds.
+const BEAT_GRID_SPAN: f32 = 2.;
 
-    info!("Encoder: {}", ffmpeg_encoder);
+/// How much smaller than the frame the blurred background is kept.
+const BACKGROUND_DOWNSAMPLE: f32 = 24.;
+/// Brightness of the moving background, close to prpr's dimming.
+const BACKGROUND_BRIGHTNESS: f32 = 0.4;
 
-    let ffmpeg_preset_name = if use_cuda {
-        ffmpeg_preset_name_list.nth(1).unwrap_or(
-            ffmpeg_preset_name_list.nth(0).unwrap_or("p4")
-        )
-    } else if has_qsv {
-        ffmpeg_preset_name_list.nth(2).unwrap_or("medium")
-    } else if has_amf {
-        ffmpeg_preset_name_list.nth(3).unwrap_or(
-            ffmpeg_preset_name_list.nth(0).unwrap_or("balanced")
-        )
-    } else {
-        ffmpeg_preset_name_list.nth(0).unwrap_or("medium")
-    };
+/// How busy the chart is around `time`, as recent notes weighted by how long
+/// ago they were hit. `times` must be sorted.
+fn note_intensity(times: &[f32], time: f32) -> f32 {
+    let end = times.partition_point(|&it| it <= time);
+    let start = times.partition_point(|&it| it <= time - 1.5);
+    times[start..end].iter().map(|&it| (-(time - it) * 3.).exp()).sum()
+}
 
-    let bitrate_control = 
-    if config.bitrate_control.to_lowercase() == "crf" {
-        if use_cuda && !config.mpeg4 {
-            "-cq"
-        } else if has_qsv || config.mpeg4 {
-            "-q"
-        }
-        else if has_amf {
-            "-qp_p"
-        } else {
-            "-crf"
+/// Samples analysed for each visualizer frame.
+const VISUALIZER_WINDOW: usize = 2048;
+const VISUALIZER_BANDS: usize = 48;
+const VISUALIZER_POINTS: usize = 256;
+
+/// Visualizer values for every video frame of the interleaved stereo `mix`:
+/// band levels (0 to 1) for [`Visualizer::Spectrum`], samples (-1 to 1) for
+/// [`Visualizer::Waveform`].
+fn visualizer_frames(kind: Visualizer, mix: &[f32], fps: u32, video_length: f64) -> Vec<Vec<f32>> {
+    let frames = (video_length * fps as f64).ceil() as usize;
+    let mono = |i: isize| -> f32 {
+        if i < 0 {
+            return 0.;
         }
-    } else {
-        "-b:v"
+        let i = i as usize * 2;
+        mix.get(i..i + 2).map_or(0., |it| (it[0] + it[1]) / 2.)
     };
+    // bars rise at once and fall over about 2/3 of a second
+    let fall = 1.5 / fps as f32;
+    let mut previous = vec![0.; VISUALIZER_BANDS];
+    (0..frames)
+        .map(|frame| {
+            let center = (frame as f64 / fps as f64 * SAMPLE_RATE as f64) as isize;
+            let start = center - VISUALIZER_WINDOW as isize / 2;
+            let window: Vec<f32> = (0..VISUALIZER_WINDOW as isize).map(|i| mono(start + i)).collect();
+            match kind {
+                Visualizer::Spectrum => {
+                    let levels = audio::spectrum(&window, SAMPLE_RATE as f32, VISUALIZER_BANDS);
+                    for (level, previous) in levels.into_iter().zip(previous.iter_mut()) {
+                        *previous = level.max(*previous - fall);
+                    }
+                    previous.clone()
+                }
+                Visualizer::Waveform => window
+                    .iter()
+                    .step_by(VISUALIZER_WINDOW / VISUALIZER_POINTS)
+                    .map(|it| it.clamp(-1., 1.))
+                    .collect(),
+                Visualizer::Off => Vec::new(),
+            }
+        })
+        .collect()
+}
 
-    let bitrate = config.bitrate;
+/// `frame <asset path> <time>`: renders the frame at `time` seconds of the video
+/// (params and output path as for `render`) and saves it as a PNG.
+pub async fn frame_main() -> Result<()> {
+    let at: f64 = std::env::args()
+        .nth(3)
+        .context("missing frame time")?
+        .parse()
+        .context("invalid frame time")?;
+    render(false, Some(at.max(0.))).await
+}
 
-    let mut args = "-probesize 50M -y -f rawvideo -c:v rawvideo".to_owned();
-    if use_cuda {
-        args += " -hwaccel_output_format cuda";
+pub async fn main(cmd: bool) -> Result<()> {
+    let result = render(cmd, None).await;
+    if let (Err(err), false) = (&result, cmd) {
+        let failure = err.downcast_ref::<Failure>();
+        crate::ipc::client::send(IPCEvent::Error {
+            code: failure.map_or("unknown", |it| it.code).to_owned(),
+            stage: failure.map_or("render", |it| it.stage).to_owned(),
+            message: failure.map_or_else(|| err.to_string(), |it| it.message.clone()),
+            detail: format!("{err:?}"),
+        });
     }
-    write!(
-        &mut args,
-        " -s {vw}x{vh} -r {fps} -pix_fmt rgba -thread_queue_size 1024 -i - -i"
-    )?;
-
-    let args2 = format!(
-        "-c:a {} -c:v {} -pix_fmt yuv420p {} {} {} {} -map 0:v:0 -map 1:a:0 {} -vf vflip -f {}",
-        if config.hires {
-            "copy"
-        } else {
-            "aac -b:a 320k"
-        },
-        ffmpeg_encoder,
-        bitrate_control,
-        bitrate,
-        ffmpeg_preset,
-        ffmpeg_preset_name,
-        if config.disable_loading {
-            format!("-ss {}", o)
-        } else {
-            "".to_string()
-        },
-        if config.hires { "mov" } else { "mp4" }
-    );
-
-    info!(
-        "Preparing Render Time:{:.2?}",
-        preparing_render_time.elapsed()
-    );
-    let pre_render_time = Instant::now();
+    result
+}
 
-    //info!("Command: {} {} {} {} {}", "ffmpeg", args,"-", args2, output_path.display());
-    let mut proc = cmd_hidden(&ffmpeg)
-        .args(args.split_whitespace())
-        .arg(mixing_output.path())
-        .args(args2.split_whitespace())
-        .arg(output_path)
-        .arg("-loglevel")
-        .arg("warning")
-        .stdin(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .with_context(|| tl!("run-ffmpeg-failed"))?;
-    let mut input = proc.stdin.take().unwrap();
-
-    let byte_size = vw as usize * vh as usize * 4;
-
-    const N: usize = 60;
-    let mut pbos: [GLuint; N] = [0; N];
-    unsafe {
-        use miniquad::gl::*;
-        glGenBuffers(N as _, pbos.as_mut_ptr());
-        for pbo in pbos {
-            glBindBuffer(GL_PIXEL_PACK_BUFFER, pbo);
-            glBufferData(
-                GL_PIXEL_PACK_BUFFER,
-                (vw as u64 * vh as u64 * 4) as _,
-                std::ptr::null(),
-                GL_STREAM_READ,
-            );
-        }
-        glBindBuffer(GL_PIXEL_PACK_BUFFER, 0);
+async fn render(cmd: bool, still: Option<f64>) -> Result<()> {
+    let loading_time = Instant::now();
+    if !cmd {
+        crate::ipc::client::send(IPCEvent::Hello {
+            protocol_version: IPC_PROTOCOL_VERSION,
+            renderer_version: env!("CARGO_PKG_VERSION").to_owned(),
+        });
     }
 
-    if ipc {
-        send(IPCEvent::StartRender(frames));
-    }
+    let mut reporter = FrameReporter::new(None, None);
+    let (fs, output_path, mut config, info, subtitles, overlay_items, job, resume) = 
+    if cmd {
+        init_assets();
 
-    let fps = fps as f64;
-    for frame in 0..N {
-        *my_time.borrow_mut() = (frame as f64 / fps).max(0.);
-        gl.quad_gl.render_pass(Some(mst.output().render_pass));
-        main.update()?;
-        main.render(&mut painter)?;
-        if *my_time.borrow() <= LoadingScene::TOTAL_TIME as f64 && !config.disable_loading {
-            draw_rectangle(0., 0., 0., 0., Color::default());
+        #[cfg(target_os = "windows")]
+        {
+            let app_data_dir = std::env::var("APPDATA").unwrap();
+            let data_dir = PathBuf::from(app_data_dir).join("com.hlmc.phi.recorder");
+            DATA_DIR.set(ensure_dir(data_dir.clone())).unwrap();
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            DATA_DIR
+                .set(ensure_dir(std::env::current_dir().unwrap().to_owned()))
+                .unwrap();
+        }
+
+        let config = match (|| -> Result<RenderConfig> {
+            Ok(serde_yaml::from_str(
+                &std::fs::read_to_string("config.yml").context("error reading config")?,
+            )?)
+        })() {
+            Err(err) => {
+                warn!("error loading config: {:?}", err);
+                RenderConfig::default()
+            }
+            Ok(config) => config,
+        };
+        let path = std::env::args().nth(2).unwrap();
+
+        let mut fs = archive::open(path.as_ref())?;
+        let info = fs::load_info(fs.deref_mut()).await?;
+        let level: String = info
+            .level
+            .split_whitespace()
+            .next()
+            .unwrap_or("UK")
+            .to_string();
+        let safe_name: String = info
+            .name
+            .chars()
+            .filter(|&it| it == '-' || it == '_' || it.is_alphanumeric())
+            .collect();
+        let safe_name2: String = info
+            .composer
+            .chars()
+            .filter(|&it| it == '-' || it == '_' || it.is_alphanumeric())
+            .collect();
+        let format = if config.hires { "mov" } else { "mp4" };
+
+        let file_name = if config.simple_file_name {
+            format!(
+                "{safe_name}.{safe_name2}_{level}.{format}",
+            )
+        } else {
+            format!(
+                "{} {safe_name}_{level}.{format}",
+                Local::now().format("%Y-%m-%d %H-%M-%S")
+            )
+        };
+        let output_path = if std::env::args().len() > 3 {
+            let dir = std::env::args().nth(3).unwrap();
+            let output_dir = PathBuf::from(dir);
+            info!("output dir: {:?}", output_dir);
+            let_output_dir(output_dir)?.join(file_name)
+        } else {
+            output_dir()?.join(file_name)
+        };
+
+        let job = serde_json::json!({ "path": path, "info": &info, "config": &config });
+        (fs, output_path, config, info, None, Vec::new(), job, None)
+    }
+    else {
+        let asset_dir = std::env::args().nth(2).unwrap();
+        set_pc_assets_folder(&asset_dir);
+        let broken = checksum::verify_assets(Path::new(&asset_dir));
+        if !broken.is_empty() {
+            bail!(failure!("loading", "assets-corrupted", "files" => broken.join(", ")));
+        }
+
+        let (params, output_path) = read_job(if still.is_some() { 4 } else { 3 })?;
+        if let Some(job) = &params.job_id {
+            crate::ipc::client::set_job(job.clone());
+        }
+        let fs = archive::open(&params.path)?;
+        let job = serde_json::to_value(&params)?;
+        reporter = FrameReporter::new(params.progress_frames, params.progress_millis);
+    
+        let config = params.config;
+        let info = params.info;
+
+        (fs, output_path, config, info, params.subtitles, params.overlays, job, params.resume)
+    };
+
+    config.apply_quality();
+    config.apply_draft();
+    if config.low_priority {
+        if let Err(err) = lower_priority() {
+            warn!("Failed to lower priority: {err:?}");
+        }
+    }
+    let cores = config.cpu_cores();
+    if !cores.is_empty() {
+        if let Err(err) = pin_to_cores(&cores) {
+            warn!("Failed to set CPU affinity: {err:?}");
+        }
+    }
+    // released when the render returns, and by the OS if the process is killed
+    let _awake = if config.keep_awake {
+        KeepAwake::acquire().map_err(|err| warn!("Failed to keep the machine awake: {err:?}")).ok()
+    } else {
+        None
+    };
+
+
+    use crate::ipc::client::*;
+    let ipc = if cmd { false } else { true };
+    let font_data = load_file("font.ttf").await?;
+    let font = FontArc::try_from_vec(font_data.clone())?;
+    let mut painter = TextPainter::new(font);
+    let overlay = Overlay::new(&font_data, config.resolution)?;
+    let mut prpr_config = config.to_config();
+    prpr_config.mods |= Mods::AUTOPLAY;
+    prpr_config.disable_audio = true;
+    let Some(ffmpeg) = find_ffmpeg()? else {
+        bail!(failure!("ffmpeg", "ffmpeg-not-found"))
+    };
+    info!("ffmpeg: {}", &ffmpeg);
+    if let Some(path) = &config.res_pack_path {
+        checksum::verify_respack(Path::new(path)).with_context(|| failure!("loading", "respack-corrupted"))?;
+    }
+
+    // kept alive for as long as prpr and the mixer may read from the pack
+    let _skin = apply_skin_overrides(&ffmpeg, &mut config).await?;
+    prpr_config.res_pack_path = config.res_pack_path.clone();
+
+    let line_texture = line_texture(&config)?;
+    let textured = line_texture.is_some();
+    let mut fs = mods::wrap(fs, &info.chart, config.chart_mods(), line_texture);
+    if textured && !mods::is_rpe(&fs.load_file(&info.chart).await?) {
+        let message = tl!("line-texture-unsupported").to_string();
+        warn!("{message}");
+        if ipc {
+            send(IPCEvent::Warning {
+                code: "line-texture-unsupported".to_owned(),
+                message,
+            });
+        }
+    }
+
+    let (mut chart, ..) = GameScene::load_chart(fs.deref_mut(), &info)
+        .await
+        .with_context(|| failure!("loading", "load-chart-failed"))?;
+    let music: Result<_> = async { AudioClip::new(fs.load_file(&info.music).await?) }.await;
+    let music = music.with_context(|| failure!("loading", "load-music-failed"))?;
+
+    let splash = if config.disable_loading && config.splash_duration > 0. {
+        let bytes = match &config.splash_image {
+            Some(path) => tokio::fs::read(path)
+                .await
+                .with_context(|| failure!("loading", "load-splash-failed"))?,
+            None => fs.load_file(&info.illustration).await?,
+        };
+        Some(Texture2D::from_file_with_format(&bytes, None))
+    } else {
+        None
+    };
+    let bga_name = if config.bga && config.render_bg {
+        fs.list_root().ok().and_then(|names| bga::find(&names).cloned())
+    } else {
+        None
+    };
+    let bga = match bga_name {
+        Some(name) => {
+            let bytes = fs
+                .load_file(&name)
+                .await
+                .with_context(|| failure!("loading", "load-bga-failed", "name" => name.clone()))?;
+            let bga = Bga::open(
+                &ffmpeg,
+                &name,
+                &bytes,
+                config.resolution,
+                config.fps,
+                config.bga_blur,
+                config.bga_offset,
+            )
+            .with_context(|| failure!("loading", "load-bga-failed", "name" => name.clone()))?;
+            // drawn by the recorder instead, like a moving background
+            prpr_config.render_bg = false;
+            Some((RefCell::new(bga), overlay::screen_material()?))
+        }
+        None => None,
+    };
+    let background_motion = config.render_bg && config.background_motion != BackgroundMotion::Off && bga.is_none();
+    let background = if background_motion {
+        let illustration = Texture2D::from_file_with_format(&fs.load_file(&info.illustration).await?, None);
+        // a tiny copy looks blurred once scaled back up, like prpr's background
+        Some((overlay.downsample(illustration, BACKGROUND_DOWNSAMPLE), overlay::screen_material()?))
+    } else {
+        None
+    };
+    let title = info.name.clone();
+    let subtitle = format!("{}  {}", info.level, info.composer);
+    let credits: Vec<String> = [
+        ("credit-charter", &info.charter),
+        ("credit-illustrator", &info.illustrator),
+        ("credit-composer", &info.composer),
+    ]
+    .into_iter()
+    .filter(|(_, name)| !name.is_empty())
+    .map(|(role, name)| tl!(role, "name" => name.as_str()).to_string())
+    .collect();
+    let credit_duration = config.credit_duration.max(0.) as f64;
+
+    let mut credits_roll = Vec::new();
+    if config.ending_credits {
+        let respack_author = config.res_pack_path.as_deref().and_then(respack_author);
+        credits_roll.push(info.name.clone());
+        credits_roll.push(String::new());
+        for (role, name) in [
+            ("roll-composer", Some(&info.composer)),
+            ("roll-charter", Some(&info.charter)),
+            ("roll-illustrator", Some(&info.illustrator)),
+            ("roll-respack", respack_author.as_ref()),
+        ] {
+            if let Some(name) = name.filter(|it| !it.is_empty()) {
+                credits_roll.push(tl!(role).to_string());
+                credits_roll.push(format!("  {name}"));
+            }
+        }
+        credits_roll.extend(config.ending_credits_extra.iter().cloned());
+        credits_roll.push(String::new());
+        credits_roll.push(tl!("roll-recorded-with", "version" => env!("CARGO_PKG_VERSION")).to_string());
+    }
+
+    let watermark = match &config.watermark_style {
+        Some(style) if !config.watermark.is_empty() => {
+            let color = overlay::parse_color(&style.color)
+                .with_context(|| format!("invalid watermark color {}", style.color))?;
+            Some((style.clone(), color))
+        }
+        _ => None,
+    };
+
+    let forensic = match config.forensic_key.as_deref() {
+        Some(key) if !key.is_empty() => Some((
+            forensic::pattern(key),
+            (overlay::additive_material(false)?, overlay::additive_material(true)?),
+        )),
+        _ => None,
+    };
+    let forensic_strength = config.forensic_strength.unwrap_or(2.) / 255.;
+
+    let mut script = Vec::with_capacity(overlay_items.len());
+    for item in overlay_items {
+        let texture = match &item.image {
+            Some(path) => Some(Texture2D::from_file_with_format(
+                &tokio::fs::read(path)
+                    .await
+                    .with_context(|| failure!("loading", "load-overlay-failed", "path" => path.display().to_string()))?,
+                None,
+            )),
+            None => None,
+        };
+        let color = match &item.style.color {
+            Some(hex) => overlay::parse_color(hex).with_context(|| format!("invalid overlay color {hex}"))?,
+            None => WHITE,
+        };
+        script.push((item, texture, color));
+    }
+
+    let lyrics = match &subtitles {
+        Some(path) => lyrics::load(path)?,
+        None => Vec::new(),
+    };
+    let burn_lyrics = config.lyrics_mode != LyricsMode::Soft && !lyrics.is_empty();
+    let lyrics_font = match (&config.lyrics_font, burn_lyrics) {
+        (Some(path), true) => {
+            let bytes = std::fs::read(path).with_context(|| format!("failed to read {path}"))?;
+            Some(overlay::load_font(&bytes)?)
+        }
+        _ => None,
+    };
+    let lyrics_color = match &config.lyrics_color {
+        Some(hex) => overlay::parse_color(hex).with_context(|| format!("invalid lyrics color {hex}"))?,
+        None => WHITE,
+    };
+
+    // chart times at which the combo goes up; holds count once they are released
+    let custom_combo = config.render_ui_combo && config.custom_combo();
+    let mut combo_times: Vec<f32> = if custom_combo && !config.all_bad {
+        chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .map(|note| match note.kind {
+                NoteKind::Hold { end_time, .. } => end_time,
+                _ => note.time,
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    combo_times.sort_by(|x, y| x.total_cmp(y));
+    let combo_min = config.combo_min.unwrap_or(3) as usize;
+    let combo_scale = config.combo_scale.unwrap_or(1.);
+
+    let mut results = Vec::new();
+    if config.ending_show_stats {
+        let notes = chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .count();
+        let (perfect, good, bad, combo) = if config.all_bad {
+            (0, 0, notes, 0)
+        } else if config.all_good {
+            (0, notes, 0, notes)
+        } else {
+            (notes, 0, 0, notes)
+        };
+        let formula = config.score_formula.unwrap_or(if config.phira_mode {
+            ScoreFormula::Phira
+        } else {
+            ScoreFormula::Phigros
+        });
+        let (score, accuracy) = formula.score(perfect, good, combo, notes);
+        results.push(tl!("results-score", "score" => format!("{score:07}")).to_string());
+        if formula == ScoreFormula::Phira {
+            results.push(tl!("results-accuracy", "accuracy" => format!("{:.2}", accuracy * 100.)).to_string());
+        }
+        results.push(tl!("results-max-combo", "combo" => combo.to_string()).to_string());
+        results.push(tl!("results-perfect", "count" => perfect.to_string()).to_string());
+        results.push(tl!("results-good", "count" => good.to_string()).to_string());
+        results.push(tl!("results-bad", "count" => bad.to_string()).to_string());
+        results.push(tl!("results-miss", "count" => "0").to_string());
+    }
+    if !config.ending_text.is_empty() {
+        results.push(config.ending_text.clone());
+    }
+
+    // combo and accuracy after each judgement, both normalized to [0, 1]
+    let mut graph = Vec::new();
+    if config.ending_graph {
+        // autoplay hits every note Perfect, Good with `allGood` or Bad with
+        // `allBad`; holds are judged when they are released
+        let mut judgements: Vec<(f32, f32)> = chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .map(|note| {
+                let time = match note.kind {
+                    NoteKind::Hold { end_time, .. } => end_time,
+                    _ => note.time,
+                };
+                let weight = if config.all_bad {
+                    0.
+                } else if config.all_good {
+                    0.65
+                } else {
+                    1.
+                };
+                (time, weight)
+            })
+            .collect();
+        judgements.sort_by(|x, y| x.0.total_cmp(&y.0));
+        let notes = judgements.len();
+        let end = judgements.last().map_or(1., |it| it.0).max(1e-3);
+        let step = (notes / 200).max(1);
+        let (mut combo, mut sum) = (0, 0.);
+        let mut combo_points = Vec::new();
+        let mut accuracy_points = Vec::new();
+        for (i, &(time, weight)) in judgements.iter().enumerate() {
+            // a Bad breaks the combo
+            combo = if weight > 0. { combo + 1 } else { 0 };
+            sum += weight;
+            if i % step == 0 || i + 1 == notes {
+                let x = time / end;
+                combo_points.push((x, combo as f32 / notes as f32));
+                accuracy_points.push((x, sum / (i + 1) as f32));
+            }
+        }
+        graph.push((combo_points, Color::new(0.35, 0.8, 1., 1.)));
+        graph.push((accuracy_points, Color::new(1., 0.85, 0.3, 1.)));
+    }
+
+    // note density for the NPS overlay
+    let mut nps_times: Vec<f32> = Vec::new();
+    let mut nps_curve = Vec::new();
+    let mut nps_curve_length = 1.;
+    if config.nps_overlay != NpsOverlay::Off {
+        nps_times = chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .map(|note| note.time)
+            .collect();
+        nps_times.sort_by(|x, y| x.total_cmp(y));
+    }
+    if config.nps_overlay == NpsOverlay::Graph {
+        let stats = ChartStats::of(&chart);
+        nps_curve_length = (stats.nps.len().max(2) - 1) as f32;
+        let peak = stats.nps.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let len = stats.nps.len().max(2) - 1;
+        let points = stats
+            .nps
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (i as f32 / len as f32, count as f32 / peak))
+            .collect();
+        nps_curve.push((points, Color::new(1., 1., 1., 0.8)));
+    }
+
+    let mut gl = unsafe { get_internal_gl() };
+
+    let timeline = Timeline::new(&config, &chart, &info, &music);
+    let o = timeline.o;
+    let video_length = timeline.video_length;
+
+    if let (Some(format), None) = (config.note_timeline, still) {
+        // times in the output, after the `-ss` cut
+        let mut events = probe::note_events(&chart, |it| timeline.video_time(it) - timeline.cut, config.fps);
+        if config.mirror {
+            for event in &mut events {
+                event.position_x = -event.position_x;
+            }
+        }
+        let (extension, content) = match format {
+            NoteTimelineFormat::Json => ("notes.json", serde_json::to_string_pretty(&events)?),
+            NoteTimelineFormat::Csv => ("notes.csv", probe::note_events_csv(&events)),
+        };
+        let path = output_path.with_extension(extension);
+        std::fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    info!("Loading Resources Time:{:.2?}", loading_time.elapsed());
+    info!("video length: {:.2}s", video_length);
+
+    let render_start_time = Instant::now();
+
+    if ipc {
+        send(IPCEvent::StartMixing);
+    }
+    let mixing_output = NamedTempFile::new()?;
+    let mut visualizer = Vec::new();
+    let mut loudness = None;
+    if still.is_none() {
+        let output = mix_audio(&config, &mut chart, &music, &timeline).await?;
+        let measured = audio::loudness(&output, SAMPLE_RATE);
+        info!(
+            "Loudness: {} LUFS, true peak {:.1} dBTP, {} clipped samples",
+            measured.integrated.map_or("-inf".to_owned(), |it| format!("{it:.1}")),
+            measured.true_peak,
+            measured.clipped
+        );
+        if ipc {
+            send(IPCEvent::Loudness(measured));
+        }
+        loudness = Some(measured);
+        if config.visualizer != Visualizer::Off {
+            visualizer = visualizer_frames(config.visualizer, &output, config.fps, video_length);
+        }
+        write_audio(&ffmpeg, output, config.audio_bit_depth, mixing_output.path())?;
+    }
+
+    if ipc {
+        send(IPCEvent::Loading);
+    }
+
+    let preparing_render_time = Instant::now();
+    let (vw, vh) = config.resolution;
+    let mst = Rc::new(MSRenderTarget::new((vw, vh), config.sample_count));
+    let my_time: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.));
+    let tm = TimeManager::manual(Box::new({
+        let my_time = Rc::clone(&my_time);
+        move || *(*my_time).borrow()
+    }));
+    static MSAA: AtomicBool = AtomicBool::new(false);
+    let mut player = build_player(&config).await?;
+    if config.ending_hide_avatar {
+        player.avatar = None;
+    }
+    let mut main = Main::new(
+        Box::new(
+            LoadingScene::new(
+                GameMode::Normal,
+                info,
+                &prpr_config,
+                fs,
+                (!config.ending_hide_rks).then_some(player),
+                None,
+                None,
+            )
+            .await?,
+        ),
+        tm,
+        {
+            let mut cnt = 0;
+            let mst = Rc::clone(&mst);
+            move || {
+                cnt += 1;
+                if cnt == 1 || cnt == 3 {
+                    MSAA.store(true, Ordering::SeqCst);
+                    Some(mst.input())
+                } else {
+                    MSAA.store(false, Ordering::SeqCst);
+                    Some(mst.output())
+                }
+            }
+        },
+    )
+    .await?;
+    main.top_level = false;
+    main.viewport = Some((0, 0, vw as _, vh as _));
+
+    let ending_start = timeline.ending_start();
+
+    // chart times at which zoom pulses and shakes start
+    let mut beat_times: Vec<f32> = Vec::new();
+    if config.zoom_pulse > 0. {
+        let every = config.zoom_pulse_every.unwrap_or(1).max(1) as f32;
+        let length = probe::chart_length(&chart);
+        let mut bpm_list = chart.bpm_list.borrow_mut();
+        let (mut last, mut time) = (f32::NEG_INFINITY, 0.);
+        while time <= length {
+            let beat = (bpm_list.beat(time) / every).floor();
+            if beat > last {
+                beat_times.push(time);
+                last = beat;
+            }
+            time += BEAT_STEP;
+        }
+    }
+    // chart times of every beat, for the beat grid
+    let mut grid_beats: Vec<f32> = Vec::new();
+    if config.beat_grid {
+        let length = probe::chart_length(&chart) + BEAT_GRID_SPAN;
+        let mut bpm_list = chart.bpm_list.borrow_mut();
+        let (mut last, mut time) = (f32::NEG_INFINITY, 0.);
+        while time <= length {
+            let beat = bpm_list.beat(time).floor();
+            if beat > last {
+                grid_beats.push(time);
+                last = beat;
+            }
+            time += BEAT_STEP;
+        }
+    }
+    let beats_per_measure = config.beats_per_measure.unwrap_or(4).max(1) as usize;
+    let mut shake_times: Vec<f32> = Vec::new();
+    if config.shake_intensity > 0. && !config.all_bad {
+        let every = config.shake_every.unwrap_or(100).max(1) as usize;
+        shake_times = chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .map(|note| match note.kind {
+                NoteKind::Hold { end_time, .. } => end_time,
+                _ => note.time,
+            })
+            .collect();
+        shake_times.sort_by(|x, y| x.total_cmp(y));
+        shake_times = shake_times.into_iter().skip(every - 1).step_by(every).collect();
+    }
+    // viewport of the scene for a video time, grown and moved for the camera effects
+    let camera = |time: f64| -> (i32, i32, i32, i32) {
+        let (w, h) = (vw as f32, vh as f32);
+        if time < o || time >= ending_start {
+            return (0, 0, w as i32, h as i32);
+        }
+        let now = timeline.chart_time(time);
+        let since = |times: &[f32]| {
+            let i = times.partition_point(|&it| it <= now);
+            (i > 0).then(|| now - times[i - 1])
+        };
+        let mut zoom = 1.;
+        if let Some(since) = since(&beat_times) {
+            zoom += config.zoom_pulse * (-since * 8.).exp();
+        }
+        let (mut dx, mut dy) = (0., 0.);
+        if let Some(since) = since(&shake_times) {
+            let strength = config.shake_intensity * (-since * 6.).exp();
+            // enough margin that the shake never shows an edge
+            zoom += strength * 2.;
+            dx = strength * h * (now * 83.).sin();
+            dy = strength * h * (now * 71.).cos();
+        }
+        let (zw, zh) = (w * zoom, h * zoom);
+        (
+            ((w - zw) / 2. + dx) as i32,
+            ((h - zh) / 2. + dy) as i32,
+            zw as i32,
+            zh as i32,
+        )
+    };
+    let background_start = o - GameScene::BEFORE_DURATION as f64;
+    let motion_amplitude = config.background_motion_amplitude.unwrap_or(0.03);
+    let mut motion_times: Vec<f32> = Vec::new();
+    let mut motion_peak = 1_f32;
+    if background.is_some() && config.background_motion == BackgroundMotion::Pulse {
+        motion_times = chart
+            .lines
+            .iter()
+            .flat_map(|line| line.notes.iter())
+            .filter(|note| !note.fake)
+            .map(|note| note.time)
+            .collect();
+        motion_times.sort_by(|x, y| x.total_cmp(y));
+        let length = motion_times.last().copied().unwrap_or(0.);
+        motion_peak = (0..=(length * 10.) as usize)
+            .map(|i| note_intensity(&motion_times, i as f32 / 10.))
+            .fold(1., f32::max);
+    }
+    let progress_style = if config.render_ui_bar {
+        config.progress_bar_style
+    } else {
+        ProgressBarStyle::Hidden
+    };
+    let has_overlay = matches!(progress_style, ProgressBarStyle::Line | ProgressBarStyle::Circle)
+        || custom_combo
+        || splash.is_some()
+        || !results.is_empty()
+        || !graph.is_empty()
+        || config.nps_overlay != NpsOverlay::Off
+        || !grid_beats.is_empty()
+        || !visualizer.is_empty()
+        || background.is_some()
+        || bga.is_some()
+        || watermark.is_some()
+        || forensic.is_some()
+        || config.debug_overlay.is_enabled()
+        || (credit_duration > 0. && !credits.is_empty())
+        || burn_lyrics
+        || !credits_roll.is_empty()
+        || !script.is_empty();
+    let script_cut = if config.disable_loading { timeline.cut } else { 0. };
+    // returns whether anything was drawn, in which case the frame needs another flush
+    let draw_overlay = |time: f64| -> bool {
+        if !has_overlay {
+            return false;
+        }
+        overlay.begin(mst.output());
+        if let Some((bga, material)) = &bga {
+            if time >= background_start && time < ending_start {
+                let texture = bga.borrow_mut().frame(timeline.chart_time(time));
+                let brightness = config.bga_brightness.unwrap_or(BACKGROUND_BRIGHTNESS);
+                overlay.backdrop(texture, *material, 1., (0., 0.), brightness);
+            }
+        }
+        if let Some((texture, material)) = background {
+            if time >= background_start && time < ending_start {
+                let t = time as f32;
+                let (zoom, offset) = match config.background_motion {
+                    BackgroundMotion::Pulse => {
+                        let intensity = note_intensity(&motion_times, timeline.chart_time(time)) / motion_peak;
+                        (1. + motion_amplitude * (1. + 2. * intensity.min(1.)), (0., 0.))
+                    }
+                    _ => {
+                        let offset = ((t * 0.05 * std::f32::consts::TAU).sin(), (t * 0.037 * std::f32::consts::TAU).cos());
+                        (1. + motion_amplitude * 2., (offset.0 * motion_amplitude, offset.1 * motion_amplitude))
+                    }
+                };
+                overlay.backdrop(texture, material, zoom, offset, BACKGROUND_BRIGHTNESS);
+            }
+        }
+        if splash.is_some() && time < o {
+            overlay.splash(splash, &title, &subtitle);
+        }
+        if time >= o && time < ending_start {
+            let progress = ((time - o) / (timeline.length - 1.).max(1e-3)).clamp(0., 1.) as f32;
+            overlay.progress(progress_style, progress);
+        }
+        if custom_combo && time >= o && time < ending_start {
+            let now = timeline.chart_time(time);
+            let combo = combo_times.partition_point(|&it| it <= now);
+            if combo > 0 && combo >= combo_min {
+                // punch: grows on every hit and settles within ~0.15s
+                let since = now - combo_times[combo - 1];
+                let punch = config.combo_punch * (-since.max(0.) * 20.).exp();
+                overlay.combo(combo, &config.combo, combo_scale * (1. + punch));
+            }
+        }
+        if let Some(values) = visualizer.get((time * config.fps as f64).round() as usize) {
+            let height = config.visualizer_height.unwrap_or(0.12);
+            overlay.visualizer(values, config.visualizer == Visualizer::Waveform, height);
+        }
+        if config.nps_overlay != NpsOverlay::Off && time >= o && time < ending_start {
+            let now = timeline.chart_time(time);
+            let nps = nps_times.partition_point(|&it| it <= now)
+                - nps_times.partition_point(|&it| it <= now - 1.);
+            let progress = (now / nps_curve_length.max(1e-3)).clamp(0., 1.);
+            overlay.nps(nps, &nps_curve, progress);
+        }
+        if !grid_beats.is_empty() && time >= o && time < ending_start {
+            overlay.beat_grid(&grid_beats, timeline.chart_time(time), BEAT_GRID_SPAN, beats_per_measure);
+        }
+        if !credits_roll.is_empty() && time >= ending_start {
+            let progress = ((time - ending_start) / (video_length - ending_start).max(1e-3)) as f32;
+            overlay.roll(&credits_roll, progress);
+        }
+        if burn_lyrics {
+            let lines: Vec<_> = lyrics
+                .iter()
+                .filter_map(|lyric| {
+                    let (start, end) = (
+                        timeline.music_video_time(lyric.start),
+                        timeline.music_video_time(lyric.end),
+                    );
+                    (time >= start && time < end).then(|| {
+                        let alpha = ((time - start).min(end - time) / LYRICS_FADE).min(1.) as f32;
+                        (lyric.text.as_str(), alpha)
+                    })
+                })
+                .collect();
+            let (x, y) = config.lyrics_position.unwrap_or((0.5, 0.85));
+            let size = config.lyrics_size.unwrap_or(0.045);
+            overlay.lyrics(&lines, lyrics_font, (x, y), size, lyrics_color);
+        }
+        // the script is timed to the finished video, which may skip the loading
+        let at = time - script_cut;
+        for (item, texture, color) in &script {
+            if at >= item.start && at < item.end {
+                let fade = item.style.fade.max(1e-3) as f64;
+                let alpha = ((at - item.start).min(item.end - at) / fade).min(1.) as f32;
+                overlay.item(item, *texture, *color, alpha);
+            }
+        }
+        if let Some((style, color)) = &watermark {
+            let (w, h) = (overlay.width(), overlay.height());
+            let (anchor, alpha) = style.place(time);
+            let margin = style.margin * h;
+            let x = margin + (w - margin * 2.) * anchor.0;
+            let y = margin + (h - margin * 2.) * anchor.1;
+            let color = Color::new(color.r, color.g, color.b, color.a * alpha);
+            if alpha > 0. {
+                overlay.watermark(&config.watermark, x, y, h * style.size, anchor, color, style.pill);
+            }
+        }
+        if credit_duration > 0. && !credits.is_empty() && time >= o && time < o + credit_duration {
+            let t = time - o;
+            let alpha = (t.min(credit_duration - t) / 0.3).min(1.) as f32;
+            let (w, h) = (overlay.width(), overlay.height());
+            overlay.panel(&credits, w * 0.03, h * 0.86, h * 0.028, (0., 1.), alpha);
+        }
+        if !results.is_empty() && time >= ending_start {
+            let alpha = ((time - ending_start) as f32 / 0.5).min(1.);
+            let (w, h) = (overlay.width(), overlay.height());
+            overlay.panel(&results, w * 0.04, h * 0.96, h * 0.035, (0., 1.), alpha);
+        }
+        if !graph.is_empty() && time >= ending_start {
+            let alpha = ((time - ending_start) as f32 / 0.5).min(1.);
+            let (w, h) = (overlay.width(), overlay.height());
+            overlay.graph(Rect::new(w * 0.6, h * 0.72, w * 0.36, h * 0.24), &graph, alpha);
+        }
+        if config.debug_overlay.is_enabled() && time >= o && time < ending_start {
+            let (w, h) = (overlay.width(), overlay.height());
+            let (cw, ch) = (w * config.chart_ratio, h * config.chart_ratio);
+            for ((x, y), text) in config.debug_overlay.labels(&chart, timeline.chart_time(time)) {
+                let (x, y) = ((w - cw) / 2. + cw * x, (h - ch) / 2. + ch * y);
+                // lines far off screen are still worth knowing about, so keep them at the edge
+                let (x, y) = (x.clamp(0., w), y.clamp(0., h));
+                overlay.panel(&text, x, y, h * 0.018, (x / w, y / h), 0.8);
+            }
+        }
+        if let Some((pattern, materials)) = &forensic {
+            // last, so that nothing drawn over it gets in the way of the pattern
+            overlay.cells(forensic::GRID, pattern, *materials, forensic_strength);
+        }
+        overlay.end();
+        true
+    };
+    if let Some(at) = still {
+        // `at` is a time in the finished video, which skips the loading when it's disabled
+        let at = if config.disable_loading { at + timeline.cut } else { at };
+        // step up to `at` so the loading scene gets to hand over to the game
+        let mut time = 0.;
+        loop {
+            let now = time.min(at);
+            *my_time.borrow_mut() = (now - timeline.shift).max(0.);
+            gl.quad_gl.render_pass(Some(mst.output().render_pass));
+            main.viewport = Some(camera(now));
+            main.update()?;
+            main.render(&mut painter)?;
+            if *my_time.borrow() <= LoadingScene::TOTAL_TIME as f64 && !config.disable_loading {
+                draw_rectangle(0., 0., 0., 0., Color::default());
+            }
+            gl.flush();
+            if MSAA.load(Ordering::SeqCst) {
+                mst.blit();
+            }
+            if now >= at {
+                break;
+            }
+            time += STILL_STEP;
         }
-        gl.flush();
-
-        if MSAA.load(Ordering::SeqCst) {
-            mst.blit();
+        if draw_overlay(at) {
+            gl.flush();
         }
+        let mut pixels = vec![0u8; vw as usize * vh as usize * 4];
         unsafe {
             use miniquad::gl::*;
-            //let tex = mst.output().texture.raw_miniquad_texture_handle();
             glBindFramebuffer(GL_READ_FRAMEBUFFER, internal_id(mst.output()));
-            glBindBuffer(GL_PIXEL_PACK_BUFFER, pbos[frame]);
             glReadPixels(
                 0,
                 0,
@@ -912,32 +2844,375 @@ pub async fn main(cmd: bool) -> Result<()> {
                 vh as _,
                 GL_RGBA,
                 GL_UNSIGNED_BYTE,
-                std::ptr::null_mut(),
+                pixels.as_mut_ptr() as _,
             );
         }
+        // GL rows start at the bottom
+        let row = vw as usize * 4;
+        let flipped: Vec<u8> = pixels.chunks_exact(row).rev().flatten().copied().collect();
+        image::save_buffer(&output_path, &flipped, vw, vh, image::ColorType::Rgba8)
+            .with_context(|| format!("failed to write {}", output_path.display()))?;
+        return Ok(());
+    }
+
+    let fps = config.fps;
+    let gl_info = GlInfo::current();
+    info!("OpenGL: {} ({})", gl_info.version, gl_info.renderer);
+    let n = ring_size(config.resolution, config.pbo_count);
+    info!("PBO ring size: {}", n);
+    let frames = (video_length * fps as f64 + n as f64 - 1.).ceil() as u64;
+
+
+    let test_encoder = |encoder: &str| test_encoder(&ffmpeg, encoder);
+    // a restarted render sticks to the encoder it was handed
+    let forced = resume.as_ref().map(|it| it.encoder.as_str());
+    let probe = |encoder: &str| match forced {
+        // the h264 flags stand for the whole backend, hevc included
+        Some(forced) => forced == encoder || (encoder.starts_with("h264") && forced.ends_with(&encoder[4..])),
+        None => config.hardware_accel && test_encoder(encoder),
+    };
+
+    let use_cuda = probe("h264_nvenc");
+    let has_qsv = probe("h264_qsv");
+    let has_amf = probe("h264_amf");
+
+    let hevc = config.hevc || forced.is_some();
+    let use_cuda_hevc = hevc && probe("hevc_nvenc");
+    let has_qsv_hevc = hevc && probe("hevc_qsv");
+    let has_amf_hevc = hevc && probe("hevc_amf");
+
+    let ffmpeg_preset = "-preset";
+    let mut ffmpeg_preset_name_list = config.ffmpeg_preset.split_whitespace();
+
+    let mut software = false;
+    if forced.is_none() && config.hardware_accel && !config.mpeg4 {
+        if (!(use_cuda_hevc || has_qsv_hevc || has_amf_hevc) && config.hevc) || !(use_cuda || has_qsv || has_amf) {
+            if nvenc_busy(&ffmpeg, if config.hevc { "hevc_nvenc" } else { "h264_nvenc" }) {
+                if config.nvenc_busy != NvencBusy::Software {
+                    bail!(failure!("encoder", "nvenc-busy"));
+                }
+            } else if !config.software_fallback {
+                bail!(failure!("encoder", "no-hwacc"));
+            }
+            software = true;
+            let message = tl!("software-fallback").to_string();
+            warn!("{message}");
+            if ipc {
+                send(IPCEvent::Warning {
+                    code: "software-fallback".to_owned(),
+                    message,
+                });
+            }
+        }
+    }
+    // whatever hardware the probes turned up, it isn't used after falling back
+    let (use_cuda, has_qsv, has_amf) = (use_cuda && !software, has_qsv && !software, has_amf && !software);
+    let (use_cuda_hevc, has_qsv_hevc, has_amf_hevc) =
+        (use_cuda_hevc && !software, has_qsv_hevc && !software, has_amf_hevc && !software);
+
+    let ffmpeg_encoder = if let Some(forced) = forced {
+        forced
+    } else if config.mpeg4 {
+        "mpeg4"
+    } else if use_cuda_hevc {
+        "hevc_nvenc"
+    } else if use_cuda {
+        "h264_nvenc"
+    } else if has_qsv_hevc {
+        "hevc_qsv"
+    } else if has_qsv {
+        "h264_qsv"
+    } else if has_amf_hevc {
+        "hevc_amf"
+    } else if has_amf {
+        "h264_amf"
+    } else {
+        if config.hevc {
+            "libx265"
+        } else {
+            "libx264"
+        }
+    };
+
+    info!("Encoder: {}", ffmpeg_encoder);
+
+    let ffmpeg_preset_name = if use_cuda {
+        ffmpeg_preset_name_list.nth(1).unwrap_or(
+            ffmpeg_preset_name_list.nth(0).unwrap_or("p4")
+        )
+    } else if has_qsv {
+        ffmpeg_preset_name_list.nth(2).unwrap_or("medium")
+    } else if has_amf {
+        ffmpeg_preset_name_list.nth(3).unwrap_or(
+            ffmpeg_preset_name_list.nth(0).unwrap_or("balanced")
+        )
+    } else {
+        ffmpeg_preset_name_list.nth(0).unwrap_or("medium")
+    };
+
+    let bitrate_control = 
+    if config.bitrate_control.to_lowercase() == "crf" {
+        if use_cuda && !config.mpeg4 {
+            "-cq"
+        } else if has_qsv || config.mpeg4 {
+            "-q"
+        }
+        else if has_amf {
+            "-qp_p"
+        } else {
+            "-crf"
+        }
+    } else {
+        "-b:v"
+    };
+
+    let bitrate = if config.bitrate.eq_ignore_ascii_case("auto") {
+        let constant_quality = bitrate_control != "-b:v";
+        let suggested = suggest_bitrate(config.resolution, config.fps, ffmpeg_encoder, constant_quality);
+        info!("Auto bitrate: {} {}", bitrate_control, suggested);
+        suggested
+    } else {
+        config.bitrate.clone()
+    };
+
+    let mut encoder_args = String::new();
+    if let Some(interval) = config.keyframe_interval {
+        let gop = (interval as f64 * fps as f64).round().max(1.) as u64;
+        write!(&mut encoder_args, " -g {gop}")?;
+        if config.disable_scene_cut {
+            write!(&mut encoder_args, " -keyint_min {gop}")?;
+        }
+    }
+    let nvenc = ffmpeg_encoder.ends_with("_nvenc");
+    if let (true, Ok(gpu)) = (nvenc, std::env::var(GPU_ENV)) {
+        write!(&mut encoder_args, " -gpu {gpu}")?;
+    }
+    if let Some(b_frames) = config.b_frames {
+        write!(&mut encoder_args, " -bf {b_frames}")?;
+    }
+    // x265 takes its own options as one list
+    let mut x265_params = Vec::new();
+    if let Some(depth) = config.rc_lookahead {
+        if ffmpeg_encoder.ends_with("_qsv") {
+            write!(&mut encoder_args, " -look_ahead 1 -look_ahead_depth {depth}")?;
+        } else if ffmpeg_encoder == "libx265" {
+            x265_params.push(format!("rc-lookahead={depth}"));
+        } else if ffmpeg_encoder == "libx264" || nvenc {
+            write!(&mut encoder_args, " -rc-lookahead {depth}")?;
+        }
+    }
+    if let Some(tune) = config.tune {
+        // of these, x265 only knows animation and grain
+        let supported = match ffmpeg_encoder {
+            "libx264" => true,
+            "libx265" => matches!(tune, EncoderTune::Animation | EncoderTune::Grain),
+            _ => false,
+        };
+        if supported {
+            write!(&mut encoder_args, " -tune {}", tune.name())?;
+        }
+    }
+    if let (Some(multipass), true) = (config.multipass, nvenc) {
+        let multipass = match multipass {
+            Multipass::Disabled => "disabled",
+            Multipass::Qres => "qres",
+            Multipass::Fullres => "fullres",
+        };
+        write!(&mut encoder_args, " -multipass {multipass}")?;
+    }
+    if let Some(threads) = config.encoder_threads.filter(|_| software_encoder(ffmpeg_encoder)) {
+        write!(&mut encoder_args, " -threads {threads}")?;
+        // x265 sizes its thread pool by itself and ignores -threads
+        x265_params.push(format!("pools={threads}"));
+    }
+    if config.disable_scene_cut {
+        encoder_args += match ffmpeg_encoder {
+            "libx264" => " -sc_threshold 0",
+            "h264_nvenc" | "hevc_nvenc" => " -no-scenecut 1",
+            _ => "",
+        };
+        x265_params.push("scenecut=0".to_owned());
+    }
+    if ffmpeg_encoder == "libx265" && !x265_params.is_empty() {
+        write!(&mut encoder_args, " -x265-params {}", x265_params.join(":"))?;
+    }
+
+    // profile for 4:4:4, where the encoder can do it
+    let chroma_444 = match ffmpeg_encoder {
+        "libx264" => Some("high444"),
+        "libx265" => Some("main444-8"),
+        "h264_nvenc" => Some("high444p"),
+        "hevc_nvenc" => Some("rext"),
+        _ => None,
+    };
+    let pix_fmt = match (config.chroma, chroma_444) {
+        (Chroma::Yuv444, Some(profile)) => {
+            write!(&mut encoder_args, " -profile:v {profile}")?;
+            "yuv444p"
+        }
+        (Chroma::Yuv444, None) => {
+            warn!("{} can't encode 4:4:4, falling back to 4:2:0", ffmpeg_encoder);
+            "yuv420p"
+        }
+        (Chroma::Yuv420, _) => "yuv420p",
+    };
+
+    let rotation = config.display_rotation.rem_euclid(360);
+    // ffmpeg 6.1 dropped the `rotate` tag in favour of an input option that sets
+    // the display matrix; autorotate would apply that to the frames, so turn it off
+    let display_matrix = rotation != 0 && ffmpeg_has_option(&ffmpeg, "display_rotation");
+
+    let mut args = "-probesize 50M -y -f rawvideo -c:v rawvideo".to_owned();
+    if use_cuda {
+        args += " -hwaccel_output_format cuda";
+    }
+    if display_matrix {
+        // counter-clockwise
+        write!(&mut args, " -display_rotation {} -noautorotate", (360 - rotation) % 360)?;
+    }
+    write!(
+        &mut args,
+        " -s {vw}x{vh} -r {fps} -pix_fmt rgba -thread_queue_size 1024 -i -"
+    )?;
+    if rotation != 0 && !display_matrix {
+        write!(&mut encoder_args, " -metadata:s:v:0 rotate={rotation}")?;
+    }
+
+    let subtitle_file = match &subtitles {
+        Some(_) if config.lyrics_mode != LyricsMode::Burn => {
+            let mut file = tempfile::Builder::new().suffix(".srt").tempfile()?;
+            // the track is muxed as it is, so it has to be timed to the trimmed video
+            let cut = if config.disable_loading { timeline.cut } else { 0. };
+            file.write_all(lyrics::to_srt(&lyrics, |it| timeline.music_video_time(it) - cut).as_bytes())?;
+            Some(file)
+        }
+        _ => None,
+    };
+
+    let audio_codec = audio_codec(&config);
+    info!(
+        "Preparing Render Time:{:.2?}",
+        preparing_render_time.elapsed()
+    );
+    let pre_render_time = Instant::now();
+
+    // the video is encoded alone, into one file or a run of segments, and muxed
+    // with the audio in a second pass that doesn't touch the frames
+    let segments = (!config.encoder_fallback.is_empty()).then(|| output_path.with_extension("parts"));
+    let segment_length = config.segment_length.max(1);
+    let segment_frames = segment_length as u64 * fps as u64;
+    let start = resume.as_ref().map_or(0, |it| it.frame);
+    let start_segment = resume.as_ref().map_or(0, |it| it.segment);
+    let cut_frames = if config.disable_loading { (timeline.cut * fps as f64).round() as u64 } else { 0 };
+
+    let video_path = output_path.with_extension("video.mkv");
+    let trim = cut_frames.saturating_sub(start);
+
+    let mut command = cmd_hidden(&ffmpeg);
+    // values from the config go in as single arguments, so that none can add options of its own
+    command
+        .args(args.split_whitespace())
+        .args(["-c:v", ffmpeg_encoder, "-pix_fmt", pix_fmt, bitrate_control, bitrate.as_str(), ffmpeg_preset, ffmpeg_preset_name])
+        .args(encoder_args.split_whitespace())
+        .args(format!("-vf vflip,trim=start_frame={trim},setpts=PTS-STARTPTS -map 0:v:0").split_whitespace());
+    if let Some(dir) = &segments {
+        std::fs::create_dir_all(dir)?;
+        command
+            .args(
+                format!(
+                    "-force_key_frames expr:gte(n,n_forced*{segment_frames}) -f segment -segment_time {segment_length} \
+                     -segment_format matroska -segment_start_number {start_segment} -reset_timestamps 1"
+                )
+                .split_whitespace(),
+            )
+            .arg(dir.join("part_%05d.mkv"));
+    } else {
+        command.args(["-f", "matroska"]).arg(&video_path);
+    }
+    let mut proc = command
+        .arg("-loglevel")
+        .arg("warning")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| failure!("ffmpeg", "run-ffmpeg-failed"))?;
+    let queue = queue_size(config.resolution, config.frame_queue);
+    info!("Frame queue size: {}", queue);
+    let mut input = FrameQueue::new(proc.stdin.take().unwrap(), queue);
+    let log = FfmpegLog::capture(&mut proc);
+
+    let mut readback = Readback::new(n, config.resolution, &gl_info);
+    info!(
+        "Readback: {}",
+        if readback.is_persistent() { "persistent mapping" } else { "map per frame" }
+    );
+
+    if ipc {
+        send(IPCEvent::StartRender(frames - start));
+    }
+
+    let fps = fps as f64;
+    if start > 0 {
+        // step the scene up to where the last attempt left off, encoding nothing
+        let at = start as f64 / fps;
+        let mut time = 0.;
+        while time < at {
+            *my_time.borrow_mut() = (time - timeline.shift).max(0.);
+            gl.quad_gl.render_pass(Some(mst.output().render_pass));
+            main.viewport = Some(camera(time));
+            main.update()?;
+            main.render(&mut painter)?;
+            if *my_time.borrow() <= LoadingScene::TOTAL_TIME as f64 && !config.disable_loading {
+                draw_rectangle(0., 0., 0., 0., Color::default());
+            }
+            gl.flush();
+            time += STILL_STEP;
+        }
+    }
+    let start_frame = start as usize;
+    let mut frame_times = FrameTimes::default();
+    for frame in start_frame..start_frame + n {
+        let frame_start = Instant::now();
+        *my_time.borrow_mut() = (frame as f64 / fps - timeline.shift).max(0.);
+        gl.quad_gl.render_pass(Some(mst.output().render_pass));
+        main.viewport = Some(camera(frame as f64 / fps));
+        main.update()?;
+        main.render(&mut painter)?;
+        if *my_time.borrow() <= LoadingScene::TOTAL_TIME as f64 && !config.disable_loading {
+            draw_rectangle(0., 0., 0., 0., Color::default());
+        }
+        gl.flush();
+
+        if MSAA.load(Ordering::SeqCst) {
+            mst.blit();
+        }
+        if draw_overlay(frame as f64 / fps) {
+            gl.flush();
+        }
+        let simulated = Instant::now();
+        readback.read(frame, internal_id(mst.output()));
+        let time = FrameTime {
+            simulate: simulated - frame_start,
+            readback: simulated.elapsed(),
+            write: std::time::Duration::ZERO,
+        };
+        if let Some(average) = frame_times.push(frame as u64, time) {
+            log_spike(frame as u64, timeline.chart_time(frame as f64 / fps), &time, average);
+        }
         if ipc {
-            send(IPCEvent::Frame);
+            reporter.frame();
         }
     }
     info!("Pre-Render Time:{:.2?}", pre_render_time.elapsed());
 
-    let frames10 = frames / 10;
     let render_time = Instant::now();
-    let mut step_time = Instant::now();
-    for frame in N as u64..frames {
-        if frame % frames10 == 0 {
-            let proc = (frame as f32 / frames as f32 * 100.).ceil() as i8 / 10 * 10;
-            info!(
-                "Render progress: {:.0}% Time elapsed: {:.2}s",
-                proc,
-                step_time.elapsed().as_secs_f32()
-            );
-            step_time = Instant::now();
-        }
-        *my_time.borrow_mut() = (frame as f64 / fps).max(0.);
+    let mut died = false;
+    for frame in start + n as u64..frames {
+        let frame_start = Instant::now();
+        *my_time.borrow_mut() = (frame as f64 / fps - timeline.shift).max(0.);
         gl.quad_gl.render_pass(Some(mst.output().render_pass));
         //clear_background(BLACK);
-        main.viewport = Some((0, 0, vw as _, vh as _));
+        main.viewport = Some(camera(frame as f64 / fps));
         main.update()?;
         main.render(&mut painter)?;
         // TODO magic. can't remove this line.
@@ -950,43 +3225,420 @@ pub async fn main(cmd: bool) -> Result<()> {
         if MSAA.load(Ordering::SeqCst) {
             mst.blit();
         }
-        unsafe {
-            use miniquad::gl::*;
-            //let tex = mst.output().texture.raw_miniquad_texture_handle();
-            glBindFramebuffer(GL_READ_FRAMEBUFFER, internal_id(mst.output()));
-
-            glBindBuffer(GL_PIXEL_PACK_BUFFER, pbos[frame as usize % N]);
-            glReadPixels(
-                0,
-                0,
-                vw as _,
-                vh as _,
-                GL_RGBA,
-                GL_UNSIGNED_BYTE,
-                std::ptr::null_mut(),
-            );
-
-            glBindBuffer(GL_PIXEL_PACK_BUFFER, pbos[(frame + 1) as usize % N]);
-            let src = glMapBuffer(GL_PIXEL_PACK_BUFFER, 0x88B8);
-            if !src.is_null() {
-                input.write_all(&std::slice::from_raw_parts(src as *const u8, byte_size))?;
-                glUnmapBuffer(GL_PIXEL_PACK_BUFFER);
+        if draw_overlay(frame as f64 / fps) {
+            gl.flush();
+        }
+        let simulated = Instant::now();
+        readback.read(frame as usize, internal_id(mst.output()));
+        let read = Instant::now();
+        let drained = readback.drain(frame as usize + 1, &mut input);
+        let time = FrameTime {
+            simulate: simulated - frame_start,
+            readback: read - simulated,
+            write: read.elapsed(),
+        };
+        if let Some(average) = frame_times.push(frame, time) {
+            log_spike(frame, timeline.chart_time(frame as f64 / fps), &time, average);
+        }
+        if let Err(err) = drained {
+            if segments.is_none() {
+                drop(input);
+                proc.wait()?;
+                let _ = std::fs::remove_file(&video_path);
+                return Err(log.failure(err));
+            }
+            warn!("Encoder stopped taking frames: {err:?}");
+            died = true;
+            break;
+        }
+        if let Some(share) = input.backpressure() {
+            let message = tl!("encoder-backpressure", "share" => format!("{:.0}", share * 100.)).to_string();
+            warn!("{message}");
+            if ipc {
+                send(IPCEvent::Warning {
+                    code: "encoder-backpressure".to_owned(),
+                    message,
+                });
             }
         }
         if ipc {
-            send(IPCEvent::Frame);
+            reporter.frame();
         }
     }
-    drop(input);
+    if ipc {
+        reporter.flush();
+    }
+    // the queue may still hold frames ffmpeg hasn't taken yet
+    let finished = input.finish();
     info!("Render Time: {:.2?}", render_time.elapsed());
     info!(
         "Average FPS: {:.2}",
         frames as f64 / render_time.elapsed().as_secs_f64()
     );
-    proc.wait()?;
+    let frame_stats = frame_times.stats();
+    if let Some(stats) = &frame_stats {
+        info!(
+            "Frame time: mean {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, worst {:.2}ms (frame {}); simulate {:.2}ms, readback {:.2}ms, write {:.2}ms; {} spikes",
+            stats.mean,
+            stats.p95,
+            stats.p99,
+            stats.worst,
+            stats.worst_frame,
+            stats.simulate,
+            stats.readback,
+            stats.write,
+            stats.spikes
+        );
+    }
+    let status = proc.wait()?;
+    if segments.is_none() {
+        // a partial video is of no use without segments to resume from
+        if !status.success() {
+            let _ = std::fs::remove_file(&video_path);
+            return Err(log.failure(status));
+        }
+        if let Err(err) = finished {
+            let _ = std::fs::remove_file(&video_path);
+            return Err(log.failure(err));
+        }
+    }
+    if let Some(dir) = &segments {
+        if died || !status.success() || finished.is_err() {
+            return encoder_died(ipc, dir, start_segment, cut_frames, segment_frames, ffmpeg_encoder);
+        }
+    }
+
+    let mux_time = Instant::now();
+    let mut command = cmd_hidden(&ffmpeg);
+    command.arg("-y");
+    match &segments {
+        Some(dir) => command.args(["-f", "concat", "-safe", "0", "-i"]).arg(segment_list(dir)?),
+        None => command.arg("-i").arg(&video_path),
+    };
+    if config.disable_loading {
+        command.arg("-ss").arg(timeline.cut.to_string());
+    }
+    command.arg("-i").arg(mixing_output.path());
+    if let Some(file) = &subtitle_file {
+        command.arg("-i").arg(file.path());
+    }
+    command.arg("-c:a").args(&audio_codec);
+    command.args(["-c:v", "copy", "-map", "0:v:0", "-map", "1:a:0"]);
+    if subtitle_file.is_some() {
+        command.args(["-map", "2:s:0", "-c:s", "mov_text"]);
+    }
+    if rotation != 0 && !display_matrix {
+        command.arg("-metadata:s:v:0").arg(format!("rotate={rotation}"));
+    }
+    let status = command
+        .args(["-f", if config.hires { "mov" } else { "mp4" }])
+        .arg(&output_path)
+        .args(["-loglevel", "warning"])
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| failure!("ffmpeg", "run-ffmpeg-failed"))?;
+    if !status.success() {
+        match segments {
+            Some(_) => bail!(failure!("ffmpeg", "join-segments-failed")),
+            None => {
+                // nothing can be done with the video alone
+                let _ = std::fs::remove_file(&video_path);
+                bail!(failure!("ffmpeg", "mux-failed"))
+            }
+        }
+    }
+    info!("Mux Time: {:.2?}", mux_time.elapsed());
+    if config.keep_stems {
+        // the video (or its segments) and the mix, to mux with another mix later
+        let audio_path = output_path.with_extension("audio.wav");
+        std::fs::copy(mixing_output.path(), &audio_path)
+            .with_context(|| format!("failed to write {}", audio_path.display()))?;
+    } else {
+        match &segments {
+            Some(dir) => std::fs::remove_dir_all(dir)?,
+            None => std::fs::remove_file(&video_path)?,
+        }
+    }
     info!("Task done in {:.2?}", render_start_time.elapsed());
+    if config.manifest {
+        let timings = Timings {
+            loading: render_start_time.duration_since(loading_time).as_secs_f64(),
+            mixing: preparing_render_time.duration_since(render_start_time).as_secs_f64(),
+            rendering: preparing_render_time.elapsed().as_secs_f64(),
+            total: loading_time.elapsed().as_secs_f64(),
+            frames: frame_stats.clone(),
+        };
+        let manifest = Manifest::new(job, &ffmpeg, ffmpeg_encoder, frames, timings, loudness)?;
+        let path = output_path.with_extension("manifest.json");
+        std::fs::write(&path, serde_json::to_string_pretty(&manifest)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    if ipc {
+        send(IPCEvent::Done {
+            duration: render_start_time.elapsed().as_secs_f64(),
+            frame_stats,
+        });
+    }
+    Ok(())
+}
+
+/// Logs a frame that took far longer than those before it, with where it is in
+/// the chart so that a stutter in the video can be traced to what is on screen.
+fn log_spike(frame: u64, chart_time: f32, time: &FrameTime, average: f64) {
+    let ms = |it: std::time::Duration| it.as_secs_f64() * 1000.;
+    warn!(
+        "Frame {frame} (chart time {chart_time:.3}s) took {:.1}ms against {average:.1}ms on average: simulate {:.1}ms, readback {:.1}ms, write {:.1}ms",
+        ms(time.total()),
+        ms(time.simulate),
+        ms(time.readback),
+        ms(time.write)
+    );
+}
+
+/// ffmpeg's `-c:a` value (and options) for the mix in the final video.
+/// Arguments that follow `-c:a`.
+fn audio_codec(config: &RenderConfig) -> Vec<&str> {
+    if config.hires {
+        vec!["copy"]
+    } else {
+        vec!["aac", "-b:a", config.audio_bitrate.as_deref().unwrap_or("320k")]
+    }
+}
+
+fn segment_name(number: u64) -> String {
+    format!("part_{number:05}.mkv")
+}
+
+/// Numbers of the segments in `dir`, in order.
+fn segment_numbers(dir: &Path) -> Result<Vec<u64>> {
+    let mut numbers: Vec<u64> = std::fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix("part_")?.strip_suffix(".mkv")?.parse().ok()
+        })
+        .collect();
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+/// Writes the concat demuxer's list of the segments in `dir`.
+fn segment_list(dir: &Path) -> Result<PathBuf> {
+    let mut list = String::new();
+    for number in segment_numbers(dir)? {
+        let path = dir.join(segment_name(number)).display().to_string();
+        writeln!(&mut list, "file '{}'", path.replace('\'', "'\\''"))?;
+    }
+    let path = dir.join("list.txt");
+    std::fs::write(&path, list)?;
+    Ok(path)
+}
+
+/// Drops the segment `encoder` died in and tells the queue where to restart.
+fn encoder_died(ipc: bool, dir: &Path, start_segment: u64, cut_frames: u64, segment_frames: u64, encoder: &str) -> Result<()> {
+    let numbers = segment_numbers(dir)?;
+    // every segment but the last was closed before the next one was opened
+    let segment = numbers.last().copied().unwrap_or(start_segment);
+    for number in numbers.into_iter().filter(|&it| it >= segment) {
+        std::fs::remove_file(dir.join(segment_name(number)))?;
+    }
     if ipc {
-        send(IPCEvent::Done(render_start_time.elapsed().as_secs_f64()));
+        crate::ipc::client::send(IPCEvent::EncoderDied {
+            frame: cut_frames + segment * segment_frames,
+            segment,
+            encoder: encoder.to_owned(),
+        });
+    }
+    bail!(failure!("encoder", "encoder-died", "encoder" => encoder.to_owned()));
+}
+
+const MIX_PREVIEW_LENGTH: f64 = 20.;
+/// How far the length of a video may be off from the mix laid onto it.
+const REMUX_TOLERANCE: f64 = 0.5;
+
+/// Lays `skin_overrides` over the resource pack, or the default one, and points
+/// `res_pack_path` at the result. The returned file backs that pack and must be
+/// kept alive for as long as the pack may be read.
+async fn apply_skin_overrides(ffmpeg: &str, config: &mut RenderConfig) -> Result<Option<NamedTempFile>> {
+    if config.skin_overrides.is_empty() {
+        return Ok(None);
+    }
+    let default = tempfile::tempdir()?;
+    let base = match &config.res_pack_path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            for name in respack::image_names().chain(["info.yml".to_owned()]) {
+                if let Ok(bytes) = load_file(&format!("respack/{name}")).await {
+                    std::fs::write(default.path().join(name), bytes)?;
+                }
+            }
+            default.path().to_owned()
+        }
+    };
+    let skin = tempfile::Builder::new().suffix(".zip").tempfile()?;
+    respack::overlay(ffmpeg, &base, &config.skin_overrides, skin.path())
+        .with_context(|| failure!("loading", "skin-override-failed"))?;
+    info!("Skin overrides: {:?}", config.skin_overrides.keys().collect::<Vec<_>>());
+    config.res_pack_path = Some(skin.path().display().to_string());
+    Ok(Some(skin))
+}
+
+/// Loads what the audio mix is made from: the chart, with mods applied, and its music.
+async fn load_mix_sources(path: &Path, info: &ChartInfo, config: &RenderConfig) -> Result<(Chart, AudioClip)> {
+    let mut fs = mods::wrap(archive::open(path)?, &info.chart, config.chart_mods(), None);
+    let (chart, ..) = GameScene::load_chart(fs.deref_mut(), info)
+        .await
+        .with_context(|| failure!("loading", "load-chart-failed"))?;
+    let music: Result<_> = async { AudioClip::new(fs.load_file(&info.music).await?) }.await;
+    let music = music.with_context(|| failure!("loading", "load-music-failed"))?;
+    Ok((chart, music))
+}
+
+/// Length of the media file at `path` in seconds, from the `Duration:` line ffmpeg
+/// prints for its inputs.
+pub fn media_duration(ffmpeg: &str, path: &Path) -> Option<f64> {
+    let output = cmd_hidden(ffmpeg).arg("-hide_banner").arg("-i").arg(path).output().ok()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let duration = stderr.lines().find_map(|it| it.trim().strip_prefix("Duration: "))?;
+    let mut seconds = 0.;
+    for part in duration.split(',').next()?.split(':') {
+        seconds = seconds * 60. + part.trim().parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Mixes the audio of a job again and lays it onto the video it was rendered to,
+/// copying the video and subtitle streams as they are.
+///
+/// `remux <assets> <video>`, with the job (usually rebuilt from the video's
+/// manifest) and the output path read as in [`read_job`].
+pub async fn remux_main() -> Result<()> {
+    set_pc_assets_folder(&std::env::args().nth(2).unwrap());
+    let video: PathBuf = std::env::args().nth(3).context("missing video")?.into();
+
+    let (params, output_path) = read_job(4)?;
+    let mut config = params.config;
+    // as a render expands them
+    config.apply_quality();
+    config.apply_draft();
+    let info = params.info;
+    let Some(ffmpeg) = find_ffmpeg()? else {
+        bail!(failure!("ffmpeg", "ffmpeg-not-found"))
+    };
+    // the hitsounds are read from the pack
+    let _skin = apply_skin_overrides(&ffmpeg, &mut config).await?;
+    let (mut chart, music) = load_mix_sources(&params.path, &info, &config).await?;
+    let timeline = Timeline::new(&config, &chart, &info, &music);
+
+    let cut = if config.disable_loading { timeline.cut } else { 0. };
+    let expected = timeline.video_length - cut;
+    let actual = media_duration(&ffmpeg, &video).with_context(|| failure!("remux", "remux-unreadable"))?;
+    if (actual - expected).abs() > REMUX_TOLERANCE {
+        bail!(failure!("remux", "remux-length-mismatch", "video" => format!("{actual:.2}"), "mix" => format!("{expected:.2}")));
+    }
+
+    let output = mix_audio(&config, &mut chart, &music, &timeline).await?;
+    let mixing_output = NamedTempFile::new()?;
+    write_audio(&ffmpeg, output, config.audio_bit_depth, mixing_output.path())?;
+
+    let mut command = cmd_hidden(&ffmpeg);
+    command.arg("-y").arg("-i").arg(&video);
+    if config.disable_loading {
+        command.arg("-ss").arg(cut.to_string());
     }
+    command.arg("-i").arg(mixing_output.path());
+    command.arg("-c:a").args(audio_codec(&config));
+    let status = command
+        .args(["-c:v", "copy", "-c:s", "copy", "-map", "0:v:0", "-map", "1:a:0", "-map", "0:s?"])
+        .args(["-f", config.container()])
+        .arg(&output_path)
+        .args(["-loglevel", "warning"])
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| failure!("ffmpeg", "run-ffmpeg-failed"))?;
+    if !status.success() {
+        bail!(failure!("ffmpeg", "mux-failed"));
+    }
+    info!("Remuxed {} into {}", video.display(), output_path.display());
+    Ok(())
+}
+
+/// Where a job's chart starts and its results screen appears, in seconds of the
+/// finished video.
+pub struct CutPoints {
+    /// `None` for a chart without real notes
+    pub first_note: Option<f64>,
+    pub ending: f64,
+    pub fps: u32,
+}
+
+/// Works out the [`CutPoints`] of a job; needs the assets folder to be set up.
+pub async fn cut_points(params: &RenderParams) -> Result<CutPoints> {
+    let config = &params.config;
+    let (chart, music) = load_mix_sources(&params.path, &params.info, config).await?;
+    let timeline = Timeline::new(config, &chart, &params.info, &music);
+    let first_note = chart
+        .lines
+        .iter()
+        .flat_map(|line| line.notes.iter())
+        .filter(|note| !note.fake)
+        .map(|note| timeline.video_time(note.time) - timeline.cut)
+        .min_by(|x, y| x.total_cmp(y));
+    Ok(CutPoints {
+        first_note,
+        ending: timeline.ending_start() - timeline.cut,
+        fps: config.fps,
+    })
+}
+
+/// Mixes the audio only and writes the densest [`MIX_PREVIEW_LENGTH`] seconds to a
+/// WAV file, so hitsound balance and offset can be checked without a render.
+pub async fn mix_main() -> Result<()> {
+    set_pc_assets_folder(&std::env::args().nth(2).unwrap());
+
+    let (params, output_path) = read_job(3)?;
+    let mut config = params.config;
+    // as a render expands them
+    config.apply_quality();
+    config.apply_draft();
+    let info = params.info;
+    let Some(ffmpeg) = find_ffmpeg()? else {
+        bail!(failure!("ffmpeg", "ffmpeg-not-found"))
+    };
+    // the hitsounds are read from the pack
+    let _skin = apply_skin_overrides(&ffmpeg, &mut config).await?;
+    let (mut chart, music) = load_mix_sources(&params.path, &info, &config).await?;
+
+    let timeline = Timeline::new(&config, &chart, &info, &music);
+    let mut times: Vec<f64> = chart
+        .lines
+        .iter()
+        .flat_map(|line| line.notes.iter())
+        .filter(|note| !note.fake)
+        .map(|note| timeline.video_time(note.time))
+        .collect();
+    times.sort_by(|x, y| x.total_cmp(y));
+
+    let mut start = 0.;
+    let mut best = 0;
+    let mut j = 0;
+    for i in 0..times.len() {
+        while j < times.len() && times[j] < times[i] + MIX_PREVIEW_LENGTH {
+            j += 1;
+        }
+        if j - i > best {
+            best = j - i;
+            start = times[i];
+        }
+    }
+    let start = start
+        .min(timeline.video_length - MIX_PREVIEW_LENGTH)
+        .max(0.);
+    info!("mix preview: {:.2}s ~ {:.2}s ({} notes)", start, start + MIX_PREVIEW_LENGTH, best);
+
+    let output = mix_audio(&config, &mut chart, &music, &timeline).await?;
+    let from = ((start * SAMPLE_RATE as f64) as usize * 2).min(output.len());
+    let to = (((start + MIX_PREVIEW_LENGTH) * SAMPLE_RATE as f64) as usize * 2).min(output.len());
+    write_audio(&ffmpeg, output[from..to].to_vec(), config.audio_bit_depth, &output_path)?;
+
     Ok(())
 }
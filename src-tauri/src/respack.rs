@@ -0,0 +1,476 @@
+use crate::{
+    checksum,
+    render::{cmd_hidden, SAMPLE_RATE},
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Hitsounds the mixer takes from a resource pack, by file stem.
+pub const HITSOUNDS: &[&str] = &["click", "drag", "flick"];
+const AUDIO_EXTENSIONS: &[&str] = &["ogg", "mp3", "wav", "flac", "m4a", "opus"];
+
+/// Every file of a resource pack, either a folder or a zip, with paths relative
+/// to the pack root.
+fn read_all(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        let mut stack = vec![path.to_owned()];
+        while let Some(dir) = stack.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?.path();
+                if entry.is_dir() {
+                    stack.push(entry);
+                    continue;
+                }
+                let name = entry.strip_prefix(path)?.to_string_lossy().replace('\\', "/");
+                files.push((name, std::fs::read(&entry)?));
+            }
+        }
+        return Ok(files);
+    }
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let prefix = root(&zip).unwrap_or_default();
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let Some(name) = file.name().strip_prefix(&prefix).filter(|it| !it.is_empty() && !it.ends_with('/')) else {
+            continue;
+        };
+        let name = name.to_owned();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        files.push((name, bytes));
+    }
+    Ok(files)
+}
+
+/// Folder the pack is zipped in, if any; packs are sometimes zipped with it.
+fn root<R: Read + std::io::Seek>(zip: &zip::ZipArchive<R>) -> Option<String> {
+    let info = zip
+        .file_names()
+        .filter(|it| it.rsplit('/').next() == Some("info.yml"))
+        .min_by_key(|it| it.len())?;
+    Some(info[..info.len() - "info.yml".len()].to_owned())
+}
+
+/// Reads `name` (relative to the pack root) from a resource pack.
+pub fn read_file(path: &Path, name: &str) -> Option<Vec<u8>> {
+    if path.is_dir() {
+        return std::fs::read(path.join(name)).ok();
+    }
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path).ok()?).ok()?;
+    let name = format!("{}{name}", root(&zip).unwrap_or_default());
+    let mut bytes = Vec::new();
+    zip.by_name(&name).ok()?.read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Whether ffmpeg describes `path` as Vorbis at [`SAMPLE_RATE`] in stereo, which
+/// the mixer takes as is.
+fn is_native(ffmpeg: &str, path: &Path) -> bool {
+    let Ok(output) = cmd_hidden(ffmpeg).arg("-hide_banner").arg("-i").arg(path).output() else {
+        return false;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr.lines().find_map(|it| it.split("Audio: ").nth(1)).is_some_and(|audio| {
+        let parts: Vec<&str> = audio.split(", ").map(str::trim).collect();
+        parts.first().is_some_and(|it| it.starts_with("vorbis"))
+            && parts.contains(&format!("{SAMPLE_RATE} Hz").as_str())
+            && parts.iter().any(|it| it.starts_with("stereo"))
+    })
+}
+
+/// Converts one hitsound to Vorbis at [`SAMPLE_RATE`] in stereo, unless it is already.
+fn convert(ffmpeg: &str, name: &str, bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    let extension = name.rsplit('.').next().unwrap_or_default();
+    let mut input = tempfile::Builder::new().suffix(&format!(".{extension}")).tempfile()?;
+    input.write_all(bytes)?;
+    if extension == "ogg" && is_native(ffmpeg, input.path()) {
+        return Ok(None);
+    }
+    let output = tempfile::Builder::new().suffix(".ogg").tempfile()?;
+    let status = cmd_hidden(ffmpeg)
+        .arg("-y")
+        .arg("-i")
+        .arg(input.path())
+        .args(["-ar", &SAMPLE_RATE.to_string(), "-ac", "2", "-c:a", "libvorbis", "-q:a", "8"])
+        .arg(output.path())
+        .args(["-loglevel", "error"])
+        .status()
+        .context("failed to run ffmpeg")?;
+    if !status.success() {
+        bail!("failed to convert {name}");
+    }
+    Ok(Some(std::fs::read(output.path())?))
+}
+
+/// Copies the pack at `path` into `dir` as a zip, with its hitsounds converted to
+/// what the mixer uses so that renders don't have to, and returns where it went.
+/// An installed pack of the same name is only replaced with `overwrite`.
+pub fn install(ffmpeg: &str, path: &Path, dir: &Path, overwrite: bool) -> Result<PathBuf> {
+    let files = read_all(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if !files.iter().any(|(name, _)| name == "info.yml") {
+        bail!("{} is not a resource pack (no info.yml)", path.display());
+    }
+    let stem = path.file_stem().context("invalid resource pack path")?.to_string_lossy();
+    let target = dir.join(format!("{stem}.zip"));
+    if target.exists() && !overwrite {
+        bail!("a resource pack named {stem} is already installed");
+    }
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default();
+    // a pack with both `click.wav` and `click.ogg` would get two `click.ogg`
+    let mut written = HashSet::new();
+    for (name, bytes) in files {
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((name.as_str(), ""));
+        let hitsound = HITSOUNDS.contains(&stem) && AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str());
+        let (name, bytes) = match hitsound.then(|| convert(ffmpeg, &name, &bytes)).transpose()?.flatten() {
+            Some(converted) => {
+                info!("Converted {} to {} Hz stereo Vorbis", name, SAMPLE_RATE);
+                (format!("{stem}.ogg"), converted)
+            }
+            None => (name, bytes),
+        };
+        if !written.insert(name.clone()) {
+            warn!("Skipping a second {name} in the resource pack");
+            continue;
+        }
+        zip.start_file(name, options)?;
+        zip.write_all(&bytes)?;
+    }
+    std::fs::write(&target, zip.finish()?.into_inner())
+        .with_context(|| format!("failed to write {}", target.display()))?;
+    checksum::record(&target)?;
+    Ok(target)
+}
+
+/// Images a resource pack has to have, by file stem.
+const REQUIRED_IMAGES: &[&str] = &["click", "drag", "flick", "hold", "hit_fx"];
+/// Images a resource pack may have: those drawn for simultaneous notes, and a
+/// judge line texture.
+const OPTIONAL_IMAGES: &[&str] = &["click_mh", "drag_mh", "flick_mh", "hold_mh", "line"];
+
+/// File names of every image a pack can have.
+pub fn image_names() -> impl Iterator<Item = String> {
+    REQUIRED_IMAGES.iter().chain(OPTIONAL_IMAGES).map(|it| format!("{it}.png"))
+}
+
+/// A resource pack to assemble, from loose files laid over an existing pack.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RespackSpec {
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    /// Pack whose files (and `info.yml` settings) are kept unless replaced
+    #[serde(default)]
+    pub base: Option<PathBuf>,
+    /// Source file per pack file name, e.g. `click.png`, `click.ogg` or `hit_fx.png`
+    #[serde(default)]
+    pub files: HashMap<String, PathBuf>,
+    /// Columns and rows of frames in `hit_fx.png`
+    #[serde(default)]
+    pub hit_fx: Option<[u32; 2]>,
+    /// Pixels of `hold.png` taken by the tail and the head
+    #[serde(default)]
+    pub hold_atlas: Option<[u32; 2]>,
+    #[serde(default)]
+    pub hold_atlas_mh: Option<[u32; 2]>,
+    /// Any other `info.yml` settings, as they are written there
+    #[serde(default)]
+    pub extra: serde_yaml::Mapping,
+}
+
+/// What a pack holds, for an editor to start from.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RespackContents {
+    pub info: serde_yaml::Value,
+    pub files: Vec<String>,
+    /// Size of every PNG, by file name
+    pub image_sizes: HashMap<String, (u32, u32)>,
+}
+
+fn image_size(bytes: &[u8]) -> Result<(u32, u32)> {
+    Ok(image::io::Reader::new(Cursor::new(bytes)).with_guessed_format()?.into_dimensions()?)
+}
+
+/// Lists the contents of the pack at `path`.
+pub fn inspect(path: &Path) -> Result<RespackContents> {
+    let files = read_all(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let info = files
+        .iter()
+        .find(|(name, _)| name == "info.yml")
+        .map(|(_, bytes)| serde_yaml::from_slice(bytes))
+        .transpose()
+        .context("invalid info.yml")?
+        .unwrap_or(serde_yaml::Value::Null);
+    let image_sizes = files
+        .iter()
+        .filter(|(name, _)| name.ends_with(".png"))
+        .filter_map(|(name, bytes)| Some((name.clone(), image_size(bytes).ok()?)))
+        .collect();
+    Ok(RespackContents {
+        info,
+        files: files.into_iter().map(|(name, _)| name).collect(),
+        image_sizes,
+    })
+}
+
+/// Everything wrong with a pack's images, given the frame grid and hold atlas its
+/// `info.yml` declares.
+fn problems(files: &HashMap<String, Vec<u8>>, info: &serde_yaml::Mapping) -> Vec<String> {
+    let mut problems = Vec::new();
+    let pair = |key: &str| -> Option<(u32, u32)> {
+        let seq = info.get(key)?.as_sequence()?;
+        Some((seq.first()?.as_u64()? as u32, seq.get(1)?.as_u64()? as u32))
+    };
+    let mut sizes = HashMap::new();
+    for &stem in REQUIRED_IMAGES.iter().chain(OPTIONAL_IMAGES) {
+        let name = format!("{stem}.png");
+        match files.get(&name).map(|it| image_size(it)) {
+            Some(Ok(size)) => {
+                sizes.insert(stem, size);
+            }
+            Some(Err(err)) => problems.push(format!("{name} is not a readable PNG: {err}")),
+            None if REQUIRED_IMAGES.contains(&stem) => problems.push(format!("{name} is missing")),
+            None => {}
+        }
+    }
+    if let (Some(&(w, h)), Some((cols, rows))) = (sizes.get("hit_fx"), pair("hitFx")) {
+        if cols == 0 || rows == 0 || w % cols != 0 || h % rows != 0 {
+            problems.push(format!("hit_fx.png is {w}x{h}, which doesn't split into {cols}x{rows} frames"));
+        }
+    } else if sizes.contains_key("hit_fx") {
+        problems.push("hitFx (columns and rows of hit_fx.png) is not set".to_owned());
+    }
+    for (stem, key) in [("hold", "holdAtlas"), ("hold_mh", "holdAtlasMH")] {
+        let Some(&(_, h)) = sizes.get(stem) else { continue };
+        match pair(key) {
+            Some((tail, head)) if tail + head >= h => {
+                problems.push(format!("{stem}.png is {h}px tall, too short for a {tail}px tail and {head}px head"))
+            }
+            Some(_) => {}
+            None => problems.push(format!("{key} (tail and head of {stem}.png) is not set")),
+        }
+    }
+    for stem in ["click", "drag", "flick", "hold"] {
+        if let (Some(&(w, _)), Some(&(mh, _))) = (sizes.get(stem), sizes.get(format!("{stem}_mh").as_str())) {
+            if mh < w {
+                problems.push(format!("{stem}_mh.png is narrower than {stem}.png"));
+            }
+        }
+    }
+    problems
+}
+
+/// The files of `base` (if any) with `replacements`, a source file per pack file
+/// name, laid over them; hitsounds are converted as on [`install`].
+///
+/// Notes share their stem between image and sound, so `click.png` replaces the
+/// image and `click.ogg` (from any audio format) the sound.
+fn layer(ffmpeg: &str, base: Option<&Path>, replacements: &HashMap<String, PathBuf>) -> Result<HashMap<String, Vec<u8>>> {
+    let mut files: HashMap<String, Vec<u8>> = match base {
+        Some(base) => read_all(base)
+            .with_context(|| format!("failed to read {}", base.display()))?
+            .into_iter()
+            .collect(),
+        None => HashMap::new(),
+    };
+    let is_sound = |name: &str, stem: &str| {
+        name.rsplit_once('.')
+            .is_some_and(|(it, extension)| it == stem && AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+    };
+    for (name, source) in replacements {
+        let hitsound = HITSOUNDS.iter().find(|&&stem| name == &format!("{stem}.ogg"));
+        if hitsound.is_none() && !image_names().any(|it| it == *name) {
+            bail!("{name} is not a resource pack file");
+        }
+        let bytes = std::fs::read(source).with_context(|| format!("failed to read {}", source.display()))?;
+        let extension = source.extension().map_or(String::new(), |it| it.to_string_lossy().to_lowercase());
+        let bytes = match hitsound {
+            Some(&stem) => {
+                if !AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+                    bail!("{} is not an audio file", source.display());
+                }
+                // the converted sound replaces whatever format the base pack had,
+                // and nothing but the sound
+                files.retain(|it, _| !is_sound(it, stem));
+                convert(ffmpeg, &format!("{stem}.{extension}"), &bytes)?.unwrap_or(bytes)
+            }
+            None => bytes,
+        };
+        files.insert(name.clone(), bytes);
+    }
+    Ok(files)
+}
+
+/// Writes `files` into a zip at `target`.
+fn write_zip(files: &HashMap<String, Vec<u8>>, target: &Path) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default();
+    let mut names: Vec<_> = files.keys().collect();
+    names.sort();
+    for name in names {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(&files[name])?;
+    }
+    std::fs::write(target, zip.finish()?.into_inner()).with_context(|| format!("failed to write {}", target.display()))
+}
+
+/// Writes `base` with single elements replaced to `target`, checked like a pack
+/// from [`create`].
+pub fn overlay(ffmpeg: &str, base: &Path, replacements: &HashMap<String, PathBuf>, target: &Path) -> Result<()> {
+    let files = layer(ffmpeg, Some(base), replacements)?;
+    let info = match files.get("info.yml") {
+        Some(bytes) => serde_yaml::from_slice(bytes).context("invalid info.yml in the resource pack")?,
+        None => serde_yaml::Mapping::new(),
+    };
+    let problems = problems(&files, &info);
+    if !problems.is_empty() {
+        bail!("{}", problems.join("\n"));
+    }
+    write_zip(&files, target)
+}
+
+/// Assembles a pack from `spec` into `dir`, converting hitsounds like [`install`],
+/// and returns where it went. Fails listing every problem if the images don't
+/// fit the metadata.
+pub fn create(ffmpeg: &str, spec: &RespackSpec, dir: &Path) -> Result<PathBuf> {
+    let mut files = layer(ffmpeg, spec.base.as_deref(), &spec.files)?;
+    let mut info = match files.get("info.yml") {
+        Some(bytes) => serde_yaml::from_slice(bytes).context("invalid info.yml in the base pack")?,
+        None => serde_yaml::Mapping::new(),
+    };
+
+    let mut set = |key: &str, value: serde_yaml::Value| {
+        info.insert(key.into(), value);
+    };
+    set("name", spec.name.clone().into());
+    set("author", spec.author.clone().into());
+    set("description", spec.description.clone().into());
+    let pair = |[a, b]: [u32; 2]| {
+        serde_yaml::Value::Sequence(vec![serde_yaml::Value::Number(a.into()), serde_yaml::Value::Number(b.into())])
+    };
+    if let Some(it) = spec.hit_fx {
+        set("hitFx", pair(it));
+    }
+    if let Some(it) = spec.hold_atlas {
+        set("holdAtlas", pair(it));
+    }
+    if let Some(it) = spec.hold_atlas_mh {
+        set("holdAtlasMH", pair(it));
+    }
+    for (key, value) in &spec.extra {
+        info.insert(key.clone(), value.clone());
+    }
+
+    let problems = problems(&files, &info);
+    if !problems.is_empty() {
+        bail!("{}", problems.join("\n"));
+    }
+    files.insert("info.yml".to_owned(), serde_yaml::to_string(&info)?.into_bytes());
+
+    let stem: String = spec
+        .name
+        .chars()
+        .filter(|&it| it == '-' || it == '_' || it == ' ' || it.is_alphanumeric())
+        .collect();
+    if stem.trim().is_empty() {
+        bail!("resource pack name is empty");
+    }
+    let target = dir.join(format!("{}.zip", stem.trim()));
+    write_zip(&files, &target)?;
+    checksum::record(&target)?;
+    Ok(target)
+}
+
+/// Beats per minute of the sample chart, which makes one time unit 1/64 s.
+const SAMPLE_BPM: f64 = 120.;
+/// Length of the sample chart's (silent) music, in seconds.
+const SAMPLE_LENGTH: f64 = 8.;
+
+/// Writes a short chart with a few of every note kind, a chord included, and
+/// silent music into `dir`, to render resource pack previews with.
+pub fn write_sample_chart(ffmpeg: &str, dir: &Path) -> Result<()> {
+    // (kind, seconds, x, hold seconds); kinds as in the official format:
+    // 1 tap, 2 drag, 3 hold, 4 flick
+    let notes: &[(u32, f64, f64, f64)] = &[
+        (1, 1.5, -3., 0.),
+        (1, 2., 3., 0.),
+        (1, 2.5, -2., 0.),
+        (1, 2.5, 2., 0.),
+        (2, 3., -4., 0.),
+        (2, 3.125, -3., 0.),
+        (2, 3.25, -2., 0.),
+        (2, 3.375, -1., 0.),
+        (3, 3.75, 2., 1.),
+        (3, 4.25, -2., 0.75),
+        (4, 5.25, -3., 0.),
+        (4, 5.5, 0., 0.),
+        (4, 5.75, 3., 0.),
+        (3, 6., 0., 0.5),
+    ];
+    let unit = |seconds: f64| (seconds * SAMPLE_BPM / 1.875).round();
+    let notes: Vec<serde_json::Value> = notes
+        .iter()
+        .map(|&(kind, time, x, hold)| {
+            serde_json::json!({
+                "type": kind,
+                "time": unit(time),
+                "positionX": x,
+                "holdTime": unit(hold),
+                "speed": 1.0,
+                "floorPosition": time,
+            })
+        })
+        .collect();
+    let forever = 1e9;
+    let chart = serde_json::json!({
+        "formatVersion": 3,
+        "offset": 0.0,
+        "judgeLineList": [{
+            "bpm": SAMPLE_BPM,
+            "notesAbove": notes,
+            "notesBelow": [],
+            "speedEvents": [{ "startTime": 0.0, "endTime": forever, "value": 1.0 }],
+            "judgeLineMoveEvents": [{
+                "startTime": -999999.0, "endTime": forever,
+                "start": 0.5, "end": 0.5, "start2": 0.25, "end2": 0.25,
+            }],
+            "judgeLineRotateEvents": [{ "startTime": -999999.0, "endTime": forever, "start": 0.0, "end": 0.0 }],
+            "judgeLineDisappearEvents": [{ "startTime": -999999.0, "endTime": forever, "start": 1.0, "end": 1.0 }],
+        }],
+    });
+    std::fs::write(dir.join("chart.json"), serde_json::to_string(&chart)?)?;
+
+    let background = image::RgbImage::from_pixel(16, 9, image::Rgb([40, 44, 52]));
+    background.save(dir.join("background.png"))?;
+    let status = cmd_hidden(ffmpeg)
+        .args(["-y", "-f", "lavfi", "-i"])
+        .arg(format!("anullsrc=r={SAMPLE_RATE}:cl=stereo"))
+        .arg("-t")
+        .arg(SAMPLE_LENGTH.to_string())
+        .args(["-c:a", "libvorbis"])
+        .arg(dir.join("music.ogg"))
+        .args(["-loglevel", "error"])
+        .status()
+        .context("failed to run ffmpeg")?;
+    if !status.success() {
+        bail!("failed to write the sample music");
+    }
+    std::fs::write(
+        dir.join("info.yml"),
+        "name: Resource Pack Preview\nlevel: SP Lv.?\ncharter: Phigros Recorder\ncomposer: ''\nillustrator: ''\n\
+         chart: chart.json\nmusic: music.ogg\nillustration: background.png\n",
+    )?;
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! `schema [params|config] [output]`: writes the JSON Schema of [`RenderParams`]
+//! (or just its [`RenderConfig`]) to `output`, or to stdout, so that scripts
+//! and the frontend can check what they send against what the renderer reads.
+
+use crate::render::{RenderConfig, RenderParams};
+use anyhow::{bail, Context, Result};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use std::path::PathBuf;
+
+/// Stands in for prpr's `ChallengeModeColor`, which has no schema of its own.
+#[derive(JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+#[allow(dead_code)]
+pub enum ChallengeModeColor {
+    White,
+    Green,
+    Blue,
+    Red,
+    Golden,
+    Rainbow,
+}
+
+pub fn of(kind: &str) -> Result<RootSchema> {
+    Ok(match kind {
+        "params" => schema_for!(RenderParams),
+        "config" => schema_for!(RenderConfig),
+        _ => bail!("unknown schema {kind}, expected params or config"),
+    })
+}
+
+pub async fn main() -> Result<()> {
+    let kind = std::env::args().nth(2).unwrap_or_else(|| "params".to_owned());
+    let output: Option<PathBuf> = std::env::args().nth(3).map(Into::into);
+    let schema = serde_json::to_string_pretty(&of(&kind)?)?;
+    match output {
+        Some(path) => std::fs::write(&path, schema).with_context(|| format!("failed to write {}", path.display()))?,
+        None => println!("{schema}"),
+    }
+    Ok(())
+}
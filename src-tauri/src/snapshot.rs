@@ -0,0 +1,168 @@
+//! `snapshot <asset path> <suite> [--update]`: renders chosen frames of a chart
+//! and compares them with reference images, to catch unintended changes after
+//! touching rendering code.
+//!
+//! A suite is a YAML (or JSON) file:
+//!
+//! ```yaml
+//! params: params.json       # RenderParams, relative to the suite
+//! frames: [1.5, 12, 30.25]  # video times in seconds
+//! references: snapshots     # optional, the folder the suite is in otherwise
+//! tolerance: 2              # optional, mean difference per channel out of 255
+//! maxChanged: 0.01          # optional, share of pixels allowed to differ visibly
+//! ```
+//!
+//! Missing references are written from the render instead, as are all of them
+//! with `--update`. A frame that fails leaves `<name>.actual.png` and
+//! `<name>.diff.png` next to its reference.
+
+use crate::render::cmd_hidden;
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+/// Difference in any channel from which a pixel counts as visibly changed.
+const VISIBLE: u8 = 32;
+/// How much the diff image brightens differences by.
+const DIFF_GAIN: u32 = 4;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Suite {
+    params: PathBuf,
+    frames: Vec<f64>,
+    #[serde(default)]
+    references: Option<PathBuf>,
+    #[serde(default = "Suite::default_tolerance")]
+    tolerance: f64,
+    #[serde(default = "Suite::default_max_changed")]
+    max_changed: f64,
+}
+
+impl Suite {
+    fn default_tolerance() -> f64 {
+        2.
+    }
+
+    fn default_max_changed() -> f64 {
+        0.01
+    }
+}
+
+/// How far a frame is from its reference.
+struct Difference {
+    /// Mean absolute difference per colour channel, out of 255
+    mean: f64,
+    /// Share of pixels with a channel off by at least [`VISIBLE`]
+    changed: f64,
+    /// Per-pixel difference, brightened
+    image: Vec<u8>,
+}
+
+fn compare(actual: &RgbaImage, expected: &RgbaImage) -> Result<Difference> {
+    if actual.dimensions() != expected.dimensions() {
+        bail!(
+            "size {}x{} differs from the reference's {}x{}",
+            actual.width(),
+            actual.height(),
+            expected.width(),
+            expected.height()
+        );
+    }
+    let (mut sum, mut changed) = (0u64, 0usize);
+    let image: Vec<u8> = actual
+        .pixels()
+        .zip(expected.pixels())
+        .map(|(a, e)| {
+            let diff = (0..3).map(|c| a[c].abs_diff(e[c])).max().unwrap();
+            sum += (0..3).map(|c| a[c].abs_diff(e[c]) as u64).sum::<u64>();
+            if diff >= VISIBLE {
+                changed += 1;
+            }
+            (diff as u32 * DIFF_GAIN).min(255) as u8
+        })
+        .collect();
+    let pixels = image.len().max(1);
+    Ok(Difference {
+        mean: sum as f64 / (pixels * 3) as f64,
+        changed: changed as f64 / pixels as f64,
+        image,
+    })
+}
+
+/// Renders the frame at `time` with the `frame` subprocess.
+fn render_frame(asset: &str, params: &Path, time: f64, output: &Path) -> Result<()> {
+    let status = cmd_hidden(std::env::current_exe()?)
+        .arg("frame")
+        .arg(asset)
+        .arg(time.to_string())
+        .arg("--params")
+        .arg(params)
+        .arg("--output")
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .context("failed to run the frame renderer")?;
+    if !status.success() {
+        bail!("rendering the frame at {time}s failed ({status})");
+    }
+    Ok(())
+}
+
+pub async fn main() -> Result<()> {
+    let asset = std::env::args().nth(2).context("missing asset path")?;
+    let suite_path: PathBuf = std::env::args().nth(3).context("missing suite path")?.into();
+    let update = std::env::args().skip(4).any(|it| it == "--update");
+
+    let text = std::fs::read_to_string(&suite_path)
+        .with_context(|| format!("failed to read {}", suite_path.display()))?;
+    let suite: Suite = serde_yaml::from_str(&text).with_context(|| format!("invalid suite {}", suite_path.display()))?;
+    let base = suite_path.parent().unwrap_or(Path::new("."));
+    let params = base.join(&suite.params);
+    let references = base.join(suite.references.as_deref().unwrap_or(Path::new("")));
+    std::fs::create_dir_all(&references)?;
+
+    let scratch = tempfile::tempdir()?;
+    let mut failed = 0;
+    for &time in &suite.frames {
+        let name = format!("frame-{time:.3}");
+        let reference = references.join(format!("{name}.png"));
+        let actual = scratch.path().join(format!("{name}.png"));
+        render_frame(&asset, &params, time, &actual)?;
+        if update || !reference.exists() {
+            std::fs::copy(&actual, &reference).with_context(|| format!("failed to write {}", reference.display()))?;
+            println!("{name}: reference written");
+            continue;
+        }
+        let actual_image = image::open(&actual)?.to_rgba8();
+        let expected = image::open(&reference)
+            .with_context(|| format!("failed to read {}", reference.display()))?
+            .to_rgba8();
+        let result = compare(&actual_image, &expected).and_then(|diff| {
+            let verdict = format!("mean difference {:.2}, {:.2}% of pixels changed", diff.mean, diff.changed * 100.);
+            if diff.mean > suite.tolerance || diff.changed > suite.max_changed {
+                let (w, h) = actual_image.dimensions();
+                image::save_buffer(references.join(format!("{name}.diff.png")), &diff.image, w, h, image::ColorType::L8)?;
+                bail!(verdict);
+            }
+            Ok(verdict)
+        });
+        match result {
+            Ok(verdict) => println!("{name}: ok ({verdict})"),
+            Err(err) => {
+                std::fs::copy(&actual, references.join(format!("{name}.actual.png")))?;
+                println!("{name}: FAILED ({err})");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} frames differ from their references", suite.frames.len());
+    }
+    Ok(())
+}
@@ -1,24 +1,28 @@
 use crate::{
+    archive,
+    audio::Loudness,
     cmd_hidden,
     common::output_dir,
-    render::{IPCEvent, RenderParams},
+    frametime::FrameStats,
+    GL_API_ENV, GPU_ENV,
+    render::{IPCEvent, IPCMessage, RenderParams, Resume, IPC_PROTOCOL_VERSION},
     ASSET_PATH,
 };
 use anyhow::Result;
 use chrono::Local;
 use prpr::fs;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::VecDeque,
     io::Write,
     ops::DerefMut,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Stdio,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
-    time::Instant,
+    time::{Duration, Instant},
 };
 use tempfile::NamedTempFile;
 use tokio::{
@@ -26,9 +30,14 @@ use tokio::{
     sync::{mpsc, Mutex},
     task::JoinHandle,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-#[derive(Serialize, Clone)]
+/// How long a task waits before trying again when all NVENC sessions are taken.
+const NVENC_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+/// How many of the last lines a render printed to stderr are kept for its status.
+const STDERR_TAIL_LINES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum TaskStatus {
     Pending,
@@ -42,62 +51,193 @@ pub enum TaskStatus {
     Done {
         duration: f64,
         output: String,
+        #[serde(default)]
+        frame_stats: Option<FrameStats>,
     },
     Canceled,
     Failed {
         error: String,
+        /// Locale key and step reported by the renderer, if it got far enough to report one.
+        code: Option<String>,
+        stage: Option<String>,
     },
 }
 
 pub struct Task {
     id: u32,
+    job: String,
     name: String,
     cover: NamedTempFile,
     output: PathBuf,
+    /// Of the chart when it was queued
+    fingerprint: String,
 
     params: RenderParams,
     status: Mutex<TaskStatus>,
     request_cancel: AtomicBool,
+    /// Where the next attempt picks up, once an encoder has died mid-render
+    resume: Mutex<Option<Resume>>,
+    /// Set when the last attempt ended with a resumable encoder death
+    restart: AtomicBool,
+    warnings: Mutex<Vec<String>>,
+    loudness: Mutex<Option<Loudness>>,
 }
 
 impl Task {
-    async fn new(id: u32, params: RenderParams) -> Result<Self> {
-        let mut fs = fs::fs_from_file(&params.path)?;
+    /// `taken` tells whether another queued task already writes to a path.
+    async fn new(id: u32, mut params: RenderParams, taken: impl Fn(&Path) -> bool) -> Result<Self> {
+        let job = params
+            .job_id
+            .get_or_insert_with(|| uuid::Uuid::new_v4().to_string())
+            .clone();
+        // only ever set by the task itself, between attempts
+        params.resume = None;
+        let fingerprint = archive::fingerprint(&params.path)?;
+        let mut fs = archive::open(&params.path)?;
         let info = fs::load_info(fs.deref_mut()).await?;
         let config = params.config.to_config();
         let mut cover = NamedTempFile::new()?;
         cover.write_all(&fs.load_file(&info.illustration).await?)?;
 
         let level: String = info.level.split_whitespace().next().unwrap_or("UK").to_string();
-        let safe_name: String = info
-            .name
-            .chars()
-            .filter(|&it| it == '-' || it == '_' || it == ' ' || it.is_alphanumeric())
-            .collect();
+        let safe = |text: &str| -> String {
+            text.chars()
+                .filter(|&it| it == '-' || it == '_' || it == ' ' || it.is_alphanumeric())
+                .collect()
+        };
+        let safe_name = safe(&info.name);
         let format = if config.hires {"mov"} else {"mp4"};
-        let output = output_dir()?.join(format!(
-            "{} {safe_name}_{level}.{format}",
-            Local::now().format("%Y-%m-%d %H-%M-%S")
-        ));
+        let date = Local::now().format("%Y-%m-%d %H-%M-%S").to_string();
+        let stem = params
+            .output_name
+            .as_ref()
+            .map(|template| {
+                let name = template
+                    .replace("{name}", &safe_name)
+                    .replace("{level}", &level)
+                    .replace("{composer}", &safe(&info.composer))
+                    .replace("{charter}", &safe(&info.charter))
+                    .replace("{date}", &date);
+                // subfolders are fine, leaving the output folder isn't: no `..`,
+                // no leading separator and no drive
+                name.split(['/', '\\'])
+                    .map(|it| it.replace(':', ""))
+                    .filter(|it| !it.is_empty() && it != "." && it != "..")
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .filter(|it| !it.is_empty())
+            .unwrap_or_else(|| format!("{date} {safe_name}_{level}"));
+        // jobs queued within the same second would share a name
+        let mut output = output_dir()?.join(format!("{stem}.{format}"));
+        let mut n = 1;
+        while output.exists() || taken(&output) {
+            n += 1;
+            output = output_dir()?.join(format!("{stem} ({n}).{format}"));
+        }
+        if let Some(parent) = output.parent() {
+            // templates may sort videos into subfolders
+            std::fs::create_dir_all(parent)?;
+        }
 
         Ok(Self {
             id,
+            job,
             name: info.name,
             cover,
             output,
+            fingerprint,
 
             params,
             status: Mutex::new(TaskStatus::Pending),
             request_cancel: AtomicBool::default(),
+            resume: Mutex::default(),
+            restart: AtomicBool::default(),
+            warnings: Mutex::default(),
+            loudness: Mutex::default(),
         })
     }
 
     pub async fn run(&self) -> Result<()> {
         info!("Task #{} started ({})", self.id, self.params.path.display());
+        if archive::fingerprint(&self.params.path).is_ok_and(|it| it != self.fingerprint) {
+            // renders what is there now, under the name and cover it was queued with
+            warn!("Task #{} chart changed since it was queued", self.id);
+            self.warnings
+                .lock()
+                .await
+                .push(mtl!("chart-changed").to_string());
+        }
 
+        let result = self.run_attempts().await;
+        self.discard_parts().await;
+        result
+    }
+
+    /// Runs the render process until it is done, fails for good or is canceled,
+    /// restarting it where its encoder died or once an NVENC session frees up.
+    async fn run_attempts(&self) -> Result<()> {
+        // desktop GL may be missing entirely (VMs, remote sessions); give GLES a go
+        // if the renderer died before producing anything
+        let can_retry = cfg!(target_os = "linux") && std::env::var_os(GL_API_ENV).is_none();
+        loop {
+            if !self.run_with(None, can_retry).await? {
+                info!("Task #{} retrying with GLES", self.id);
+                self.run_with(Some("gles"), false).await?;
+            }
+            if self.restart.swap(false, Ordering::SeqCst) && !self.request_cancel.load(Ordering::Relaxed) {
+                continue;
+            }
+            let busy = matches!(
+                &*self.status.lock().await,
+                TaskStatus::Failed { code: Some(code), .. } if code == "nvenc-busy"
+            );
+            if !busy || !self.params.config.waits_for_nvenc() {
+                return Ok(());
+            }
+            info!("Task #{} waiting for a free NVENC session", self.id);
+            *self.status.lock().await = TaskStatus::Pending;
+            tokio::time::sleep(NVENC_RETRY_INTERVAL).await;
+            if self.request_cancel.load(Ordering::Relaxed) {
+                *self.status.lock().await = TaskStatus::Canceled;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Deletes the segments a failed or canceled render kept for a restart that
+    /// won't happen any more, and the video it was encoding into otherwise.
+    async fn discard_parts(&self) {
+        if matches!(&*self.status.lock().await, TaskStatus::Done { .. }) {
+            return;
+        }
+        let parts = self.output.with_extension("parts");
+        if parts.is_dir() {
+            if let Err(err) = std::fs::remove_dir_all(&parts) {
+                warn!("Task #{} failed to remove {}: {err:?}", self.id, parts.display());
+            }
+        }
+        let video = self.output.with_extension("video.mkv");
+        if video.is_file() {
+            if let Err(err) = std::fs::remove_file(&video) {
+                warn!("Task #{} failed to remove {}: {err:?}", self.id, video.display());
+            }
+        }
+    }
+
+    /// Returns `false` if `can_retry` is set and the render process failed before
+    /// it started rendering, leaving the status for the next attempt to fill in.
+    async fn run_with(&self, gl_api: Option<&str>, can_retry: bool) -> Result<bool> {
         *self.status.lock().await = TaskStatus::Loading;
 
-        let mut child = cmd_hidden(std::env::current_exe()?)
+        let mut command = cmd_hidden(std::env::current_exe()?);
+        if let Some(gl_api) = gl_api {
+            command.env(GL_API_ENV, gl_api);
+        }
+        if let Some(gpu) = self.params.gpu {
+            command.env(GPU_ENV, gpu.to_string());
+        }
+        let mut child = command
             .arg("render")
             .arg(ASSET_PATH.get().unwrap())
             .stdin(Stdio::piped())
@@ -106,9 +246,26 @@ impl Task {
             .spawn()?;
         let mut stdin = child.stdin.take().unwrap();
         let stdout = child.stdout.take().unwrap();
+        // drained all along, as a full pipe would block the render on its next log line
+        let stderr = child.stderr.take().unwrap();
+        let stderr = tokio::spawn(async move {
+            let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            let mut lines = BufReader::new(stderr).split(b'\n');
+            while let Ok(Some(line)) = lines.next_segment().await {
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(String::from_utf8_lossy(&line).into_owned());
+            }
+            Vec::from(tail).join("\n")
+        });
 
+        let mut params = serde_json::to_value(&self.params)?;
+        if let Some(resume) = &*self.resume.lock().await {
+            params["resume"] = serde_json::to_value(resume)?;
+        }
         stdin
-            .write_all(format!("{}\n", serde_json::to_string(&self.params)?).as_bytes())
+            .write_all(format!("{}\n", serde_json::to_string(&params)?).as_bytes())
             .await?;
         stdin
             .write_all(format!("{}\n", serde_json::to_string(&self.output)?).as_bytes())
@@ -122,11 +279,68 @@ impl Task {
         let mut frame_times = VecDeque::new();
         let mut last_update_fps_sec: u32 = 0;
         let mut last_fps: usize = 0;
+        let mut greeted = false;
+        let mut reported = None;
         loop {
             let line = lines.next_line().await?;
             let Some(line) = line else { break };
-            let Ok(event): Result<IPCEvent, _> = serde_json::from_str(line.trim()) else { continue };
+            let Ok(IPCMessage { job, event }) = serde_json::from_str(line.trim()) else { continue };
+            if job.as_ref().is_some_and(|it| *it != self.job) {
+                warn!("Task #{} ignoring event for job {job:?}", self.id);
+                continue;
+            }
+            let mismatch = match &event {
+                IPCEvent::Hello { protocol_version, renderer_version } => {
+                    (*protocol_version != IPC_PROTOCOL_VERSION).then(|| {
+                        format!("renderer {renderer_version} speaks protocol v{protocol_version}")
+                    })
+                }
+                _ if !greeted => Some("renderer did not announce its protocol version".to_owned()),
+                _ => None,
+            };
+            if let Some(reason) = mismatch {
+                child.kill().await?;
+                *self.status.lock().await = TaskStatus::Failed {
+                    error: format!(
+                        "Incompatible renderer: {reason}, expected protocol v{IPC_PROTOCOL_VERSION} ({})",
+                        env!("CARGO_PKG_VERSION")
+                    ),
+                    code: None,
+                    stage: None,
+                };
+                return Ok(true);
+            }
             match event {
+                IPCEvent::Hello { .. } => {
+                    greeted = true;
+                }
+                IPCEvent::Error {
+                    code,
+                    stage,
+                    message,
+                    detail,
+                } => {
+                    error!("Task #{} failed at {stage} ({code}): {message}", self.id);
+                    reported = Some((code, stage, format!("{message}\n\n{detail}")));
+                }
+                IPCEvent::EncoderDied { frame, segment, encoder } => {
+                    if let Some(next) = self.params.config.next_encoder(&encoder) {
+                        warn!("Task #{} lost {encoder} at frame {frame}, resuming with {next}", self.id);
+                        *self.resume.lock().await = Some(Resume {
+                            frame,
+                            segment,
+                            encoder: next.to_owned(),
+                        });
+                        self.restart.store(true, Ordering::SeqCst);
+                    }
+                }
+                IPCEvent::Loudness(loudness) => {
+                    *self.loudness.lock().await = Some(loudness);
+                }
+                IPCEvent::Warning { code, message } => {
+                    warn!("Task #{} ({code}): {message}", self.id);
+                    self.warnings.lock().await.push(message);
+                }
                 IPCEvent::Loading => {
                     *self.status.lock().await = TaskStatus::Loading;
                 }
@@ -141,11 +355,15 @@ impl Task {
                     };
                     total = total_frame;
                 }
-                IPCEvent::Frame => {
-                    frame_count += 1;
+                IPCEvent::Frame | IPCEvent::Frames(_) => {
+                    let count = match event {
+                        IPCEvent::Frames(count) => count,
+                        _ => 1,
+                    };
+                    frame_count += count;
                     let cur = start.elapsed().as_secs_f64();
                     let sec = cur as u32;
-                    frame_times.push_back(cur);
+                    frame_times.extend(std::iter::repeat(cur).take(count as usize));
                     while frame_times.front().is_some_and(|it| cur - *it > 1.) {
                         frame_times.pop_front();
                     }
@@ -161,41 +379,48 @@ impl Task {
                         estimate,
                     };
                 }
-                IPCEvent::Done(duration) => {
-                    let output = child.wait_with_output().await?;
-                    let stdout = String::from_utf8(output.stdout)
-                        .unwrap_or_else(|_| "Invalid output".to_owned());
-                    let stderr = String::from_utf8(output.stderr)
-                        .unwrap_or_else(|_| "Invalid output".to_owned());
+                IPCEvent::Done { duration, frame_stats } => {
+                    child.wait().await?;
                     *self.status.lock().await = TaskStatus::Done {
                         duration,
-                        //output: format!("[STDOUT]\n{stdout}\n\n[STDERR]\n{stderr}"),
-                        output: format!("{stdout}\n{stderr}"),
+                        output: stderr.await.unwrap_or_default(),
+                        frame_stats,
                     };
-                    return Ok(());
+                    return Ok(true);
                 }
             }
             if self.request_cancel.load(Ordering::Relaxed) {
                 child.kill().await?;
                 *self.status.lock().await = TaskStatus::Canceled;
-                return Ok(());
+                return Ok(true);
             }
         }
 
-        let output = child.wait_with_output().await?;
-        if !output.status.success() {
-            *self.status.lock().await = TaskStatus::Failed {
-                error: format!(
-                    "Child process exited abnormally ({:?})\n{}\n{}",
-                    output.status.code().unwrap_or_default(),
-                    String::from_utf8(output.stdout)?,
-                    String::from_utf8(output.stderr)?
-                ),
+        let status = child.wait().await?;
+        if !status.success() {
+            if total == 0 && reported.is_none() && can_retry {
+                return Ok(false);
+            }
+            *self.status.lock().await = match reported {
+                Some((code, stage, error)) => TaskStatus::Failed {
+                    error,
+                    code: Some(code),
+                    stage: Some(stage),
+                },
+                None => TaskStatus::Failed {
+                    error: format!(
+                        "Child process exited abnormally ({:?})\n{}",
+                        status.code().unwrap_or_default(),
+                        stderr.await.unwrap_or_default()
+                    ),
+                    code: None,
+                    stage: None,
+                },
             };
-            return Ok(());
+            return Ok(true);
         }
 
-        Ok(())
+        Ok(true)
     }
 
     pub fn cancel(&self) {
@@ -205,11 +430,14 @@ impl Task {
     pub async fn to_view(&self) -> TaskView {
         TaskView {
             id: self.id,
+            job: self.job.clone(),
             name: self.name.clone(),
             output: self.output.clone(),
             path: self.params.path.display().to_string(),
             cover: self.cover.path().display().to_string(),
             status: self.status.lock().await.clone(),
+            warnings: self.warnings.lock().await.clone(),
+            loudness: *self.loudness.lock().await,
         }
     }
 }
@@ -217,33 +445,75 @@ impl Task {
 #[derive(Serialize)]
 pub struct TaskView {
     id: u32,
+    job: String,
     name: String,
     output: PathBuf,
     path: String,
     cover: String,
     status: TaskStatus,
+    warnings: Vec<String>,
+    loudness: Option<Loudness>,
+}
+
+/// State of the whole queue, for a single progress display.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSummary {
+    pub pending: usize,
+    pub running: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub canceled: usize,
+    /// Share of all queued work that is done, finished tasks counting in full
+    pub progress: f64,
+    /// Combined speed of the running renders
+    pub fps: u64,
+    /// No new renders are started until the queue is resumed
+    pub paused: bool,
 }
 
 pub struct TaskQueue {
     sender: mpsc::UnboundedSender<Arc<Task>>,
     worker: JoinHandle<()>,
+    /// Renders allowed to run at once
+    parallel: Arc<AtomicUsize>,
+    /// Holds back pending tasks; running ones carry on
+    paused: Arc<AtomicBool>,
 
     tasks: Mutex<Vec<Arc<Task>>>,
 }
 impl TaskQueue {
     pub fn new() -> Self {
         let (sender, mut receiver) = mpsc::unbounded_channel::<Arc<Task>>();
-        let task = tokio::spawn(async move {
-            loop {
-                let Ok(task) = receiver.try_recv() else {
-                    std::thread::sleep(std::time::Duration::from_millis(500));
-                    continue
-                };
-                if let Err(err) = task.run().await {
-                    error!("Failed to render: {err:?}");
-                    *task.status.lock().await = TaskStatus::Failed {
-                        error: format!("{err:?}"),
+        let parallel = Arc::new(AtomicUsize::new(1));
+        let paused = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn({
+            let parallel = Arc::clone(&parallel);
+            let paused = Arc::clone(&paused);
+            async move {
+                loop {
+                    if paused.load(Ordering::SeqCst) || running.load(Ordering::SeqCst) >= parallel.load(Ordering::SeqCst) {
+                        std::thread::sleep(std::time::Duration::from_millis(500));
+                        continue;
+                    }
+                    let Ok(task) = receiver.try_recv() else {
+                        std::thread::sleep(std::time::Duration::from_millis(500));
+                        continue
                     };
+                    running.fetch_add(1, Ordering::SeqCst);
+                    let running = Arc::clone(&running);
+                    tokio::spawn(async move {
+                        if let Err(err) = task.run().await {
+                            error!("Failed to render: {err:?}");
+                            *task.status.lock().await = TaskStatus::Failed {
+                                error: format!("{err:?}"),
+                                code: None,
+                                stage: None,
+                            };
+                        }
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    });
                 }
             }
         });
@@ -251,21 +521,88 @@ impl TaskQueue {
         Self {
             sender,
             worker: task,
+            parallel,
+            paused,
 
             tasks: Mutex::default(),
         }
     }
 
+    pub fn set_parallel(&self, count: usize) {
+        self.parallel.store(count.max(1), Ordering::SeqCst);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub async fn summary(&self) -> QueueSummary {
+        let guard = self.tasks.lock().await;
+        let mut summary = QueueSummary {
+            paused: self.is_paused(),
+            ..QueueSummary::default()
+        };
+        let mut progress = 0.;
+        for task in guard.iter() {
+            match &*task.status.lock().await {
+                TaskStatus::Pending => summary.pending += 1,
+                TaskStatus::Loading | TaskStatus::Mixing => summary.running += 1,
+                TaskStatus::Rendering { progress: done, fps, .. } => {
+                    summary.running += 1;
+                    summary.fps += fps;
+                    progress += done;
+                }
+                TaskStatus::Done { .. } => {
+                    summary.done += 1;
+                    progress += 1.;
+                }
+                // nothing more will happen to these, so they count as finished
+                TaskStatus::Failed { .. } => {
+                    summary.failed += 1;
+                    progress += 1.;
+                }
+                TaskStatus::Canceled => {
+                    summary.canceled += 1;
+                    progress += 1.;
+                }
+            }
+        }
+        summary.progress = if guard.is_empty() { 0. } else { progress / guard.len() as f64 };
+        summary
+    }
+
     pub async fn post(&self, params: RenderParams) -> Result<u32> {
         let mut guard = self.tasks.lock().await;
         let id = guard.len() as u32;
-        let task = Arc::new(Task::new(id, params).await?);
+        let task = Arc::new(Task::new(id, params, |path| guard.iter().any(|it| it.output == path)).await?);
         guard.push(Arc::clone(&task));
         self.sender.send(task)?;
 
         Ok(id)
     }
 
+    /// Current status and output path of task `id`.
+    pub async fn status(&self, id: u32) -> (TaskStatus, PathBuf) {
+        let task = Arc::clone(&self.tasks.lock().await[id as usize]);
+        let status = task.status.lock().await.clone();
+        (status, task.output.clone())
+    }
+
+    /// Waits for task `id` to finish, returning its final status and output path.
+    pub async fn wait(&self, id: u32) -> (TaskStatus, PathBuf) {
+        loop {
+            let (status, output) = self.status(id).await;
+            if matches!(status, TaskStatus::Done { .. } | TaskStatus::Failed { .. } | TaskStatus::Canceled) {
+                return (status, output);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+
     pub async fn tasks(&self) -> Vec<TaskView> {
         let guard = self.tasks.lock().await;
         let mut result = Vec::with_capacity(guard.capacity());
@@ -279,6 +616,30 @@ impl TaskQueue {
     pub async fn cancel(&self, id: u32) {
         self.tasks.lock().await[id as usize].cancel();
     }
+
+    /// Name and progress of the oldest task that is running.
+    pub async fn current(&self) -> Option<(String, f64)> {
+        for task in self.tasks.lock().await.iter() {
+            match &*task.status.lock().await {
+                TaskStatus::Loading | TaskStatus::Mixing => return Some((task.name.clone(), 0.)),
+                TaskStatus::Rendering { progress, .. } => return Some((task.name.clone(), *progress)),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Cancels every task that is running.
+    pub async fn cancel_running(&self) {
+        for task in self.tasks.lock().await.iter() {
+            if matches!(
+                &*task.status.lock().await,
+                TaskStatus::Loading | TaskStatus::Mixing | TaskStatus::Rendering { .. }
+            ) {
+                task.cancel();
+            }
+        }
+    }
 }
 
 impl Drop for TaskQueue {
@@ -0,0 +1,130 @@
+use crate::task::QueueSummary;
+use std::sync::mpsc::Sender;
+use tauri::Window;
+#[cfg(target_os = "windows")]
+use tracing::warn;
+
+/// What the taskbar button shows, with the share of the queue that is done.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Progress {
+    None,
+    Normal(f64),
+    /// Work is queued but nothing renders: the queue is paused, or waits for an NVENC session
+    Paused(f64),
+    /// The queue ran dry with at least one failed render
+    Error(f64),
+}
+
+impl Progress {
+    pub fn of(summary: &QueueSummary) -> Self {
+        if summary.running > 0 {
+            Self::Normal(summary.progress)
+        } else if summary.pending > 0 {
+            Self::Paused(summary.progress)
+        } else if summary.failed > 0 {
+            Self::Error(summary.progress)
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// The taskbar button of a window, updated from a thread of its own since COM
+/// objects stay on the thread that made them.
+pub struct Taskbar {
+    sender: Sender<Progress>,
+    last: Option<Progress>,
+}
+
+impl Taskbar {
+    /// `None` where there is no taskbar progress to show.
+    pub fn new(window: &Window) -> Option<Self> {
+        #[cfg(target_os = "windows")]
+        {
+            let hwnd = match window.hwnd() {
+                Ok(hwnd) => hwnd.0,
+                Err(err) => {
+                    warn!("Taskbar progress unavailable: {err:?}");
+                    return None;
+                }
+            };
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                if let Err(err) = windows::run(hwnd, receiver) {
+                    warn!("Taskbar progress unavailable: {err:?}");
+                }
+            });
+            Some(Self { sender, last: None })
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let _ = window;
+            None
+        }
+    }
+
+    pub fn set(&mut self, progress: Progress) {
+        if self.last != Some(progress) {
+            let _ = self.sender.send(progress);
+            self.last = Some(progress);
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::Progress;
+    use anyhow::{bail, Result};
+    use std::{ptr::null_mut, sync::mpsc::Receiver};
+    use winapi::{
+        shared::{windef::HWND, winerror::FAILED, wtypesbase::CLSCTX_INPROC_SERVER},
+        um::{
+            combaseapi::{CoCreateInstance, CoInitializeEx},
+            objbase::COINIT_APARTMENTTHREADED,
+            shobjidl_core::{
+                CLSID_TaskbarList, ITaskbarList3, TBPF_ERROR, TBPF_NOPROGRESS, TBPF_NORMAL, TBPF_PAUSED,
+            },
+        },
+        Interface,
+    };
+
+    const STEPS: u64 = 1000;
+
+    pub fn run(hwnd: isize, receiver: Receiver<Progress>) -> Result<()> {
+        let hwnd = hwnd as HWND;
+        unsafe {
+            CoInitializeEx(null_mut(), COINIT_APARTMENTTHREADED);
+            let mut taskbar: *mut ITaskbarList3 = null_mut();
+            let hr = CoCreateInstance(
+                &CLSID_TaskbarList,
+                null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &ITaskbarList3::uuidof(),
+                &mut taskbar as *mut _ as *mut _,
+            );
+            if FAILED(hr) {
+                bail!("CoCreateInstance failed ({hr:#x})");
+            }
+            let taskbar = &*taskbar;
+            let hr = taskbar.HrInit();
+            if FAILED(hr) {
+                taskbar.Release();
+                bail!("ITaskbarList3::HrInit failed ({hr:#x})");
+            }
+            for progress in receiver {
+                let (state, value) = match progress {
+                    Progress::None => (TBPF_NOPROGRESS, None),
+                    Progress::Normal(value) => (TBPF_NORMAL, Some(value)),
+                    Progress::Paused(value) => (TBPF_PAUSED, Some(value)),
+                    Progress::Error(value) => (TBPF_ERROR, Some(value)),
+                };
+                taskbar.SetProgressState(hwnd, state);
+                if let Some(value) = value {
+                    taskbar.SetProgressValue(hwnd, (value.clamp(0., 1.) * STEPS as f64) as u64, STEPS);
+                }
+            }
+            taskbar.Release();
+        }
+        Ok(())
+    }
+}
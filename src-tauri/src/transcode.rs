@@ -0,0 +1,305 @@
+use crate::render::{cmd_hidden, cut_points, media_duration, pick_encoder, require_ffmpeg, suggest_bitrate};
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::set_pc_assets_folder;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::info;
+
+/// Audio bitrate, in kbit/s, assumed when aiming for a file size.
+const TARGET_AUDIO_KBPS: f64 = 192.;
+/// The least video bitrate a file size may squeeze the video down to.
+const MIN_VIDEO_KBPS: f64 = 100.;
+
+/// What to turn an existing video into.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeParams {
+    pub input: PathBuf,
+    /// Next to the input, named after the codec and height, if unset
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// `h264`, `hevc` or `av1`
+    pub codec: String,
+    /// Scales down (never up) to this height, keeping the aspect ratio
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Picks the bitrate so that the whole file comes out at about this size
+    #[serde(default)]
+    pub target_size_mb: Option<f64>,
+    /// Video bitrate as ffmpeg takes it, for when there's no target size
+    #[serde(default)]
+    pub bitrate: Option<String>,
+}
+
+/// The first video stream of a file.
+struct VideoStream {
+    /// As ffmpeg names it, e.g. `h264`
+    codec: String,
+    size: (u32, u32),
+    fps: u32,
+}
+
+/// Reads the [`VideoStream`] from what ffmpeg prints for its inputs.
+fn video_stream(ffmpeg: &str, path: &Path) -> Option<VideoStream> {
+    let output = cmd_hidden(ffmpeg).arg("-hide_banner").arg("-i").arg(path).output().ok()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stderr.lines().find(|it| it.contains("Video:"))?;
+    let codec = line.split("Video: ").nth(1)?.split_whitespace().next()?.trim_end_matches(',').to_owned();
+    let mut size = None;
+    let mut fps = None;
+    for part in line.split(", ") {
+        let part = part.trim();
+        if let Some(value) = part.strip_suffix(" fps") {
+            fps = value.parse::<f64>().ok();
+        } else if let Some((w, h)) = part.split_whitespace().next().and_then(|it| it.split_once('x')) {
+            if let (Ok(w), Ok(h)) = (w.parse(), h.parse()) {
+                size.get_or_insert((w, h));
+            }
+        }
+    }
+    Some(VideoStream {
+        codec,
+        size: size?,
+        fps: fps?.round() as u32,
+    })
+}
+
+/// Runs `command` with ffmpeg's errors collected, failing with them if it fails.
+fn run_ffmpeg(command: &mut Command) -> Result<()> {
+    let mut proc = command
+        .args(["-loglevel", "error"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run ffmpeg")?;
+    let errors: Vec<String> = std::io::BufReader::new(proc.stderr.take().unwrap())
+        .lines()
+        .map_while(|it| it.ok())
+        .collect();
+    if !proc.wait()?.success() {
+        bail!("ffmpeg failed: {}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// `input` with `suffix` put before its extension, `mp4` if it has none.
+fn sibling(input: &Path, suffix: &str) -> Result<PathBuf> {
+    let stem = input.file_stem().context("invalid input path")?.to_string_lossy();
+    let extension = input.extension().map_or("mp4".into(), |it| it.to_string_lossy());
+    Ok(input.with_file_name(format!("{stem}.{suffix}.{extension}")))
+}
+
+/// Re-encodes `params.input` with the best encoder this machine has for the codec,
+/// copying audio and subtitles where the size allows, and returns the output path.
+pub fn run(params: &TranscodeParams) -> Result<PathBuf> {
+    let ffmpeg = require_ffmpeg()?;
+    let Some(encoder) = pick_encoder(&ffmpeg, &params.codec) else {
+        bail!("no working encoder for {}", params.codec);
+    };
+    let VideoStream {
+        size: (width, height),
+        fps,
+        ..
+    } = video_stream(&ffmpeg, &params.input).with_context(|| format!("no video in {}", params.input.display()))?;
+    let scaled = params.height.filter(|&it| it < height);
+    let size = match scaled {
+        // width rounded to even, as `scale=-2` does
+        Some(h) => ((width as f64 * h as f64 / height as f64 / 2.).round() as u32 * 2, h),
+        None => (width, height),
+    };
+
+    let output = match &params.output {
+        Some(output) => output.clone(),
+        None => sibling(&params.input, &format!("{}.{}p", params.codec, size.1))?,
+    };
+    if output == params.input {
+        bail!("output would overwrite the input");
+    }
+
+    let bitrate = if let Some(target) = params.target_size_mb {
+        let duration = media_duration(&ffmpeg, &params.input).context("can't read the length of the video")?;
+        let kbps = target * 8192. / duration.max(1.) - TARGET_AUDIO_KBPS;
+        format!("{:.0}k", kbps.max(MIN_VIDEO_KBPS))
+    } else if let Some(bitrate) = &params.bitrate {
+        bitrate.clone()
+    } else {
+        suggest_bitrate(size, fps, encoder, false)
+    };
+    info!("Transcoding with {} at {} to {}", encoder, bitrate, output.display());
+
+    let mut command = cmd_hidden(&ffmpeg);
+    command
+        .arg("-y")
+        .arg("-i")
+        .arg(&params.input)
+        .args(["-map", "0", "-c", "copy", "-c:v", encoder, "-b:v", &bitrate]);
+    if let Some(height) = scaled {
+        command.arg("-vf").arg(format!("scale=-2:{height}"));
+    }
+    if params.target_size_mb.is_some() {
+        // copied audio could be any size
+        command.args(["-c:a", "aac", "-b:a"]).arg(format!("{TARGET_AUDIO_KBPS:.0}k"));
+    }
+    run_ffmpeg(command.arg(&output))?;
+    Ok(output)
+}
+
+/// Where a trimmed video starts.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TrimStart {
+    /// Where the render starts
+    #[default]
+    Video,
+    /// `lead` seconds before the first note
+    FirstNote,
+}
+
+/// What `trim` prints when it is done, as one JSON line among its logs.
+#[derive(Serialize, Deserialize)]
+pub struct TrimResult {
+    pub output: PathBuf,
+}
+
+/// How to trim, crop or pad a finished render, with the cut points taken from the
+/// chart its manifest names.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrimParams {
+    pub manifest: PathBuf,
+    pub video: PathBuf,
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub start: TrimStart,
+    #[serde(default = "TrimParams::default_lead")]
+    pub lead: f64,
+    /// Seconds of the results screen to keep, all of it if unset
+    #[serde(default)]
+    pub ending_length: Option<f64>,
+    /// `[width, height, x, y]` of the area to keep
+    #[serde(default)]
+    pub crop: Option<[u32; 4]>,
+    /// `[width, height]` to pad to, centered
+    #[serde(default)]
+    pub pad: Option<[u32; 2]>,
+    /// Re-encode to cut on the exact frame, rather than copying from the keyframe
+    /// before the start
+    #[serde(default)]
+    pub exact: bool,
+}
+
+impl TrimParams {
+    fn default_lead() -> f64 {
+        1.
+    }
+}
+
+/// Trims, crops and pads `params.video`, copying the streams untouched unless a
+/// filter or an exact cut needs the video re-encoded, and returns the output path.
+async fn trim(params: &TrimParams) -> Result<PathBuf> {
+    let ffmpeg = require_ffmpeg()?;
+    let job = crate::manifest::load_params(&params.manifest, None)?;
+    let points = cut_points(&job).await?;
+    let stream =
+        video_stream(&ffmpeg, &params.video).with_context(|| format!("no video in {}", params.video.display()))?;
+    let duration = media_duration(&ffmpeg, &params.video).context("can't read the length of the video")?;
+
+    // on frame boundaries, so that a re-encode doesn't start on half a frame
+    let frame = |time: f64| (time * points.fps as f64).round() / points.fps as f64;
+    let start = match (params.start, points.first_note) {
+        (TrimStart::FirstNote, Some(first)) => frame((first - params.lead.max(0.)).max(0.)),
+        _ => 0.,
+    };
+    let end = match params.ending_length {
+        Some(length) => frame((points.ending + length.max(0.)).min(duration)),
+        None => duration,
+    };
+    if end <= start {
+        bail!("nothing left of the video after trimming");
+    }
+    let output = match &params.output {
+        Some(output) => output.clone(),
+        None => sibling(&params.video, "trim")?,
+    };
+    if output == params.video {
+        bail!("output would overwrite the input");
+    }
+
+    let mut filters = Vec::new();
+    if let Some([w, h, x, y]) = params.crop {
+        filters.push(format!("crop={w}:{h}:{x}:{y}"));
+    }
+    if let Some([w, h]) = params.pad {
+        filters.push(format!("pad={w}:{h}:(ow-iw)/2:(oh-ih)/2"));
+    }
+    let reencode = params.exact || !filters.is_empty();
+    info!(
+        "Trimming {} to {:.3}s ~ {:.3}s{}",
+        params.video.display(),
+        start,
+        end,
+        if reencode { "" } else { " (copy)" }
+    );
+
+    let mut command = cmd_hidden(&ffmpeg);
+    command.arg("-y");
+    if !reencode {
+        // seeking the input lands on the keyframe at or before `start`
+        command.arg("-ss").arg(start.to_string());
+    }
+    command.arg("-i").arg(&params.video).args(["-map", "0", "-c", "copy"]);
+    if reencode {
+        let codec = if stream.codec == "mpeg4" { "h264" } else { stream.codec.as_str() };
+        let encoder = pick_encoder(&ffmpeg, codec).with_context(|| format!("no working encoder for {codec}"))?;
+        let size = match (params.pad, params.crop) {
+            (Some([w, h]), _) | (None, Some([w, h, ..])) => (w, h),
+            _ => stream.size,
+        };
+        command
+            .arg("-ss")
+            .arg(start.to_string())
+            .args(["-c:v", encoder, "-b:v"])
+            .arg(suggest_bitrate(size, stream.fps, encoder, false));
+        if !filters.is_empty() {
+            command.arg("-vf").arg(filters.join(","));
+        }
+    }
+    command.arg("-t").arg((end - start).to_string());
+    run_ffmpeg(command.arg(&output))?;
+    Ok(output)
+}
+
+/// `transcode <params file>`, or the [`TranscodeParams`] as one JSON line on stdin.
+pub async fn main() -> Result<()> {
+    let params: TranscodeParams = match std::env::args().nth(2) {
+        Some(file) => {
+            let text = std::fs::read_to_string(&file).with_context(|| format!("failed to read {file}"))?;
+            serde_json::from_str(&text).with_context(|| format!("invalid params in {file}"))?
+        }
+        None => {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            serde_json::from_str(line.trim()).context("invalid params")?
+        }
+    };
+    let output = tokio::task::spawn_blocking(move || run(&params)).await??;
+    println!("{}", output.display());
+    Ok(())
+}
+
+/// `trim <asset path>`, with the [`TrimParams`] as one JSON line on stdin; prints a
+/// [`TrimResult`].
+pub async fn trim_main() -> Result<()> {
+    set_pc_assets_folder(&std::env::args().nth(2).context("missing asset path")?);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let params: TrimParams = serde_json::from_str(line.trim()).context("invalid params")?;
+    let output = trim(&params).await?;
+    println!("{}", serde_json::to_string(&TrimResult { output })?);
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::{
+    task::TaskQueue,
+    taskbar::{Progress, Taskbar},
+};
+use std::time::Duration;
+use tauri::{AppHandle, CustomMenuItem, Manager, SystemTrayMenu, SystemTrayMenuItem};
+
+/// How often the queue is looked at for the tray and the taskbar button.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn menu() -> SystemTrayMenu {
+    SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("status".to_owned(), mtl!("tray-idle")).disabled())
+        .add_item(CustomMenuItem::new("pause".to_owned(), mtl!("tray-pause")))
+        .add_item(CustomMenuItem::new("cancel".to_owned(), mtl!("tray-cancel")).disabled())
+        .add_item(CustomMenuItem::new("output".to_owned(), mtl!("tray-output")))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("toggle".to_owned(), mtl!("tray-hide")))
+        .add_item(CustomMenuItem::new("tasks".to_owned(), mtl!("tray-tasks")))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit".to_owned(), mtl!("tray-quit")))
+}
+
+/// Keeps the tray menu, its tooltip and the taskbar button in step with the
+/// task queue, for as long as the app runs.
+pub fn watch(app: AppHandle) {
+    let mut taskbar = app.get_window("main").and_then(|window| Taskbar::new(&window));
+    tokio::spawn(async move {
+        let mut last = None;
+        loop {
+            let queue = app.state::<TaskQueue>();
+            let summary = queue.summary().await;
+            if let Some(taskbar) = &mut taskbar {
+                taskbar.set(Progress::of(&summary));
+            }
+            let status = match queue.current().await {
+                Some((name, progress)) => {
+                    mtl!("tray-rendering", "name" => name, "progress" => format!("{:.0}", progress * 100.)).to_string()
+                }
+                None if summary.paused && summary.pending > 0 => mtl!("tray-paused").to_string(),
+                None if summary.pending > 0 => mtl!("tray-waiting").to_string(),
+                None => mtl!("tray-idle").to_string(),
+            };
+            let state = (status, summary.paused, summary.running > 0);
+            if last.as_ref() != Some(&state) {
+                let (status, paused, running) = &state;
+                let tray = app.tray_handle();
+                let _ = tray.set_tooltip(&format!("Phi Recorder\n{status}"));
+                let _ = tray.get_item("status").set_title(status);
+                let _ = tray
+                    .get_item("pause")
+                    .set_title(if *paused { mtl!("tray-resume") } else { mtl!("tray-pause") });
+                let _ = tray.get_item("cancel").set_enabled(*running);
+                last = Some(state);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+/// Runs the queue actions of the tray menu.
+pub fn on_click(app: &AppHandle, id: &str) {
+    match id {
+        "pause" => {
+            let queue = app.state::<TaskQueue>();
+            queue.set_paused(!queue.is_paused());
+        }
+        "cancel" => {
+            let app = app.clone();
+            tokio::spawn(async move { app.state::<TaskQueue>().cancel_running().await });
+        }
+        "output" => {
+            crate::show_folder().ok();
+        }
+        _ => {}
+    }
+}
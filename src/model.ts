@@ -32,6 +32,7 @@ export type TaskStatus =
       type: 'done';
       duration: number;
       output: string;
+      frame_stats?: FrameStats | null;
     }
   | {
       type: 'canceled';
@@ -39,15 +40,106 @@ export type TaskStatus =
   | {
       type: 'failed';
       error: string;
+      code?: string | null;
+      stage?: string | null;
     };
 
 export interface Task {
   id: number;
+  job: string;
   name: string;
   output: string;
   path: string;
   cover: string;
   status: TaskStatus;
+  warnings: string[];
+  loudness?: Loudness | null;
+}
+
+export interface Loudness {
+  integrated?: number | null;
+  truePeak: number;
+  clipped: number;
+}
+
+export interface FrameStats {
+  frames: number;
+  mean: number;
+  p95: number;
+  p99: number;
+  worst: number;
+  worstFrame: number;
+  simulate: number;
+  readback: number;
+  write: number;
+  spikes?: number;
+}
+
+export type FarmStatus =
+  | { type: 'queued' }
+  | { type: 'rendering'; worker: string }
+  | { type: 'done'; worker: string; output: string }
+  | { type: 'failed'; worker: string; error: string };
+
+export interface FarmJob {
+  id: string;
+  name: string;
+  status: FarmStatus;
+}
+
+export interface QueueSummary {
+  pending: number;
+  running: number;
+  done: number;
+  failed: number;
+  canceled: number;
+  progress: number;
+  fps: number;
+  paused: boolean;
+}
+
+export interface TranscodeParams {
+  input: string;
+  output?: string | null;
+  codec: 'h264' | 'hevc' | 'av1';
+  height?: number | null;
+  targetSizeMb?: number | null;
+  bitrate?: string | null;
+}
+
+export interface TrimParams {
+  manifest: string;
+  video: string;
+  output?: string | null;
+  start?: 'video' | 'firstNote';
+  lead?: number;
+  endingLength?: number | null;
+  crop?: [number, number, number, number] | null;
+  pad?: [number, number] | null;
+  exact?: boolean;
+}
+
+export interface RespackSpec {
+  name: string;
+  author?: string;
+  description?: string;
+  base?: string | null;
+  files?: Record<string, string>;
+  hitFx?: [number, number] | null;
+  holdAtlas?: [number, number] | null;
+  holdAtlasMh?: [number, number] | null;
+  extra?: Record<string, unknown>;
+}
+
+export interface RespackContents {
+  info: unknown;
+  files: string[];
+  imageSizes: Record<string, [number, number]>;
+}
+
+export interface RespackPreview {
+  video: string;
+  thumbnail: string;
 }
 
 export interface RenderConfig {
@@ -57,15 +149,36 @@ export interface RenderConfig {
   disableLoading: boolean;
   hires: boolean;
   chartDebug: boolean;
+  debugOverlay?: {
+    lineIndices?: boolean;
+    noteIds?: boolean;
+    eventValues?: boolean;
+    time?: boolean;
+  };
   chartRatio: number;
   allGood: boolean;
   allBad: boolean;
   fps: number;
   hardwareAccel: boolean;
+  softwareFallback?: boolean;
+  nvencBusy?: 'wait' | 'software' | 'fail';
+  encoderFallback?: string[];
+  segmentLength?: number;
   hevc: boolean;
   mpeg4: boolean;
   bitrateControl: string;
   bitrate: string;
+  draft?: boolean;
+  quality?: 'draft' | 'standard' | 'high' | 'archival' | null;
+  audioBitrate?: string | null;
+  keyframeInterval?: number | null;
+  disableSceneCut?: boolean;
+  bFrames?: number | null;
+  rcLookahead?: number | null;
+  tune?: 'film' | 'animation' | 'grain' | 'stillimage' | null;
+  multipass?: 'disabled' | 'qres' | 'fullres' | null;
+  chroma?: '420' | '444';
+  displayRotation?: number;
 
   aggressive: boolean;
   challengeColor: string;
@@ -81,11 +194,19 @@ export interface RenderConfig {
   playerRks: number;
   sampleCount: number;
   resPackPath: string | null;
+  skinOverrides?: Record<string, string>;
   speed: number;
+  speedResample?: boolean;
   volumeMusic: number;
   volumeSfx: number;
   compressionRatio: number;
+  compressionThreshold?: number;
+  compressionAttack?: number;
+  compressionRelease?: number;
   watermark: string;
+  watermarkStyle?: WatermarkStyle | null;
+  forensicKey?: string | null;
+  forensicStrength?: number | null;
   roman: boolean;
   chinese: boolean;
   combo: string;
@@ -93,7 +214,37 @@ export interface RenderConfig {
   judgeOffset: number;
   forceLimit: boolean;
   limitThreshold: number;
+  limitLookahead?: number | null;
+  limitRelease?: number | null;
+  audioBitDepth?: 'f32' | 's24' | 's16';
+  musicFadeIn?: number;
+  musicFadeOut?: number | null;
+  splashDuration?: number;
+  splashImage?: string | null;
+  creditDuration?: number;
+  lyricsMode?: 'soft' | 'burn' | 'both';
+  lyricsFont?: string | null;
+  lyricsPosition?: [number, number] | null;
+  lyricsSize?: number | null;
+  lyricsColor?: string | null;
+  endingHideAvatar?: boolean;
+  endingHideRks?: boolean;
+  endingShowStats?: boolean;
+  endingText?: string;
+  endingGraph?: boolean;
+  endingCredits?: boolean;
+  endingCreditsExtra?: string[];
+  phiraMode?: boolean;
+  scoreFormula?: 'phigros' | 'phira' | null;
+  mirror?: boolean;
+  convertNotes?: 'click' | 'flick' | null;
+  removeFakeNotes?: boolean;
+  onlyLines?: number[];
+  hiddenLines?: number[];
   simpleFileName: boolean;
+  manifest?: boolean;
+  keepStems?: boolean;
+  noteTimeline?: 'json' | 'csv' | null;
 
   renderLine: boolean;
   renderLineExtra: boolean;
@@ -101,11 +252,108 @@ export interface RenderConfig {
   renderUiPause: boolean;
   renderUiScore: boolean;
   renderUiCombo: boolean;
+  comboMin?: number | null;
+  comboPunch?: number;
+  comboScale?: number | null;
+  shakeIntensity?: number;
+  shakeEvery?: number | null;
+  zoomPulse?: number;
+  zoomPulseEvery?: number | null;
   renderUiBar: boolean;
   renderBg: boolean;
+  backgroundMotion?: 'off' | 'drift' | 'pulse';
+  backgroundMotionAmplitude?: number | null;
+  bga?: boolean;
+  bgaBlur?: number;
+  bgaBrightness?: number | null;
+  bgaOffset?: number;
+  progressBarStyle?: 'bar' | 'line' | 'circle' | 'hidden';
+  npsOverlay?: 'off' | 'meter' | 'graph';
+  beatGrid?: boolean;
+  beatsPerMeasure?: number | null;
+  visualizer?: 'off' | 'spectrum' | 'waveform';
+  visualizerHeight?: number | null;
 
   maxParticles: number;
+  pboCount?: number | null;
+  frameQueue?: number | null;
+  lowPriority?: boolean;
+  cpuAffinity?: number[];
+  cpuLimit?: number | null;
+  encoderThreads?: number | null;
+  keepAwake?: boolean;
   fade: number;
+
+  hitsoundJitter?: number;
+  hitsoundJitterSeed?: number;
+  hitsoundOnset?: boolean;
+  lineTexture?: LineTexture | null;
+}
+
+export interface LineTexture {
+  image?: string | null;
+  length?: number;
+  height?: number | null;
+  tile?: boolean;
+  glow?: number;
+  glowColor?: string | null;
+  glowStrength?: number;
+}
+
+export interface WatermarkStyle {
+  size?: number;
+  color?: string;
+  corner?: 'topleft' | 'topright' | 'bottomleft' | 'bottomright';
+  margin?: number;
+  pill?: boolean;
+  motion?: 'static' | 'drift' | 'hop' | 'blink';
+  interval?: number;
+}
+
+export interface Detection {
+  frames: number;
+  correlation: number;
+  score: number;
+  detected: boolean;
+}
+
+export interface NoteCounts {
+  tap: number;
+  drag: number;
+  hold: number;
+  flick: number;
+  fake: number;
+  total: number;
+}
+
+export interface ChartProbe {
+  info: ChartInfo;
+  musicLength: number;
+  chartLength: number;
+  offset: number;
+  lineCount: number;
+  notes: NoteCounts;
+}
+
+export interface EncoderInfo {
+  name: string;
+  codec: 'h264' | 'hevc' | 'av1' | 'mpeg4';
+  backend: 'software' | 'nvenc' | 'qsv' | 'amf' | 'videotoolbox' | 'vaapi';
+  available: boolean;
+}
+
+export interface FfmpegRelease {
+  version: string;
+  url: string;
+  sha256: string;
+}
+
+export interface FfmpegStatus {
+  path: string | null;
+  version: string | null;
+  pinned: string | null;
+  installed: string[];
+  latest: FfmpegRelease | null;
 }
 
 export interface RPEChart {